@@ -0,0 +1,147 @@
+//! Writes the blocks of era files as one stream of RLP encoded blocks, each
+//! `[header, transactions, uncles]`, the format `geth import`, the `evm`
+//! tool and the consensus tests read chains in.
+
+use anyhow::{anyhow, bail, Context};
+use era_file_sink::compression::{snap_decode, MAX_ENTRY_SIZE};
+use era_file_sink::e2store::builder::MAX_HEADER_SIZE;
+use era_file_sink::e2store::{read_entry_header, E2StoreType, UnknownEntryPolicy, HEADER_SIZE};
+use era_file_sink::encryption::{EncryptionKey, EraReader};
+use era_file_sink::rlp::{list_items, raw_list};
+use reth_primitives::Header;
+use reth_rlp::Decodable;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes blocks `first` to `last`, both included, from `files` to `out` in
+/// block order. Every block of the range must be in one of the files.
+pub fn write_blocks(
+    files: &[PathBuf],
+    first: u64,
+    last: u64,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+    out: &mut impl Write,
+) -> Result<u64, anyhow::Error> {
+    if first > last {
+        bail!("first block {} is after last block {}", first, last);
+    }
+
+    // Files are read in block order, whatever order they were given in.
+    let mut starts = Vec::new();
+    for file in files {
+        let start = first_block_of(file, unknown_entries, key)
+            .context(format!("read '{}'", file.display()))?;
+        starts.push((start, file));
+    }
+    starts.sort();
+
+    let mut next = first;
+    for (_, file) in starts {
+        if next > last {
+            break;
+        }
+        next = write_file(file, next, last, unknown_entries, key, out)
+            .context(format!("read '{}'", file.display()))?;
+    }
+    if next <= last {
+        bail!("block {} isn't in any of the files", next);
+    }
+    out.flush()?;
+
+    Ok(last - first + 1)
+}
+
+/// Number of the first block in the era file at `path`.
+fn first_block_of(
+    path: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+) -> Result<Option<u64>, anyhow::Error> {
+    let mut reader = EraReader::open(path, key)?;
+    let mut offset = 0;
+    loop {
+        let (type_, length) = match read_entry_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        unknown_entries.check_era1(type_, offset)?;
+        offset += HEADER_SIZE + length as u64;
+        let mut data = vec![0; length as usize];
+        reader.read_exact(&mut data)?;
+        if type_ == E2StoreType::CompressedHeader as u16 {
+            return Ok(Some(decode_number(&snap_decode(&data, MAX_HEADER_SIZE)?)?));
+        }
+    }
+}
+
+/// Writes the blocks of the era file at `path` from `next` up to `last`, and
+/// returns the number of the block after the last one written. Blocks before
+/// `next` are skipped, a block after it means the files leave a gap.
+fn write_file(
+    path: &Path,
+    mut next: u64,
+    last: u64,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+    out: &mut impl Write,
+) -> Result<u64, anyhow::Error> {
+    let mut reader = EraReader::open(path, key)?;
+    let mut header = None;
+    let mut offset = 0;
+    while next <= last {
+        let (type_, length) = match read_entry_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        unknown_entries.check_era1(type_, offset)?;
+        offset += HEADER_SIZE + length as u64;
+        let mut data = vec![0; length as usize];
+        reader.read_exact(&mut data)?;
+
+        if type_ == E2StoreType::CompressedHeader as u16 {
+            let rlp = snap_decode(&data, MAX_HEADER_SIZE)?;
+            let number = decode_number(&rlp)?;
+            if number > next {
+                bail!(
+                    "block {} is missing, the file goes on with {}",
+                    next,
+                    number
+                );
+            }
+            header = (number == next).then_some(rlp);
+        } else if type_ == E2StoreType::CompressedBody as u16 {
+            let Some(header) = header.take() else {
+                continue;
+            };
+            let body = snap_decode(&data, MAX_ENTRY_SIZE)?;
+            out.write_all(&block_rlp(&header, &body).context(format!("body of block {}", next))?)?;
+            next += 1;
+        } else if type_ == E2StoreType::Accumulator as u16 {
+            break;
+        }
+    }
+
+    Ok(next)
+}
+
+fn decode_number(header: &[u8]) -> Result<u64, anyhow::Error> {
+    Ok(Header::decode(&mut &header[..])
+        .map_err(|err| anyhow!("invalid header RLP: {:?}", err))?
+        .number)
+}
+
+/// The block made of `header` and the transactions and uncles of `body`.
+fn block_rlp(header: &[u8], body: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let body = list_items(body)?;
+    let [transactions, uncles, ..] = body[..] else {
+        bail!(
+            "body has {} items, expected transactions and uncles",
+            body.len()
+        );
+    };
+
+    Ok(raw_list(&[header, transactions, uncles]))
+}
@@ -0,0 +1,80 @@
+use crate::epoch::{first_block, get_epoch, EPOCH_SIZE};
+use anyhow::anyhow;
+use std::str::FromStr;
+
+/// The parameters that differ between the chains era files can be built for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainConfig {
+    /// Network name used in era file names.
+    pub network: &'static str,
+    /// EIP-155 chain id signed into transactions.
+    pub chain_id: u64,
+    /// First block whose receipts carry a status instead of a state root.
+    pub byzantium_block: u64,
+    /// Default Substreams endpoint, if a public one serves this chain.
+    pub endpoint_url: Option<&'static str>,
+    /// Whether the accumulator values embedded in the binary belong to this
+    /// chain.
+    pub embedded_accumulators: bool,
+    /// Last proof-of-work block, if the chain moved to proof of stake. The
+    /// era holding it is the last one and ends early.
+    pub last_pow_block: Option<u64>,
+}
+
+pub const MAINNET: ChainConfig = ChainConfig {
+    network: "mainnet",
+    chain_id: 1,
+    byzantium_block: 4_370_000,
+    endpoint_url: Some("https://mainnet.eth.streamingfast.io:443"),
+    embedded_accumulators: true,
+    last_pow_block: Some(15_537_393),
+};
+
+/// Ethereum Classic, where Byzantium's receipt changes arrived with Atlantis
+/// and there is no merge.
+pub const CLASSIC: ChainConfig = ChainConfig {
+    network: "classic",
+    chain_id: 61,
+    byzantium_block: 8_772_000,
+    endpoint_url: None,
+    embedded_accumulators: false,
+    last_pow_block: None,
+};
+
+impl ChainConfig {
+    /// Last era of the chain, if it moved to proof of stake.
+    pub fn last_epoch(&self) -> Option<u64> {
+        self.last_pow_block.map(get_epoch)
+    }
+
+    /// Number of blocks in the era of `epoch`: a full epoch, fewer for the
+    /// era holding the last proof-of-work block, and none past it.
+    pub fn era_size(&self, epoch: u64) -> u64 {
+        match self.last_pow_block {
+            Some(last) if epoch > get_epoch(last) => 0,
+            Some(last) if epoch == get_epoch(last) => last - first_block(epoch) + 1,
+            _ => EPOCH_SIZE,
+        }
+    }
+
+    /// Whether `block_number` is the last block of its era.
+    pub fn is_era_end(&self, block_number: u64) -> bool {
+        let epoch = get_epoch(block_number);
+        block_number + 1 == first_block(epoch) + self.era_size(epoch)
+    }
+}
+
+impl FromStr for ChainConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(MAINNET),
+            "classic" => Ok(CLASSIC),
+            _ => Err(anyhow!(
+                "unknown network '{}', expected 'mainnet' or 'classic'",
+                s
+            )),
+        }
+    }
+}
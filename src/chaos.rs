@@ -0,0 +1,138 @@
+//! Fault injection for resilience testing, compiled in with the `chaos`
+//! feature.
+//!
+//! Every fault has a probability read from the environment when the first
+//! fault point is reached, and all faults are disabled by default:
+//!
+//! - `CHAOS_DISCONNECT_RATE`: drop the stream connection after a message.
+//! - `CHAOS_DELAY_RATE`: delay a message by up to `CHAOS_MAX_DELAY_MS`.
+//! - `CHAOS_CORRUPT_RATE`: flip one bit of a block payload.
+//! - `CHAOS_WRITE_FAILURE_RATE`: fail an era file write.
+//!
+//! `CHAOS_SEED` makes a run reproducible.
+
+use crate::pb::sf::substreams::rpc::v2::BlockScopedData;
+use std::env;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+struct Chaos {
+    disconnect_rate: f64,
+    delay_rate: f64,
+    max_delay: Duration,
+    corrupt_rate: f64,
+    write_failure_rate: f64,
+    state: u64,
+}
+
+impl Chaos {
+    fn from_env() -> Self {
+        let rate = |name: &str| {
+            env::var(name)
+                .ok()
+                .and_then(|rate| rate.parse::<f64>().ok())
+                .unwrap_or(0.0)
+        };
+        let seed = env::var("CHAOS_SEED")
+            .ok()
+            .and_then(|seed| seed.parse::<u64>().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(1, |elapsed| elapsed.as_nanos() as u64)
+            });
+        let max_delay_ms = env::var("CHAOS_MAX_DELAY_MS")
+            .ok()
+            .and_then(|ms| ms.parse::<u64>().ok())
+            .unwrap_or(5_000);
+
+        Self {
+            disconnect_rate: rate("CHAOS_DISCONNECT_RATE"),
+            delay_rate: rate("CHAOS_DELAY_RATE"),
+            max_delay: Duration::from_millis(max_delay_ms),
+            corrupt_rate: rate("CHAOS_CORRUPT_RATE"),
+            write_failure_rate: rate("CHAOS_WRITE_FAILURE_RATE"),
+            // xorshift gets stuck on zero.
+            state: seed.max(1),
+        }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn roll(&mut self, rate: f64) -> bool {
+        rate > 0.0 && (self.next() as f64 / u64::MAX as f64) < rate
+    }
+}
+
+fn with_chaos<T>(f: impl FnOnce(&mut Chaos) -> T) -> T {
+    static CHAOS: OnceLock<Mutex<Chaos>> = OnceLock::new();
+    let mut chaos = CHAOS
+        .get_or_init(|| Mutex::new(Chaos::from_env()))
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(&mut chaos)
+}
+
+pub fn inject_disconnect() -> bool {
+    let disconnect = with_chaos(|chaos| chaos.roll(chaos.disconnect_rate));
+    if disconnect {
+        println!("Chaos: dropping stream connection");
+    }
+
+    disconnect
+}
+
+pub async fn inject_delay() {
+    let delay = with_chaos(|chaos| {
+        if chaos.roll(chaos.delay_rate) {
+            let max_delay_ms = chaos.max_delay.as_millis() as u64;
+            Some(Duration::from_millis(chaos.next() % (max_delay_ms + 1)))
+        } else {
+            None
+        }
+    });
+
+    if let Some(delay) = delay {
+        println!("Chaos: delaying message by {:?}", delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+pub fn inject_corruption(mut data: BlockScopedData) -> BlockScopedData {
+    let Some(map_output) = data
+        .output
+        .as_mut()
+        .and_then(|output| output.map_output.as_mut())
+        .filter(|map_output| !map_output.value.is_empty())
+    else {
+        return data;
+    };
+
+    let bit = with_chaos(|chaos| {
+        if chaos.roll(chaos.corrupt_rate) {
+            Some(chaos.next() as usize % (map_output.value.len() * 8))
+        } else {
+            None
+        }
+    });
+
+    if let Some(bit) = bit {
+        println!("Chaos: flipping bit {} of a block payload", bit);
+        map_output.value[bit / 8] ^= 1 << (bit % 8);
+    }
+
+    data
+}
+
+pub fn inject_write_failure() -> Result<(), std::io::Error> {
+    if with_chaos(|chaos| chaos.roll(chaos.write_failure_rate)) {
+        return Err(std::io::Error::other("chaos: injected write failure"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,238 @@
+//! Content identifiers of finished files, as IPFS computes them when adding
+//! a file with raw leaves and CID version 1, so pinning services and other
+//! content addressed tools agree on them without hashing the files again.
+//!
+//! A file is split into fixed size chunks stored as raw blocks. Files of more
+//! than one chunk get a balanced tree of UnixFS dag-pb nodes above them, each
+//! linking up to [`MAX_LINKS`] children.
+
+use anyhow::{anyhow, bail, Context};
+use reth_primitives::keccak256;
+use sha2::{Digest, Sha256, Sha512};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Chunk size used when `ERA_FILE_CID_CHUNK_SIZE` isn't set, the IPFS
+/// default.
+const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+
+/// IPFS peers refuse blocks above this size.
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Children per tree node, the IPFS default.
+pub const MAX_LINKS: usize = 174;
+
+const RAW_CODEC: u64 = 0x55;
+const DAG_PB_CODEC: u64 = 0x70;
+const UNIXFS_FILE: u64 = 2;
+
+/// Hash function of the multihashes in a CID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CidHash {
+    Sha2_256,
+    Sha2_512,
+    Keccak256,
+}
+
+impl CidHash {
+    /// Multicodec code of the hash function.
+    fn code(&self) -> u64 {
+        match self {
+            CidHash::Sha2_256 => 0x12,
+            CidHash::Sha2_512 => 0x13,
+            CidHash::Keccak256 => 0x1b,
+        }
+    }
+
+    fn multihash(&self, data: &[u8]) -> Vec<u8> {
+        let digest = match self {
+            CidHash::Sha2_256 => Sha256::digest(data).to_vec(),
+            CidHash::Sha2_512 => Sha512::digest(data).to_vec(),
+            CidHash::Keccak256 => keccak256(data).0.to_vec(),
+        };
+        let mut multihash = Vec::with_capacity(digest.len() + 2);
+        put_varint(&mut multihash, self.code());
+        put_varint(&mut multihash, digest.len() as u64);
+        multihash.extend_from_slice(&digest);
+        multihash
+    }
+}
+
+impl FromStr for CidHash {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha2-256" => Ok(CidHash::Sha2_256),
+            "sha2-512" => Ok(CidHash::Sha2_512),
+            "keccak-256" => Ok(CidHash::Keccak256),
+            _ => Err(anyhow!(
+                "unknown CID hash function '{}', expected 'sha2-256', 'sha2-512' or 'keccak-256'",
+                s
+            )),
+        }
+    }
+}
+
+/// How CIDs are computed.
+#[derive(Clone, Copy, Debug)]
+pub struct CidSettings {
+    pub hash: CidHash,
+    pub chunk_size: usize,
+}
+
+impl CidSettings {
+    /// The settings in `ERA_FILE_CID_HASH` and `ERA_FILE_CID_CHUNK_SIZE`.
+    /// `None` if no hash function is set, in which case no CIDs are computed.
+    pub fn from_env() -> Result<Option<Self>, anyhow::Error> {
+        let Ok(hash) = env::var("ERA_FILE_CID_HASH") else {
+            return Ok(None);
+        };
+        let chunk_size = match env::var("ERA_FILE_CID_CHUNK_SIZE") {
+            Ok(size) => size
+                .parse::<usize>()
+                .context("ERA_FILE_CID_CHUNK_SIZE is not a valid integer")?,
+            Err(_) => DEFAULT_CHUNK_SIZE,
+        };
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            bail!(
+                "ERA_FILE_CID_CHUNK_SIZE must be between 1 and {} bytes",
+                MAX_CHUNK_SIZE
+            );
+        }
+
+        Ok(Some(Self {
+            hash: hash.parse()?,
+            chunk_size,
+        }))
+    }
+}
+
+/// A block of the file's tree.
+struct Node {
+    cid: Vec<u8>,
+    /// Bytes of the file below this node.
+    file_size: u64,
+    /// Bytes of all blocks below and including this node.
+    tree_size: u64,
+}
+
+/// The CID of the file at `path`, in the base32 text form.
+pub fn file_cid(path: &Path, settings: CidSettings) -> Result<String, anyhow::Error> {
+    let mut file = File::open(path).context(format!("open '{}'", path.display()))?;
+    let mut nodes = Vec::new();
+    let mut chunk = vec![0; settings.chunk_size];
+    loop {
+        let length = read_chunk(&mut file, &mut chunk)?;
+        if length == 0 && !nodes.is_empty() {
+            break;
+        }
+        nodes.push(Node {
+            cid: cid_bytes(RAW_CODEC, &settings.hash.multihash(&chunk[..length])),
+            file_size: length as u64,
+            tree_size: length as u64,
+        });
+        if length < chunk.len() {
+            break;
+        }
+    }
+
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(MAX_LINKS)
+            .map(|children| parent(children, settings.hash))
+            .collect();
+    }
+
+    Ok(format!("b{}", base32(&nodes[0].cid)))
+}
+
+/// Fills `chunk` as far as the file allows, returning the bytes read.
+fn read_chunk(file: &mut File, chunk: &mut [u8]) -> Result<usize, std::io::Error> {
+    let mut length = 0;
+    while length < chunk.len() {
+        match file.read(&mut chunk[length..])? {
+            0 => break,
+            read => length += read,
+        }
+    }
+    Ok(length)
+}
+
+/// The dag-pb node linking `children`, in the canonical encoding: links
+/// first, then the UnixFS data.
+fn parent(children: &[Node], hash: CidHash) -> Node {
+    let file_size = children.iter().map(|child| child.file_size).sum();
+    let mut data = Vec::new();
+    put_uint(&mut data, 1, UNIXFS_FILE);
+    put_uint(&mut data, 3, file_size);
+    for child in children {
+        put_uint(&mut data, 4, child.file_size);
+    }
+
+    let mut node = Vec::new();
+    for child in children {
+        let mut link = Vec::new();
+        put_bytes(&mut link, 1, &child.cid);
+        put_bytes(&mut link, 2, b"");
+        put_uint(&mut link, 3, child.tree_size);
+        put_bytes(&mut node, 2, &link);
+    }
+    put_bytes(&mut node, 1, &data);
+
+    Node {
+        cid: cid_bytes(DAG_PB_CODEC, &hash.multihash(&node)),
+        file_size,
+        tree_size: node.len() as u64 + children.iter().map(|child| child.tree_size).sum::<u64>(),
+    }
+}
+
+fn cid_bytes(codec: u64, multihash: &[u8]) -> Vec<u8> {
+    let mut cid = Vec::with_capacity(multihash.len() + 2);
+    put_varint(&mut cid, 1);
+    put_varint(&mut cid, codec);
+    cid.extend_from_slice(multihash);
+    cid
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_uint(out: &mut Vec<u8>, field: u64, value: u64) {
+    put_varint(out, field << 3);
+    put_varint(out, value);
+}
+
+fn put_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(out, (field << 3) | 2);
+    put_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Lowercase RFC 4648 base32 without padding, the multibase `b` encoding.
+fn base32(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut encoded = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u64;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
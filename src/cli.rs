@@ -0,0 +1,279 @@
+//! Command line arguments. Settings that apply to every command, such as the
+//! network or the accumulator source, are read from the environment instead.
+
+use crate::math::parse_number;
+use crate::substreams::parse_url;
+use crate::{DEFAULT_MODULE_NAME, DEFAULT_PACKAGE_FILE};
+use anyhow::{bail, format_err, Context};
+use clap::{Args, Parser, Subcommand};
+use era_file_sink::chain::ChainConfig;
+use era_file_sink::epoch::{first_block, EPOCH_SIZE};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Parser)]
+#[command(version, about = "Writes era1 files from a Substreams endpoint")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Stream a range of eras and write them as era1 files.
+    ///
+    /// SUBSTREAMS_API_KEY must contain a valid Substreams API key, unless a
+    /// token is given with --token or SUBSTREAMS_JWT.
+    Sink {
+        /// Directory the era files and the cursor are written to.
+        output_dir: PathBuf,
+        /// Eras to write, `<start_era>:<stop_era>` with both included.
+        #[arg(required_unless_present = "jobs_file")]
+        range: Option<EraRange>,
+        /// Write the ranges listed in this file instead, one per line with an
+        /// optional priority, replacing their existing files.
+        #[arg(long, conflicts_with = "range")]
+        jobs_file: Option<PathBuf>,
+        /// Stream this many parts of the range at the same time, each over
+        /// its own connection and with its own cursor.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        workers: u64,
+        /// Skip the confirmation asked for large ranges.
+        #[arg(long)]
+        yes: bool,
+        /// Write every era of the range again, even those that already have
+        /// a complete file in `output_dir`.
+        #[arg(long)]
+        overwrite: bool,
+        /// Move finished era files and the cursor to this directory or
+        /// `s3://`, `gs://` or `az://` URL, keeping only the era in progress
+        /// in `output_dir`.
+        #[arg(long)]
+        output: Option<String>,
+        #[command(flatten)]
+        stream: StreamArgs,
+    },
+    /// List the eras of a range without a complete file in `output_dir`,
+    /// missing or corrupt, and stream only those.
+    FillGaps {
+        output_dir: PathBuf,
+        /// Eras to check, `<start_era>:<stop_era>` with both included.
+        range: EraRange,
+        /// Only list the eras that need to be written.
+        #[arg(long)]
+        list: bool,
+        /// Stream this many gaps at the same time.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        workers: u64,
+        /// Skip the confirmation asked for large gaps.
+        #[arg(long)]
+        yes: bool,
+        #[command(flatten)]
+        stream: StreamArgs,
+    },
+    /// Complete the eras left pending for lack of an accumulator value.
+    FinalizePending {
+        output_dir: PathBuf,
+        /// Move the completed era files to this directory or storage URL.
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Read era1 files back and check that they follow the era1 spec.
+    /// Post-merge `.era` files are checked against the era spec.
+    Verify {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Also compare the era of `--epoch` with this JSON-RPC archive node.
+        #[arg(long, requires = "epoch")]
+        against_rpc: Option<String>,
+        /// Epoch to compare with the node, whose file must be among `files`.
+        #[arg(long, requires = "against_rpc")]
+        epoch: Option<u64>,
+        /// Blocks of the epoch to compare, spread evenly over it.
+        #[arg(long, default_value_t = 64)]
+        samples: u64,
+        /// Compare every block of the epoch instead of a sample.
+        #[arg(long, conflicts_with = "samples")]
+        exhaustive: bool,
+    },
+    /// Compare two era1 files of the same epoch entry by entry, such as one
+    /// written by geth's `era` tool and one written by the sink.
+    Compare {
+        /// The reference file.
+        expected: PathBuf,
+        actual: PathBuf,
+        /// Most differing entries to list.
+        #[arg(long, default_value_t = 20)]
+        max_differences: usize,
+    },
+    /// Write blocks of era files as a stream of RLP encoded blocks, as read
+    /// by `geth import`, the `evm` tool and the consensus tests.
+    Cat {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// First block to write.
+        #[arg(long, value_parser = parse_number)]
+        from: u64,
+        /// Last block to write.
+        #[arg(long, value_parser = parse_number)]
+        to: u64,
+        /// Write to this file instead of standard output.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Check everything a run depends on and print a fix for every problem.
+    Doctor {
+        output_dir: PathBuf,
+        range: EraRange,
+        #[command(flatten)]
+        stream: StreamArgs,
+    },
+    /// Read, check or regenerate header accumulator values.
+    #[command(subcommand)]
+    Accumulators(AccumulatorsCommand),
+    /// Convert between block numbers, epochs and era file names.
+    #[command(subcommand)]
+    Math(MathCommand),
+}
+
+#[derive(Subcommand)]
+pub enum MathCommand {
+    /// Show the era file holding a block, e.g. `12,965,000`.
+    Block {
+        #[arg(value_parser = parse_number)]
+        number: u64,
+    },
+    /// Show the blocks and the file of an epoch.
+    Epoch {
+        #[arg(value_parser = parse_number)]
+        epoch: u64,
+    },
+    /// Show the blocks held by an era file.
+    File { name: String },
+}
+
+#[derive(Subcommand)]
+pub enum AccumulatorsCommand {
+    /// Print the accumulator root stored in every era1 file of a directory.
+    Extract {
+        /// Directory of trusted era1 files.
+        era_dir: PathBuf,
+    },
+    /// Recompute the accumulator roots of a directory of era1 files and
+    /// compare them with HEADER_ACCUMULATOR_SOURCE.
+    Verify {
+        /// Directory of era1 files to recompute the roots of.
+        era_dir: PathBuf,
+    },
+    /// Stream a range of eras, decoding only headers, and append their
+    /// accumulator roots to a file.
+    Generate {
+        /// Eras to compute the roots of, `<start_era>:<stop_era>`.
+        range: EraRange,
+        /// File the roots are appended to, one per line.
+        values_file: PathBuf,
+        #[command(flatten)]
+        stream: StreamArgs,
+    },
+}
+
+/// Where blocks are streamed from.
+#[derive(Args)]
+pub struct StreamArgs {
+    /// Substreams endpoint, the default one of the network if unset.
+    #[arg(long, env = "SUBSTREAMS_ENDPOINT")]
+    pub endpoint: Option<String>,
+    /// Substreams package, as a URL or a local path.
+    #[arg(long, env = "SUBSTREAMS_PACKAGE", default_value = DEFAULT_PACKAGE_FILE)]
+    pub spkg: String,
+    /// Module of the package to stream, which must output
+    /// `acme.verifiable_block.v1.VerifiableBlock` messages.
+    #[arg(long, env = "SUBSTREAMS_MODULE", default_value = DEFAULT_MODULE_NAME)]
+    pub module: String,
+    /// Token sent to the endpoint as is, instead of one issued from
+    /// SUBSTREAMS_API_KEY.
+    #[arg(long, env = "SUBSTREAMS_JWT", hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+impl StreamArgs {
+    /// The endpoint that was set, or the default one of `chain`. Fails if
+    /// the endpoint that was set isn't a valid URL.
+    pub fn endpoint_url(&self, chain: ChainConfig) -> Result<String, anyhow::Error> {
+        match (&self.endpoint, chain.endpoint_url) {
+            (Some(url), _) => {
+                parse_url(url).context("invalid --endpoint or SUBSTREAMS_ENDPOINT")?;
+                Ok(url.clone())
+            }
+            (None, Some(url)) => Ok(url.to_string()),
+            (None, None) => Err(format_err!(
+                "no default Substreams endpoint for {}, set --endpoint or SUBSTREAMS_ENDPOINT",
+                chain.network
+            )),
+        }
+    }
+}
+
+/// An inclusive range of eras, written `<start_era>:<stop_era>`, or
+/// `<stop_era>` to start from era 0.
+#[derive(Clone, Copy, Debug)]
+pub struct EraRange {
+    pub start: u64,
+    pub stop: u64,
+}
+
+impl EraRange {
+    /// The first block of the range and the block after its last one, which
+    /// for the last era of a chain that moved to proof of stake is the first
+    /// proof-of-stake block. Fails if the range goes past that era.
+    pub fn blocks(&self, chain: ChainConfig) -> Result<(i64, u64), anyhow::Error> {
+        if let (Some(last_epoch), Some(last_pow_block)) = (chain.last_epoch(), chain.last_pow_block)
+        {
+            if self.stop > last_epoch {
+                bail!(
+                    "era {} is past the last era of {}, {}, which ends at the last proof-of-work block {}",
+                    self.stop,
+                    chain.network,
+                    last_epoch,
+                    last_pow_block
+                );
+            }
+        }
+
+        Ok((
+            first_block(self.start) as i64,
+            first_block(self.stop) + chain.era_size(self.stop),
+        ))
+    }
+
+    pub fn eras(&self) -> u64 {
+        self.stop - self.start + 1
+    }
+}
+
+impl FromStr for EraRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, stop) = match s.split_once(':') {
+            Some((start, stop)) => (
+                start
+                    .parse::<u64>()
+                    .context(format!("start era '{}' is not a valid era number", start))?,
+                stop,
+            ),
+            None => (0, s),
+        };
+        let stop = stop
+            .parse::<u64>()
+            .context(format!("stop era '{}' is not a valid era number", stop))?;
+        if stop >= u64::MAX / EPOCH_SIZE {
+            bail!("stop era {} is too large", stop);
+        }
+        if start > stop {
+            bail!("start era {} is after stop era {}", start, stop);
+        }
+
+        Ok(EraRange { start, stop })
+    }
+}
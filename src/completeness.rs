@@ -0,0 +1,101 @@
+//! The check closing a run: every era of the requested range must have a
+//! finalized file, at the destination when there is one, so a run that
+//! stopped short or skipped blocks can't exit as if it had succeeded.
+
+use crate::cli::EraRange;
+use crate::gaps::{EraFile, EraFiles};
+use crate::naming::{discover_epoch, parse_pending_epoch};
+use crate::sink::Sink;
+use anyhow::{bail, Context};
+use era_file_sink::chain::ChainConfig;
+use era_file_sink::encryption::ENCRYPTED_SUFFIX;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Checks that every era of `range` has a finalized file with a complete
+/// block index in `output_dir`, or at `remote` if the files are moved there,
+/// and fails with the list of eras that don't. Pending eras only pass if
+/// `allow_pending`.
+pub async fn check_range(
+    output_dir: &Path,
+    chain: ChainConfig,
+    range: EraRange,
+    remote: Option<&Sink>,
+    allow_pending: bool,
+) -> Result<(), anyhow::Error> {
+    let network = chain.network;
+    let local = EraFiles::scan(output_dir, chain)?;
+    let mut pending = HashSet::new();
+    for entry in std::fs::read_dir(output_dir).context("list output directory")? {
+        let path = entry?.path();
+        if let Some(epoch) = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| parse_pending_epoch(network, file_name))
+        {
+            pending.insert(epoch);
+        }
+    }
+    let uploaded = match remote {
+        Some(remote) => Some(
+            remote
+                .file_names()
+                .await?
+                .iter()
+                .filter_map(|file_name| {
+                    discover_epoch(
+                        network,
+                        file_name
+                            .strip_suffix(ENCRYPTED_SUFFIX)
+                            .unwrap_or(file_name),
+                    )
+                })
+                .collect::<HashSet<u64>>(),
+        ),
+        None => None,
+    };
+
+    let mut incomplete = Vec::new();
+    let mut left_pending = 0;
+    for epoch in range.start..=range.stop {
+        let problem = if pending.contains(&epoch) {
+            left_pending += 1;
+            (!allow_pending).then_some("pending, it has no header accumulator value")
+        } else {
+            match (&uploaded, local.get(epoch)) {
+                (Some(uploaded), _) if uploaded.contains(&epoch) => None,
+                (Some(_), EraFile::Complete) => Some("written but not uploaded"),
+                (None, EraFile::Complete) => None,
+                (_, EraFile::Corrupt(_)) => Some("its file has a missing or short block index"),
+                (_, EraFile::Missing) => Some("missing"),
+            }
+        };
+        if let Some(problem) = problem {
+            incomplete.push((epoch, problem));
+        }
+    }
+
+    if incomplete.is_empty() {
+        println!(
+            "All {} eras from {} to {} are finalized{}",
+            range.stop - range.start + 1,
+            range.start,
+            range.stop,
+            match left_pending {
+                0 => String::new(),
+                pending => format!(", except {} left pending", pending),
+            }
+        );
+        return Ok(());
+    }
+    for (epoch, problem) in &incomplete {
+        println!("Era {} is incomplete: {}", epoch, problem);
+    }
+    bail!(
+        "{} of the {} eras from {} to {} are incomplete",
+        incomplete.len(),
+        range.stop - range.start + 1,
+        range.start,
+        range.stop
+    )
+}
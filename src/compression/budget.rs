@@ -0,0 +1,147 @@
+//! A memory budget shared by threads decompressing entries in parallel.
+
+use super::{decoded_len, snap_decode};
+use anyhow::{bail, Context};
+use std::env;
+use std::fmt::Display;
+use std::ops::Deref;
+use std::sync::{Condvar, Mutex};
+
+/// Budget used when `DECOMPRESSION_BUDGET_MIB` isn't set.
+const DEFAULT_BUDGET_MIB: u64 = 1024;
+
+/// Caps the bytes held by decompressed entries across threads. Each entry
+/// reserves its decompressed size before it is inflated and waits while the
+/// budget is used up, so a burst of large entries slows processing down
+/// instead of running out of memory.
+pub struct DecompressionBudget {
+    total: u64,
+    usage: Mutex<Usage>,
+    released: Condvar,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    pub in_use: u64,
+    pub peak: u64,
+    pub reservations: u64,
+    /// Reservations that had to wait for others to be released.
+    pub waits: u64,
+}
+
+impl DecompressionBudget {
+    pub fn new(total: u64) -> Self {
+        Self {
+            total,
+            usage: Mutex::new(Usage::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// The budget in `DECOMPRESSION_BUDGET_MIB`, 1 GiB by default.
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let mib = match env::var("DECOMPRESSION_BUDGET_MIB") {
+            Ok(mib) => mib
+                .parse::<u64>()
+                .context("DECOMPRESSION_BUDGET_MIB is not a valid integer")?,
+            Err(_) => DEFAULT_BUDGET_MIB,
+        };
+        if mib == 0 {
+            bail!("DECOMPRESSION_BUDGET_MIB must be above 0");
+        }
+
+        Ok(Self::new(mib * 1024 * 1024))
+    }
+
+    /// Like [`snap_decode`], after waiting for the decompressed size to fit
+    /// in the budget. The bytes are given back when the result is dropped.
+    pub fn snap_decode(&self, encoded_data: &[u8], max_size: usize) -> anyhow::Result<Decoded<'_>> {
+        let size = decoded_len(encoded_data)?;
+        if size > max_size {
+            bail!("decompressed entry exceeds the limit of {} bytes", max_size);
+        }
+        let permit = self.reserve(size as u64);
+
+        Ok(Decoded {
+            data: snap_decode(encoded_data, max_size)?,
+            _permit: permit,
+        })
+    }
+
+    /// Blocks until `bytes` fit in the budget. Reservations larger than the
+    /// whole budget wait until nothing else is held.
+    pub fn reserve(&self, bytes: u64) -> Permit<'_> {
+        let bytes = bytes.min(self.total);
+        let mut usage = self.lock_usage();
+        usage.reservations += 1;
+        if usage.in_use + bytes > self.total {
+            usage.waits += 1;
+            while usage.in_use + bytes > self.total {
+                usage = self
+                    .released
+                    .wait(usage)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+            }
+        }
+        usage.in_use += bytes;
+        usage.peak = usage.peak.max(usage.in_use);
+
+        Permit {
+            budget: self,
+            bytes,
+        }
+    }
+
+    pub fn usage(&self) -> Usage {
+        *self.lock_usage()
+    }
+
+    /// The usage, even if a thread panicked while holding the lock, so that
+    /// releasing a permit never panics while unwinding.
+    fn lock_usage(&self) -> std::sync::MutexGuard<'_, Usage> {
+        self.usage
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Display for DecompressionBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let usage = self.usage();
+        write!(
+            f,
+            "peak {} of {} MiB, {} of {} entries waited for memory",
+            usage.peak / (1024 * 1024),
+            self.total / (1024 * 1024),
+            usage.waits,
+            usage.reservations
+        )
+    }
+}
+
+/// Bytes reserved in a [`DecompressionBudget`], released on drop.
+pub struct Permit<'a> {
+    budget: &'a DecompressionBudget,
+    bytes: u64,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.budget.lock_usage().in_use -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// A decompressed entry, holding its share of the budget.
+pub struct Decoded<'a> {
+    data: Vec<u8>,
+    _permit: Permit<'a>,
+}
+
+impl Deref for Decoded<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
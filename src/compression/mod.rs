@@ -0,0 +1,79 @@
+use anyhow::bail;
+use bytes::BufMut;
+use std::io::{Read, Write};
+
+pub mod budget;
+
+/// e2store entries store their payload length as a `u32`, so no compressed
+/// entry may exceed this size.
+pub const MAX_ENTRY_SIZE: usize = u32::MAX as usize;
+
+pub fn snap_encoder<W: Write>(writer: W) -> snap::write::FrameEncoder<W> {
+    snap::write::FrameEncoder::new(writer)
+}
+
+pub fn snap_decoder<R: Read>(reader: R) -> snap::read::FrameDecoder<R> {
+    snap::read::FrameDecoder::new(reader)
+}
+
+pub fn snap_encode(decoded_data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let encoded_data = Vec::new();
+    let writer = encoded_data.writer();
+    let mut encoder = snap_encoder(writer);
+
+    encoder.write_all(decoded_data)?;
+
+    let encoded_data = encoder.into_inner()?.into_inner();
+    if encoded_data.len() > MAX_ENTRY_SIZE {
+        bail!(
+            "compressed entry is {} bytes, above the e2store limit of {} bytes",
+            encoded_data.len(),
+            MAX_ENTRY_SIZE
+        );
+    }
+
+    Ok(encoded_data)
+}
+
+/// Decompresses a snappy framed payload, refusing to inflate it past
+/// `max_size` bytes so a corrupt or hostile entry can't exhaust memory.
+pub fn snap_decode(encoded_data: &[u8], max_size: usize) -> anyhow::Result<Vec<u8>> {
+    let mut decoded_data = Vec::new();
+    snap_decoder(encoded_data)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decoded_data)?;
+
+    if decoded_data.len() > max_size {
+        bail!("decompressed entry exceeds the limit of {} bytes", max_size);
+    }
+
+    Ok(decoded_data)
+}
+
+/// Size a snappy framed payload decompresses to, read from its chunk headers
+/// without decompressing it.
+pub fn decoded_len(encoded_data: &[u8]) -> anyhow::Result<usize> {
+    let mut remaining = encoded_data;
+    let mut total = 0;
+    while !remaining.is_empty() {
+        if remaining.len() < 4 {
+            bail!("truncated snappy chunk header");
+        }
+        let chunk_type = remaining[0];
+        let length = u32::from_le_bytes([remaining[1], remaining[2], remaining[3], 0]) as usize;
+        let Some(chunk) = remaining.get(4..4 + length) else {
+            bail!("truncated snappy chunk");
+        };
+        // Compressed and uncompressed chunks start with a 4 byte checksum.
+        match chunk_type {
+            0x00 if length >= 4 => total += snap::raw::decompress_len(&chunk[4..])?,
+            0x01 if length >= 4 => total += length - 4,
+            0x00 | 0x01 => bail!("snappy chunk too short for its checksum"),
+            0x02..=0x7f => bail!("unknown unskippable snappy chunk {:#04x}", chunk_type),
+            _ => {}
+        }
+        remaining = &remaining[4 + length..];
+    }
+
+    Ok(total)
+}
@@ -0,0 +1,138 @@
+use crate::sink::Sink;
+use anyhow::{anyhow, Context};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const CURSOR_FILE: &str = "cursor.txt";
+
+/// First line of a versioned cursor file, followed by the format version.
+/// Files from before versioning start directly with the block number and are
+/// read as version 0, then rewritten in the current format on the next commit.
+const FORMAT_MARKER: &str = "era-file-sink cursor v";
+const FORMAT_VERSION: u32 = 1;
+
+/// Decides when the stream cursor is persisted relative to the era file writes.
+///
+/// A cursor is only ever committed after the bytes it covers have been synced
+/// to disk, so a restart never skips blocks that didn't make it into a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommitPolicy {
+    /// Sync and commit after every block. A restart loses no work and resumes
+    /// inside the partially written era, at the cost of one fsync per block.
+    PerBlock,
+    /// Sync and commit only once an era has been finalized. Cheap, but a
+    /// restart rebuilds the interrupted era from its first block.
+    PerEra,
+}
+
+impl FromStr for CommitPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-block" => Ok(CommitPolicy::PerBlock),
+            "per-era" => Ok(CommitPolicy::PerEra),
+            _ => Err(anyhow!(
+                "unknown cursor commit policy '{}', expected 'per-block' or 'per-era'",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub cursor: String,
+    pub block_number: u64,
+}
+
+/// Location of the cursor file in `output_dir`.
+pub fn path(output_dir: &Path) -> PathBuf {
+    output_dir.join(CURSOR_FILE)
+}
+
+pub fn load(output_dir: &Path) -> Result<Option<Cursor>, anyhow::Error> {
+    let path = path(output_dir);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context(format!("read cursor file '{}'", path.display())),
+    };
+
+    let content = content.trim_end();
+    let content = match content.strip_prefix(FORMAT_MARKER) {
+        Some(versioned) => {
+            let (version, rest) = versioned.split_once('\n').unwrap_or((versioned, ""));
+            let version = version.parse::<u32>().context(format!(
+                "cursor file '{}' has an invalid format version",
+                path.display()
+            ))?;
+            if version > FORMAT_VERSION {
+                return Err(anyhow!(
+                    "cursor file '{}' was written by a newer version of the sink (format {}, this build reads up to {})",
+                    path.display(),
+                    version,
+                    FORMAT_VERSION
+                ));
+            }
+            rest
+        }
+        None => content,
+    };
+
+    let (block_number, cursor) = content
+        .split_once('\n')
+        .ok_or(anyhow!("cursor file '{}' is malformed", path.display()))?;
+    let block_number = block_number.parse::<u64>().context(format!(
+        "cursor file '{}' has an invalid block number",
+        path.display()
+    ))?;
+
+    Ok(Some(Cursor {
+        cursor: cursor.to_string(),
+        block_number,
+    }))
+}
+
+/// Fetches the cursor stored next to the era files in `remote` when
+/// `output_dir` has none, so a run can resume on another machine.
+pub async fn restore(output_dir: &Path, remote: &Sink) -> Result<(), anyhow::Error> {
+    let path = path(output_dir);
+    if path.exists() || !remote.stores_cursor() {
+        return Ok(());
+    }
+    if let Some(content) = remote.download(CURSOR_FILE).await? {
+        println!("Restored the cursor from {}", remote.describe());
+        fs::write(&path, content).context(format!("write cursor file '{}'", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the cursor through a temporary file and a rename so a crash can
+/// never leave a half written cursor behind. The directory is synced after
+/// the rename, so the new cursor survives a crash once this returns.
+///
+/// `fs::rename` replaces an existing cursor file on every platform, including
+/// Windows.
+pub fn persist(output_dir: &Path, cursor: &Cursor) -> Result<(), anyhow::Error> {
+    let path = path(output_dir);
+    let tmp_path = output_dir.join(format!("{}.tmp", CURSOR_FILE));
+
+    let mut file = File::create(&tmp_path)?;
+    writeln!(file, "{}{}", FORMAT_MARKER, FORMAT_VERSION)?;
+    writeln!(file, "{}", cursor.block_number)?;
+    writeln!(file, "{}", cursor.cursor)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &path).context(format!("persist cursor to '{}'", path.display()))?;
+    // Directories can't be opened as files on Windows, where NTFS journals
+    // the rename itself.
+    #[cfg(unix)]
+    File::open(output_dir)
+        .and_then(|dir| dir.sync_all())
+        .context(format!("sync directory '{}'", output_dir.display()))?;
+
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use crate::cli::{EraRange, StreamArgs};
+use crate::http::HttpClient;
+use crate::substreams::{issue_token, SubstreamsEndpoint};
+use crate::{expected_package_sha256, read_package, DEFAULT_PACKAGE_FILE, ESTIMATED_ERA_SIZE};
+use era_file_sink::chain::ChainConfig;
+use era_file_sink::header_accumulator::{self, AccumulatorProvider, MissingValuePolicy, ValueList};
+use std::env;
+use std::fmt::Display;
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Largest difference with the clock of a remote server that is not reported.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Prints the outcome of each check and counts the failures.
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn ok(&mut self, check: &str, detail: impl Display) {
+        println!("[ok]   {}: {}", check, detail);
+    }
+
+    fn fail(&mut self, check: &str, problem: impl Display, fix: impl Display) {
+        println!("[FAIL] {}: {:#}", check, problem);
+        println!("       fix: {}", fix);
+        self.failures += 1;
+    }
+}
+
+/// Checks everything a run over `range` into `output_dir` depends on,
+/// printing a fix for every problem found. Returns whether all checks passed.
+pub async fn run(
+    output_dir: &Path,
+    range: EraRange,
+    stream: &StreamArgs,
+    chain: ChainConfig,
+) -> Result<bool, anyhow::Error> {
+    let mut report = Report::default();
+    let http = HttpClient::from_env()?;
+
+    match env::var("SUBSTREAMS_API_KEY") {
+        // A token given as is can only be checked by streaming with it.
+        _ if stream.token.is_some() => report.ok(
+            "token",
+            "a token is given with --token or SUBSTREAMS_JWT, it is sent as is",
+        ),
+        Ok(api_key) if !api_key.is_empty() => match issue_token(&api_key, &http).await {
+            Ok(_) => report.ok(
+                "token",
+                "SUBSTREAMS_API_KEY was exchanged for a session token",
+            ),
+            Err(err) => report.fail(
+                "token",
+                err,
+                "check that SUBSTREAMS_API_KEY holds a valid, unexpired API key",
+            ),
+        },
+        _ => report.fail(
+            "token",
+            "SUBSTREAMS_API_KEY is not set",
+            "set SUBSTREAMS_API_KEY to a Substreams API key, or SUBSTREAMS_JWT to a token",
+        ),
+    }
+
+    match stream.endpoint_url(chain) {
+        Ok(url) => match SubstreamsEndpoint::check_reachable(&url).await {
+            Ok(()) => report.ok("endpoint", format!("connected to {}", url)),
+            Err(err) => report.fail(
+                "endpoint",
+                err.context(format!("connect to {}", url)),
+                "check that the URL is http or https and the endpoint can be reached, or point --endpoint at another one",
+            ),
+        },
+        Err(err) => report.fail(
+            "endpoint",
+            err,
+            "set --endpoint or SUBSTREAMS_ENDPOINT to the URL of an endpoint",
+        ),
+    }
+
+    let package = match expected_package_sha256() {
+        Ok(expected_sha256) => read_package(&stream.spkg, &http, expected_sha256)
+            .await
+            .map(|(package, _)| package),
+        Err(err) => Err(err),
+    };
+    match package {
+        Ok(package) => {
+            let has_module = package.modules.is_some_and(|modules| {
+                modules
+                    .modules
+                    .iter()
+                    .any(|module| module.name == stream.module)
+            });
+            if has_module {
+                report.ok(
+                    "package",
+                    format!("{} holds module {}", stream.spkg, stream.module),
+                )
+            } else {
+                report.fail(
+                    "package",
+                    format!("{} has no module {}", stream.spkg, stream.module),
+                    "set --module to a module of the package",
+                )
+            }
+        }
+        Err(err) => report.fail(
+            "package",
+            err,
+            "check network access to the package or set HTTP_PROXY_URL, and check SUBSTREAMS_PACKAGE_SHA256 if set",
+        ),
+    }
+
+    check_clock(&mut report, &http).await;
+
+    let block_range = range.blocks(chain)?;
+    let eras = range.eras();
+    check_output_dir(&mut report, output_dir, eras);
+
+    let accumulator_source =
+        env::var("HEADER_ACCUMULATOR_SOURCE").unwrap_or_else(|_| "embedded".to_string());
+    let values = if accumulator_source == "computed" {
+        Ok(None)
+    } else if accumulator_source == "embedded" && !chain.embedded_accumulators {
+        Err(anyhow::anyhow!(
+            "no accumulator values are embedded for {}",
+            chain.network
+        ))
+    } else {
+        ValueList::load(&accumulator_source).await.map(Some)
+    };
+    match values.and_then(|values| {
+        let Some(values) = values else {
+            return Ok("values computed from the blocks of each era".to_string());
+        };
+        header_accumulator::validate_range(
+            &values,
+            block_range.0 as u64,
+            block_range.1,
+            MissingValuePolicy::Fail,
+        )?;
+        Ok(values.describe())
+    }) {
+        Ok(description) => report.ok(
+            "accumulators",
+            format!("{} cover the requested range", description),
+        ),
+        Err(err) => report.fail(
+            "accumulators",
+            err,
+            "set HEADER_ACCUMULATOR_SOURCE to values covering the range, shorten the range, or set MISSING_ACCUMULATOR=defer",
+        ),
+    }
+
+    if report.failures == 0 {
+        println!("All checks passed");
+    } else {
+        println!("{} checks failed", report.failures);
+    }
+
+    Ok(report.failures == 0)
+}
+
+/// Compares the local clock with the Date header of the package server, as a
+/// skewed clock makes session tokens look expired.
+async fn check_clock(report: &mut Report, http: &HttpClient) {
+    let remote = match http.send(|client| client.head(DEFAULT_PACKAGE_FILE)).await {
+        Ok(response) => response
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| httpdate::parse_http_date(date).ok()),
+        Err(err) => {
+            report.fail(
+                "clock",
+                format!("cannot fetch the time of the package server: {}", err),
+                "check network access to spkg.io",
+            );
+            return;
+        }
+    };
+    let Some(remote) = remote else {
+        report.fail(
+            "clock",
+            "the package server sent no usable Date header",
+            "compare the system clock with a trusted time source by hand",
+        );
+        return;
+    };
+
+    let now = SystemTime::now();
+    let skew = now
+        .duration_since(remote)
+        .or_else(|_| remote.duration_since(now))
+        .unwrap_or_default();
+    if skew > MAX_CLOCK_SKEW {
+        report.fail(
+            "clock",
+            format!("local clock is {}s off", skew.as_secs()),
+            "synchronize the system clock, for example by enabling NTP",
+        );
+    } else {
+        report.ok(
+            "clock",
+            format!("within {}s of the package server", skew.as_secs()),
+        );
+    }
+}
+
+/// Checks that `output_dir` can be written to and has room for `eras` era
+/// files.
+fn check_output_dir(report: &mut Report, output_dir: &Path, eras: u64) {
+    let probe = output_dir.join(".era-file-sink-doctor");
+    match File::create(&probe).and_then(|_| std::fs::remove_file(&probe)) {
+        Ok(()) => report.ok(
+            "output dir",
+            format!("{} is writable", output_dir.display()),
+        ),
+        Err(err) => {
+            report.fail(
+                "output dir",
+                format!("cannot write to {}: {}", output_dir.display(), err),
+                "create the directory or fix its ownership and permissions",
+            );
+            return;
+        }
+    }
+
+    let needed = eras * ESTIMATED_ERA_SIZE;
+    match fs2::available_space(output_dir) {
+        Ok(available) if available >= needed => report.ok(
+            "free space",
+            format!(
+                "{} GiB available, roughly {} GiB needed",
+                available / (1024 * 1024 * 1024),
+                needed / (1024 * 1024 * 1024)
+            ),
+        ),
+        Ok(available) => report.fail(
+            "free space",
+            format!(
+                "{} GiB available but roughly {} GiB needed",
+                available / (1024 * 1024 * 1024),
+                needed / (1024 * 1024 * 1024)
+            ),
+            "free up space, use a larger volume, or request fewer eras",
+        ),
+        Err(err) => report.fail(
+            "free space",
+            format!(
+                "cannot read the free space of {}: {}",
+                output_dir.display(),
+                err
+            ),
+            "check that the output directory exists",
+        ),
+    }
+}
@@ -0,0 +1,688 @@
+//! Post-merge `.era` files, which store the beacon chain where era1 files
+//! store execution blocks. An era spans 8192 slots and its file is laid out as
+//!
+//! `Version | CompressedSignedBeaconBlock* | CompressedBeaconState | SlotIndex | SlotIndex`
+//!
+//! The blocks of era `n` are those of slots `(n - 1) * 8192` up to
+//! `n * 8192`, excluded, and the state is the one at slot `n * 8192`, so era
+//! 0 only holds the genesis state. The first slot index lists the offset of
+//! the block of every slot of the era, 0 for slots without a block, and the
+//! second one the offset of the state. Era 0 has no block index. Offsets are
+//! relative to the start of the index entry.
+//!
+//! Blocks and states are SSZ encoded. They are compressed and stored as
+//! given, nothing here decodes them.
+
+use crate::compression::budget::DecompressionBudget;
+use crate::compression::MAX_ENTRY_SIZE;
+use crate::e2store::{read_entry_header, E2Store, E2StoreType, UnknownEntryPolicy, HEADER_SIZE};
+use crate::encryption::{EncryptionKey, EraReader, ENCRYPTED_SUFFIX};
+use crate::error::PipelineContext;
+use crate::writers::CountingWriter;
+use anyhow::{bail, Context};
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Slots spanned by an era.
+pub const SLOTS_PER_HISTORICAL_ROOT: u64 = 8192;
+
+/// Extension of post-merge era files, era1 files use `era1`.
+pub const BEACON_ERA_EXTENSION: &str = "era";
+
+/// File name of `era`, `<network>-<era>-<era count>-<short root>.era`. The
+/// short root is the first 4 bytes of `root`, the historical root the era
+/// ends with, or the genesis validators root for era 0.
+pub fn beacon_file_name(network: &str, era: u64, root: &[u8; 32]) -> String {
+    format!(
+        "{}-{:05}-{:05}-{}.{}",
+        network,
+        era,
+        1,
+        hex::encode(&root[..4]),
+        BEACON_ERA_EXTENSION
+    )
+}
+
+/// Whether `path` names a post-merge era file, encrypted or not.
+pub fn is_beacon_era_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|file_name| file_name.to_str())
+        .map(|file_name| {
+            file_name
+                .strip_suffix(ENCRYPTED_SUFFIX)
+                .unwrap_or(file_name)
+        })
+        .is_some_and(|file_name| file_name.ends_with(&format!(".{}", BEACON_ERA_EXTENSION)))
+}
+
+/// First slot whose block belongs to `era`, none for era 0.
+fn first_block_slot(era: u64) -> Option<u64> {
+    era.checked_sub(1)
+        .map(|previous| previous * SLOTS_PER_HISTORICAL_ROOT)
+}
+
+/// Slot of the state stored in `era`.
+fn state_slot(era: u64) -> u64 {
+    era * SLOTS_PER_HISTORICAL_ROOT
+}
+
+/// Where the builder is in the life cycle of the era on its writer, like the
+/// era1 builder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Building { era: u64, last_slot: Option<u64> },
+    Finalized { era: u64 },
+}
+
+/// Writes a `.era` file: `start_era`, one `add_block` per slot with a block,
+/// in slot order, then `finalize` with the state.
+pub struct BeaconEraBuilder<W: Write> {
+    writer: CountingWriter<W>,
+    phase: Phase,
+    /// Offset of the block of each slot of the era, 0 for slots without one.
+    block_offsets: Vec<u64>,
+}
+
+impl<W: Write> BeaconEraBuilder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: CountingWriter::new(writer),
+            phase: Phase::Idle,
+            block_offsets: Vec::new(),
+        }
+    }
+
+    /// Begins `era` by writing the Version record.
+    pub fn start_era(&mut self, era: u64) -> Result<(), anyhow::Error> {
+        if self.phase != Phase::Idle {
+            bail!("cannot start an era in phase {:?}", self.phase);
+        }
+
+        self.write(E2Store::new(E2StoreType::Version, Vec::new())?)
+            .stage("write-version")?;
+        self.block_offsets = match first_block_slot(era) {
+            Some(_) => vec![0; SLOTS_PER_HISTORICAL_ROOT as usize],
+            None => Vec::new(),
+        };
+        self.phase = Phase::Building {
+            era,
+            last_slot: None,
+        };
+
+        Ok(())
+    }
+
+    /// Appends the SSZ encoded signed beacon block of `slot`.
+    pub fn add_block(&mut self, slot: u64, block: &[u8]) -> Result<(), anyhow::Error> {
+        let Phase::Building { era, last_slot } = self.phase else {
+            bail!("cannot add a block in phase {:?}", self.phase);
+        };
+        let Some(first_slot) = first_block_slot(era) else {
+            bail!("era 0 only holds the genesis state, it has no blocks");
+        };
+        if slot < first_slot || slot >= state_slot(era) {
+            bail!(
+                "the block of slot {} isn't part of era {}, which holds slots {} to {}",
+                slot,
+                era,
+                first_slot,
+                state_slot(era) - 1
+            );
+        }
+        if let Some(last_slot) = last_slot.filter(|last_slot| slot <= *last_slot) {
+            bail!(
+                "the block of slot {} comes after the block of slot {}",
+                slot,
+                last_slot
+            );
+        }
+
+        let entry = E2Store::compressed(E2StoreType::CompressedSignedBeaconBlock, block)
+            .stage("compress-beacon-block")?;
+        self.block_offsets[(slot - first_slot) as usize] = self.writer.count();
+        self.write(entry).stage("write-beacon-block")?;
+        self.phase = Phase::Building {
+            era,
+            last_slot: Some(slot),
+        };
+
+        Ok(())
+    }
+
+    /// Appends the SSZ encoded beacon state at the end of the era and the
+    /// slot indices, completing the file.
+    pub fn finalize(&mut self, state: &[u8]) -> Result<(), anyhow::Error> {
+        let Phase::Building { era, .. } = self.phase else {
+            bail!("cannot finalize an era in phase {:?}", self.phase);
+        };
+
+        let state_offset = self.writer.count();
+        let entry = E2Store::compressed(E2StoreType::CompressedBeaconState, state)
+            .stage("compress-beacon-state")?;
+        self.write(entry).stage("write-beacon-state")?;
+
+        if let Some(first_slot) = first_block_slot(era) {
+            let index_offset = self.writer.count() as i64;
+            let offsets: Vec<i64> = self
+                .block_offsets
+                .iter()
+                .map(|offset| match offset {
+                    0 => 0,
+                    offset => *offset as i64 - index_offset,
+                })
+                .collect();
+            self.write(slot_index(first_slot, &offsets)?)
+                .stage("write-block-index")?;
+        }
+        let index_offset = self.writer.count() as i64;
+        self.write(slot_index(
+            state_slot(era),
+            &[state_offset as i64 - index_offset],
+        )?)
+        .stage("write-state-index")?;
+        self.phase = Phase::Finalized { era };
+
+        Ok(())
+    }
+
+    /// Blocks added to the era being built.
+    pub fn blocks(&self) -> usize {
+        self.block_offsets
+            .iter()
+            .filter(|offset| **offset != 0)
+            .count()
+    }
+
+    fn write(&mut self, entry: E2Store) -> Result<(), std::io::Error> {
+        self.writer.write_all(&entry.into_bytes())
+    }
+}
+
+/// A SlotIndex entry: the starting slot, one offset per slot and their count.
+fn slot_index(starting_slot: u64, offsets: &[i64]) -> Result<E2Store, anyhow::Error> {
+    let mut data = Vec::with_capacity(16 + 8 * offsets.len());
+    data.extend_from_slice(&starting_slot.to_le_bytes());
+    for offset in offsets {
+        data.extend_from_slice(&offset.to_le_bytes());
+    }
+    data.extend_from_slice(&(offsets.len() as u64).to_le_bytes());
+
+    E2Store::new(E2StoreType::SlotIndex, data)
+}
+
+/// Reads the entry data of a SlotIndex back into its starting slot and
+/// offsets.
+fn parse_slot_index(mut data: &[u8]) -> Result<(u64, Vec<i64>), anyhow::Error> {
+    let slots = data.len().saturating_sub(16) / 8;
+    if data.len() != 16 + 8 * slots {
+        bail!("slot index is {} bytes long", data.len());
+    }
+    let starting_slot = data.read_u64::<LittleEndian>()?;
+    let offsets = (0..slots)
+        .map(|_| data.read_i64::<LittleEndian>())
+        .collect::<Result<Vec<i64>, std::io::Error>>()?;
+    let count = data.read_u64::<LittleEndian>()?;
+    if count != slots as u64 {
+        bail!("slot index counts {} slots but lists {}", count, slots);
+    }
+
+    Ok((starting_slot, offsets))
+}
+
+/// Outcome of [`verify_beacon_era_file`].
+#[derive(Debug, Default)]
+pub struct BeaconEraReport {
+    pub era: u64,
+    pub blocks: u64,
+    /// Every way the file breaks the format, empty if it is valid.
+    pub problems: Vec<String>,
+}
+
+impl BeaconEraReport {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks that the `.era` file at `path` holds a version entry, blocks, one
+/// state and the slot indices, in that order, that every block and the state
+/// decompress, and that the indices point at them. An error is only returned
+/// when the file can't be read at all. Encrypted files are decrypted with
+/// `key` first.
+pub fn verify_beacon_era_file(
+    path: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+    budget: &DecompressionBudget,
+) -> Result<BeaconEraReport, anyhow::Error> {
+    let mut reader = EraReader::open(path, key)?;
+    let mut report = BeaconEraReport::default();
+    let mut block_offsets = Vec::new();
+    let mut state_offset = None;
+    let mut indices = Vec::new();
+    let mut entries = 0;
+    let mut offset = 0;
+    loop {
+        let entry_offset = offset;
+        let (type_, length) = match read_entry_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        unknown_entries.check(type_, entry_offset)?;
+        offset += HEADER_SIZE + length as u64;
+        let mut data = vec![0; length as usize];
+        reader
+            .read_exact(&mut data)
+            .context(format!("read entry at offset {}", entry_offset))?;
+        let Ok(type_) = E2StoreType::try_from(type_) else {
+            continue;
+        };
+
+        let in_order = match type_ {
+            E2StoreType::Version => entries == 0,
+            E2StoreType::CompressedSignedBeaconBlock => {
+                entries > 0 && state_offset.is_none() && indices.is_empty()
+            }
+            E2StoreType::CompressedBeaconState => {
+                entries > 0 && state_offset.is_none() && indices.is_empty()
+            }
+            E2StoreType::SlotIndex => state_offset.is_some() && indices.len() < 2,
+            _ => false,
+        };
+        entries += 1;
+        if !in_order {
+            report.problems.push(format!(
+                "entry at offset {} is a {:?} entry, which doesn't belong there in a .era file",
+                entry_offset, type_
+            ));
+            return Ok(report);
+        }
+
+        match type_ {
+            E2StoreType::CompressedSignedBeaconBlock | E2StoreType::CompressedBeaconState => {
+                if let Err(err) = budget.snap_decode(&data, MAX_ENTRY_SIZE) {
+                    report.problems.push(format!(
+                        "{:?} entry at offset {} doesn't decompress: {:#}",
+                        type_, entry_offset, err
+                    ));
+                }
+                if type_ == E2StoreType::CompressedBeaconState {
+                    state_offset = Some(entry_offset);
+                } else {
+                    block_offsets.push(entry_offset);
+                }
+            }
+            E2StoreType::SlotIndex => match parse_slot_index(&data) {
+                Ok((starting_slot, offsets)) => {
+                    indices.push((entry_offset, starting_slot, offsets))
+                }
+                Err(err) => {
+                    report
+                        .problems
+                        .push(format!("slot index at offset {}: {:#}", entry_offset, err));
+                    return Ok(report);
+                }
+            },
+            _ => {}
+        }
+    }
+    report.blocks = block_offsets.len() as u64;
+
+    let Some(state_offset) = state_offset else {
+        report
+            .problems
+            .push("file ends without a beacon state".to_string());
+        return Ok(report);
+    };
+    let Some((index_offset, starting_slot, offsets)) = indices.pop() else {
+        report
+            .problems
+            .push("file ends without a state index".to_string());
+        return Ok(report);
+    };
+    if starting_slot % SLOTS_PER_HISTORICAL_ROOT != 0 {
+        report.problems.push(format!(
+            "state index starts at slot {}, which doesn't end an era",
+            starting_slot
+        ));
+        return Ok(report);
+    }
+    report.era = starting_slot / SLOTS_PER_HISTORICAL_ROOT;
+    if offsets.len() != 1 || offsets[0] + index_offset as i64 != state_offset as i64 {
+        report.problems.push(format!(
+            "state index doesn't point at the state at offset {}",
+            state_offset
+        ));
+    }
+
+    match (first_block_slot(report.era), indices.pop()) {
+        (None, None) => {
+            if !block_offsets.is_empty() {
+                report
+                    .problems
+                    .push("era 0 holds blocks, it should only hold the genesis state".to_string());
+            }
+        }
+        (None, Some(_)) => report
+            .problems
+            .push("era 0 has a block index, it has no blocks".to_string()),
+        (Some(_), None) => report.problems.push("file has no block index".to_string()),
+        (Some(first_slot), Some((index_offset, starting_slot, offsets))) => {
+            check_block_index(
+                &mut report,
+                first_slot,
+                index_offset,
+                starting_slot,
+                &offsets,
+                &block_offsets,
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+/// Checks that the block index at `index_offset` starts at `first_slot`,
+/// spans the era and points at the blocks at `block_offsets`, in order.
+fn check_block_index(
+    report: &mut BeaconEraReport,
+    first_slot: u64,
+    index_offset: u64,
+    starting_slot: u64,
+    offsets: &[i64],
+    block_offsets: &[u64],
+) {
+    if starting_slot != first_slot {
+        report.problems.push(format!(
+            "block index starts at slot {}, expected {}",
+            starting_slot, first_slot
+        ));
+    }
+    if offsets.len() as u64 != SLOTS_PER_HISTORICAL_ROOT {
+        report.problems.push(format!(
+            "block index spans {} slots, expected {}",
+            offsets.len(),
+            SLOTS_PER_HISTORICAL_ROOT
+        ));
+    }
+    let blocks: HashSet<u64> = block_offsets.iter().copied().collect();
+    let mut indexed = Vec::new();
+    for (slot, offset) in offsets.iter().enumerate() {
+        if *offset == 0 {
+            continue;
+        }
+        let target = *offset + index_offset as i64;
+        if target < 0 || !blocks.contains(&(target as u64)) {
+            report.problems.push(format!(
+                "block index entry of slot {} points at offset {}, where there is no block",
+                starting_slot + slot as u64,
+                target
+            ));
+        } else {
+            indexed.push(target as u64);
+        }
+    }
+    if indexed != block_offsets {
+        report.problems.push(format!(
+            "block index lists {} of the {} blocks, or not in file order",
+            indexed.len(),
+            block_offsets.len()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::snap_decode;
+    use crate::e2store::verify::verify_era_file;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    /// The entries of a file, with their offsets.
+    fn entries(file: &[u8]) -> Vec<(u64, E2StoreType, Vec<u8>)> {
+        let mut reader = Cursor::new(file);
+        let mut entries = Vec::new();
+        while reader.position() < file.len() as u64 {
+            let offset = reader.position();
+            let (type_, length) = read_entry_header(&mut reader).unwrap();
+            let mut data = vec![0; length as usize];
+            reader.read_exact(&mut data).unwrap();
+            entries.push((offset, E2StoreType::try_from(type_).unwrap(), data));
+        }
+        entries
+    }
+
+    /// Builds `era` from the blocks in `blocks` and `state`.
+    fn build(era: u64, blocks: &[(u64, Vec<u8>)], state: &[u8]) -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut builder = BeaconEraBuilder::new(&mut file);
+        builder.start_era(era).unwrap();
+        for (slot, block) in blocks {
+            builder.add_block(*slot, block).unwrap();
+        }
+        assert_eq!(builder.blocks(), blocks.len());
+        builder.finalize(state).unwrap();
+        file
+    }
+
+    /// Writes `file` where the verifier can read it.
+    fn write_file(name: &str, file: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "era-file-sink-{}-{}.{}",
+            std::process::id(),
+            name,
+            BEACON_ERA_EXTENSION
+        ));
+        std::fs::write(&path, file).unwrap();
+        path
+    }
+
+    fn verify(name: &str, file: &[u8]) -> BeaconEraReport {
+        let path = write_file(name, file);
+        let report = verify_beacon_era_file(
+            &path,
+            UnknownEntryPolicy::Strict,
+            None,
+            &DecompressionBudget::new(1 << 30),
+        )
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+        report
+    }
+
+    #[test]
+    fn genesis_era_holds_only_the_state() {
+        let state = vec![0x42; 10_000];
+        let file = build(0, &[], &state);
+
+        let entries = entries(&file);
+        let types: Vec<E2StoreType> = entries.iter().map(|(_, type_, _)| *type_).collect();
+        assert_eq!(
+            types,
+            [
+                E2StoreType::Version,
+                E2StoreType::CompressedBeaconState,
+                E2StoreType::SlotIndex
+            ]
+        );
+        assert_eq!(snap_decode(&entries[1].2, MAX_ENTRY_SIZE).unwrap(), state);
+        let (index_offset, _, index) = &entries[2];
+        let (starting_slot, offsets) = parse_slot_index(index).unwrap();
+        assert_eq!(starting_slot, 0);
+        assert_eq!(offsets, [entries[1].0 as i64 - *index_offset as i64]);
+
+        let report = verify("genesis", &file);
+        assert!(report.passed(), "{:?}", report.problems);
+        assert_eq!((report.era, report.blocks), (0, 0));
+
+        let mut builder = BeaconEraBuilder::new(Vec::new());
+        builder.start_era(0).unwrap();
+        assert!(builder.add_block(0, b"block").is_err());
+    }
+
+    #[test]
+    fn sparse_era_indexes_empty_slots_as_zero() {
+        let era = 3;
+        let first_slot = 2 * SLOTS_PER_HISTORICAL_ROOT;
+        let filled = [0, 2, 8191];
+        let blocks: Vec<(u64, Vec<u8>)> = filled
+            .iter()
+            .map(|slot| (first_slot + slot, vec![*slot as u8; 500]))
+            .collect();
+        let file = build(era, &blocks, b"state");
+
+        let entries = entries(&file);
+        let types: Vec<E2StoreType> = entries.iter().map(|(_, type_, _)| *type_).collect();
+        assert_eq!(
+            types,
+            [
+                E2StoreType::Version,
+                E2StoreType::CompressedSignedBeaconBlock,
+                E2StoreType::CompressedSignedBeaconBlock,
+                E2StoreType::CompressedSignedBeaconBlock,
+                E2StoreType::CompressedBeaconState,
+                E2StoreType::SlotIndex,
+                E2StoreType::SlotIndex
+            ]
+        );
+        for ((_, block), (_, _, data)) in blocks.iter().zip(&entries[1..4]) {
+            assert_eq!(&snap_decode(data, MAX_ENTRY_SIZE).unwrap(), block);
+        }
+
+        // Offsets are relative to the start of the index entry.
+        let (block_index_offset, _, block_index) = &entries[5];
+        let (starting_slot, offsets) = parse_slot_index(block_index).unwrap();
+        assert_eq!(starting_slot, first_slot);
+        assert_eq!(offsets.len() as u64, SLOTS_PER_HISTORICAL_ROOT);
+        for (slot, offset) in offsets.iter().enumerate() {
+            let expected = match filled.iter().position(|filled| *filled == slot as u64) {
+                Some(block) => entries[1 + block].0 as i64 - *block_index_offset as i64,
+                None => 0,
+            };
+            assert_eq!(*offset, expected, "slot {}", slot);
+        }
+        let (state_index_offset, _, state_index) = &entries[6];
+        let (starting_slot, offsets) = parse_slot_index(state_index).unwrap();
+        assert_eq!(starting_slot, era * SLOTS_PER_HISTORICAL_ROOT);
+        assert_eq!(offsets, [entries[4].0 as i64 - *state_index_offset as i64]);
+
+        let report = verify("sparse", &file);
+        assert!(report.passed(), "{:?}", report.problems);
+        assert_eq!((report.era, report.blocks), (era, 3));
+    }
+
+    #[test]
+    fn verifier_rejects_tampered_indices() {
+        let first_slot = SLOTS_PER_HISTORICAL_ROOT;
+        let blocks = [(first_slot, vec![1; 100]), (first_slot + 5, vec![2; 100])];
+        let file = build(2, &blocks, b"state");
+        let entries = entries(&file);
+        let block_index_data = entries[4].0 + HEADER_SIZE;
+        let state_index_data = entries[5].0 + HEADER_SIZE;
+        let tampered = |position: u64, value: i64| {
+            let mut file = file.clone();
+            let position = position as usize;
+            file[position..position + 8].copy_from_slice(&value.to_le_bytes());
+            file
+        };
+
+        // An empty slot pointing at the state.
+        let report = verify(
+            "empty-slot",
+            &tampered(
+                block_index_data + 8 + 8,
+                entries[3].0 as i64 - entries[4].0 as i64,
+            ),
+        );
+        assert_eq!(
+            report.problems,
+            [format!(
+                "block index entry of slot {} points at offset {}, where there is no block",
+                first_slot + 1,
+                entries[3].0
+            )]
+        );
+
+        // The second block pointing at the first.
+        let report = verify(
+            "swapped",
+            &tampered(
+                block_index_data + 8 + 8 * 5,
+                entries[1].0 as i64 - entries[4].0 as i64,
+            ),
+        );
+        assert_eq!(
+            report.problems,
+            ["block index lists 2 of the 2 blocks, or not in file order"]
+        );
+
+        // The state index pointing at a block.
+        let report = verify(
+            "state",
+            &tampered(
+                state_index_data + 8,
+                entries[1].0 as i64 - entries[5].0 as i64,
+            ),
+        );
+        assert_eq!(
+            report.problems,
+            [format!(
+                "state index doesn't point at the state at offset {}",
+                entries[3].0
+            )]
+        );
+
+        // A block index count that doesn't match its offsets.
+        let report = verify(
+            "count",
+            &tampered(entries[5].0 - 8, SLOTS_PER_HISTORICAL_ROOT as i64 + 1),
+        );
+        assert_eq!(
+            report.problems,
+            [format!(
+                "slot index at offset {}: slot index counts 8193 slots but lists 8192",
+                entries[4].0
+            )]
+        );
+    }
+
+    #[test]
+    fn builder_refuses_blocks_outside_the_era_or_out_of_order() {
+        let mut builder = BeaconEraBuilder::new(Vec::new());
+        builder.start_era(1).unwrap();
+        assert!(builder
+            .add_block(SLOTS_PER_HISTORICAL_ROOT, b"next era")
+            .is_err());
+        builder.add_block(5, b"block").unwrap();
+        assert!(builder.add_block(5, b"same slot").is_err());
+        assert!(builder.add_block(4, b"earlier slot").is_err());
+        builder.finalize(b"state").unwrap();
+        assert!(builder.add_block(6, b"after finalize").is_err());
+    }
+
+    #[test]
+    fn era1_readers_refuse_beacon_entries() {
+        let file = build(1, &[(0, vec![1; 100])], b"state");
+        let path = write_file("era1", &file);
+        let err = verify_era_file(
+            &path,
+            UnknownEntryPolicy::Warn,
+            None,
+            &DecompressionBudget::new(1 << 30),
+        )
+        .unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "entry at offset {} is a CompressedSignedBeaconBlock entry, which only belongs in post-merge .era files",
+                HEADER_SIZE
+            )
+        );
+    }
+}
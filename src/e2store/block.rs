@@ -0,0 +1,107 @@
+//! Encoding of a single block into the header, body and receipts payloads of
+//! an era file, independent of any builder state.
+
+use crate::chain::ChainConfig;
+use crate::e2store::{rlp_encode, rlp_encode_legacy_receipts};
+use crate::error::PipelineContext;
+use crate::model::{self, Block};
+use crate::reth_mappings::map_signed_transaction;
+use anyhow::Context;
+use bytes::BytesMut;
+use reth_primitives::{BlockBody as RethBlockBody, Header, ReceiptWithBloom};
+
+/// Receipts of one block, in the encoding its fork calls for.
+pub enum Receipts<'a> {
+    /// Before Byzantium, with a post-transaction state root.
+    Legacy(Vec<&'a model::Receipt>),
+    /// From Byzantium on, with a status and a bloom.
+    Typed(Vec<ReceiptWithBloom>),
+}
+
+/// A block mapped to the types era files encode.
+pub struct MappedBlock<'a> {
+    pub header: Header,
+    pub body: RethBlockBody,
+    pub receipts: Receipts<'a>,
+}
+
+/// Canonical RLP encodings of a block's header, body and receipts.
+pub struct EncodedBlock {
+    pub header: BytesMut,
+    pub body: BytesMut,
+    pub receipts: BytesMut,
+}
+
+/// Maps `block` of `chain`, walking its transactions once for both the body
+/// and the receipts.
+pub fn map_block(block: &Block, chain: ChainConfig) -> Result<MappedBlock<'_>, anyhow::Error> {
+    let header = Header::try_from(&block.header).stage("map-header")?;
+
+    let transactions = if block.number == 0 {
+        &[][..]
+    } else {
+        block.transactions.as_slice()
+    };
+
+    let mut signed = Vec::with_capacity(transactions.len());
+    let mut receipts = if block.number < chain.byzantium_block {
+        Receipts::Legacy(Vec::with_capacity(transactions.len()))
+    } else {
+        Receipts::Typed(Vec::with_capacity(transactions.len()))
+    };
+    for tx in transactions {
+        signed.push(
+            map_signed_transaction(tx, chain.chain_id)
+                .with_transaction(&tx.hash)
+                .stage("map-transactions")?,
+        );
+        match &mut receipts {
+            Receipts::Legacy(receipts) => receipts.push(
+                tx.receipt
+                    .as_ref()
+                    .ok_or(anyhow::anyhow!("No receipt"))
+                    .with_transaction(&tx.hash)
+                    .stage("map-receipts")?,
+            ),
+            Receipts::Typed(receipts) => receipts.push(
+                ReceiptWithBloom::try_from(tx)
+                    .with_transaction(&tx.hash)
+                    .stage("map-receipts")?,
+            ),
+        }
+    }
+
+    let body = RethBlockBody {
+        transactions: signed,
+        ommers: block
+            .uncles
+            .iter()
+            .enumerate()
+            .map(|(index, uncle)| {
+                Header::try_from(uncle)
+                    .context(format!("uncle {}", index))
+                    .stage("map-uncles")
+            })
+            .collect::<Result<Vec<Header>, anyhow::Error>>()?,
+        withdrawals: None,
+    };
+
+    Ok(MappedBlock {
+        header,
+        body,
+        receipts,
+    })
+}
+
+impl MappedBlock<'_> {
+    pub fn rlp(&self) -> EncodedBlock {
+        EncodedBlock {
+            header: rlp_encode(&self.header),
+            body: rlp_encode(&self.body),
+            receipts: match &self.receipts {
+                Receipts::Legacy(receipts) => rlp_encode_legacy_receipts(receipts),
+                Receipts::Typed(receipts) => rlp_encode(receipts),
+            },
+        }
+    }
+}
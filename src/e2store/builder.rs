@@ -1,135 +1,355 @@
+use crate::chain::ChainConfig;
+use crate::compression::{snap_decode, MAX_ENTRY_SIZE};
+use crate::e2store::block::map_block;
 use crate::e2store::utils::encode_bigint;
-use crate::e2store::{E2Store, E2StoreType, BYZANTIUM_HARDFORK};
-use crate::pb::acme::verifiable_block::v1::{TransactionReceipt, VerifiableBlock};
-use decoder::receipts::error::ReceiptError;
-use reth_primitives::{BlockBody as RethBlockBody, Header, ReceiptWithBloom, TransactionSigned};
-use std::io::Write;
+use crate::e2store::{read_entry_header, E2Store, E2StoreType, UnknownEntryPolicy, HEADER_SIZE};
+use crate::epoch::get_epoch;
+use crate::error::PipelineContext;
+use crate::header_accumulator::{AccumulatorRoot, EpochAccumulator};
+use crate::model::Block;
+use crate::timings::{timed, EraTimings};
+use crate::total_difficulty::TotalDifficultyProvider;
+use crate::writers::{CountingWriter, HashingWriter};
+use anyhow::{anyhow, bail, Context};
+use reth_primitives::{keccak256, Header};
+use reth_rlp::Decodable;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+/// Upper bound for a decompressed header, far above any valid encoding.
+pub const MAX_HEADER_SIZE: usize = 1 << 20;
+
+/// Limit of body and receipts entries when `MAX_BODY_ENTRY_MIB` or
+/// `MAX_RECEIPTS_ENTRY_MIB` isn't set, far above any mainnet block.
+const DEFAULT_ENTRY_LIMIT_MIB: usize = 256;
+
+/// Largest RLP encodings, before compression, the builder writes into the
+/// entries of a block. A block above them fails to be added instead of
+/// producing a file readers refuse to inflate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryLimits {
+    pub header: usize,
+    pub body: usize,
+    pub receipts: usize,
+}
+
+impl Default for EntryLimits {
+    fn default() -> Self {
+        Self {
+            header: MAX_HEADER_SIZE,
+            body: DEFAULT_ENTRY_LIMIT_MIB << 20,
+            receipts: DEFAULT_ENTRY_LIMIT_MIB << 20,
+        }
+    }
+}
+
+impl EntryLimits {
+    /// The limits in `MAX_BODY_ENTRY_MIB` and `MAX_RECEIPTS_ENTRY_MIB`, 256
+    /// MiB by default. Headers are always limited to [`MAX_HEADER_SIZE`], the
+    /// size readers decode them up to.
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            header: MAX_HEADER_SIZE,
+            body: limit_from_env("MAX_BODY_ENTRY_MIB")?,
+            receipts: limit_from_env("MAX_RECEIPTS_ENTRY_MIB")?,
+        })
+    }
+
+    fn check(&self, block: u64, header: usize, body: usize, receipts: usize) -> anyhow::Result<()> {
+        let entries = [
+            ("header", header, self.header, None),
+            ("body", body, self.body, Some("MAX_BODY_ENTRY_MIB")),
+            (
+                "receipts",
+                receipts,
+                self.receipts,
+                Some("MAX_RECEIPTS_ENTRY_MIB"),
+            ),
+        ];
+        for (name, size, limit, variable) in entries {
+            if size > limit {
+                bail!(
+                    "the {} of block {} is {} bytes, above the limit of {} bytes{}",
+                    name,
+                    block,
+                    size,
+                    limit,
+                    variable.map_or(String::new(), |variable| format!(
+                        ", raise {} if the block is valid",
+                        variable
+                    ))
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn limit_from_env(variable: &str) -> Result<usize, anyhow::Error> {
+    let mib = match env::var(variable) {
+        Ok(mib) => mib
+            .parse::<usize>()
+            .context(format!("{} is not a valid integer", variable))?,
+        Err(_) => DEFAULT_ENTRY_LIMIT_MIB,
+    };
+    if mib == 0 {
+        bail!("{} must be above 0", variable);
+    }
+    // Readers inflate entries up to the e2store limit.
+    mib.checked_mul(1 << 20)
+        .filter(|bytes| *bytes <= MAX_ENTRY_SIZE)
+        .ok_or(anyhow!(
+            "{} is above the e2store limit of {} MiB",
+            variable,
+            MAX_ENTRY_SIZE >> 20
+        ))
+}
+
+/// Where the builder is in the life cycle of the era on its current writer.
+/// Every era goes through `start_era`, one `add` per block and `finalize`, in
+/// that order, so each file gets exactly one Version record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Nothing written to the current writer yet.
+    Idle,
+    /// Version written, blocks are added from `starting_number` on.
+    Building { starting_number: u64 },
+    /// Accumulator and block index written, the era is complete.
+    Finalized {
+        starting_number: u64,
+        header_accumulator: AccumulatorRoot,
+    },
+}
 
 pub struct EraBuilder<W: Write> {
-    writer: W,
-    bytes_written: u64,
+    /// The current writer. Bytes are counted for the block index offsets and
+    /// hashed as they are written, so finalizing needs no extra pass over the
+    /// file.
+    writer: HashingWriter<CountingWriter<W>>,
+    chain: ChainConfig,
+    total_difficulty: Box<dyn TotalDifficultyProvider>,
+    limits: EntryLimits,
     indexes: Vec<u64>,
-    pub(crate) starting_number: i64,
+    phase: Phase,
+    timings: EraTimings,
+    digest: Option<[u8; 32]>,
+    /// Records of the blocks added so far, for computing the accumulator
+    /// root of the era instead of looking it up.
+    accumulator: EpochAccumulator,
 }
 
 impl<W: Write> EraBuilder<W> {
-    pub fn new(writer: W) -> Self {
+    pub fn new(
+        writer: W,
+        chain: ChainConfig,
+        total_difficulty: Box<dyn TotalDifficultyProvider>,
+    ) -> Self {
         Self {
-            writer,
-            bytes_written: 0,
+            writer: HashingWriter::new(CountingWriter::new(writer)),
+            chain,
+            total_difficulty,
+            limits: EntryLimits::default(),
             indexes: Vec::new(),
-            starting_number: -1,
+            phase: Phase::Idle,
+            timings: EraTimings::default(),
+            digest: None,
+            accumulator: EpochAccumulator::default(),
         }
     }
 
-    pub fn add(&mut self, block: VerifiableBlock) -> Result<(), anyhow::Error> {
-        if self.starting_number == -1 {
-            let version = E2Store {
-                type_: E2StoreType::Version,
-                length: 0,
-                reserved: 0,
-                data: Vec::new(),
-            };
-            let version = version.into_bytes();
+    /// Begins an era whose first block is `starting_number` by writing the
+    /// Version record.
+    pub fn start_era(&mut self, starting_number: u64) -> Result<(), anyhow::Error> {
+        if self.phase != Phase::Idle {
+            bail!("cannot start an era in phase {:?}", self.phase);
+        }
+
+        let version = E2Store::new(E2StoreType::Version, Vec::new())?.into_bytes();
+
+        self.write(&version).stage("write-version")?;
+        self.phase = Phase::Building { starting_number };
+
+        Ok(())
+    }
 
-            self.writer.write_all(&version)?;
-            self.bytes_written += version.len() as u64;
-            self.starting_number = block.number as i64;
+    pub fn add(&mut self, block: Block) -> Result<(), anyhow::Error> {
+        let Phase::Building { starting_number } = self.phase else {
+            bail!("cannot add a block in phase {:?}", self.phase);
+        };
+        let expected_number = starting_number + self.indexes.len() as u64;
+        if block.number != expected_number {
+            bail!(
+                "expected block {} but received block {}",
+                expected_number,
+                block.number
+            );
+        }
+        // era1 only covers proof-of-work history, which ends at the merge.
+        let difficulty = block.header.difficulty.as_ref();
+        if difficulty.is_some_and(|difficulty| difficulty.iter().all(|byte| *byte == 0)) {
+            bail!(
+                "block {} has zero difficulty, post-merge blocks can't be stored in era1 files",
+                block.number
+            );
         }
 
-        self.indexes.push(self.bytes_written);
-        let header = block.header.clone().ok_or(anyhow::anyhow!("No header"))?;
-        let block_header = Header::try_from(&header)?;
-        let total_difficulty = header
+        let chain = self.chain;
+        let mapped = timed(&mut self.timings.mapping, || map_block(&block, chain))?;
+        let total_difficulty = self
             .total_difficulty
-            .ok_or(anyhow::anyhow!("No total difficulty"))?;
-        let header = E2Store::try_from(block_header)?;
-        let header = header.into_bytes();
-        self.writer.write_all(&header)?;
-        self.bytes_written += header.len() as u64;
-
-        let transactions = if block.number == 0 {
-            Vec::new()
-        } else {
-            block.transactions
-        };
+            .total_difficulty(&block)
+            .stage("map-total-difficulty")?;
+        let encoded = timed(&mut self.timings.rlp, || mapped.rlp());
 
-        let reth_body = RethBlockBody {
-            transactions: transactions
-                .clone()
-                .into_iter()
-                .map(|tx| TransactionSigned::try_from(&tx.clone()).unwrap())
-                .collect(),
-            ommers: block
-                .uncles
-                .clone()
-                .into_iter()
-                .map(|uncle| Header::try_from(&uncle.clone()).unwrap())
-                .collect(),
-            withdrawals: None,
-        };
+        self.add_block_parts(
+            &encoded.header,
+            &encoded.body,
+            &encoded.receipts,
+            total_difficulty,
+        )
+    }
 
-        let body = E2Store::try_from(reth_body)?.into_bytes();
-
-        self.writer.write_all(&body)?;
-        self.bytes_written += body.len() as u64;
-        let receipts = if block.number < BYZANTIUM_HARDFORK {
-            let receipts_vec = transactions
-                .iter()
-                .map(|transaction| {
-                    transaction
-                        .receipt
-                        .clone()
-                        .ok_or(anyhow::anyhow!("No receipt"))
-                })
-                .collect::<Result<Vec<TransactionReceipt>, anyhow::Error>>()?;
-            E2Store::try_from(receipts_vec)?
-        } else {
-            let receipts_vec = transactions
-                .iter()
-                .map(|transaction| ReceiptWithBloom::try_from(transaction.clone()))
-                .collect::<Result<Vec<ReceiptWithBloom>, ReceiptError>>()?;
-            E2Store::try_from(receipts_vec)?
+    /// Appends a block from its canonical RLP encoded header, body and
+    /// receipts and its big endian total difficulty, for callers that already
+    /// have the encodings and don't go through the protobuf mapping. Blocks
+    /// must be passed in order, from the starting number of the era on, and
+    /// a header of another block is refused.
+    pub fn add_block_parts(
+        &mut self,
+        header: &[u8],
+        body: &[u8],
+        receipts: &[u8],
+        total_difficulty: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        let Phase::Building { starting_number } = self.phase else {
+            bail!("cannot add a block in phase {:?}", self.phase);
         };
+        let block = starting_number + self.indexes.len() as u64;
+        let number = Header::decode(&mut &header[..])
+            .map_err(|err| anyhow!("invalid header RLP: {:?}", err))?
+            .number;
+        if number != block {
+            bail!("expected block {} but received block {}", block, number);
+        }
+        self.limits
+            .check(block, header.len(), body.len(), receipts.len())?;
+        let era_size = self.chain.era_size(get_epoch(starting_number));
+        if self.indexes.len() as u64 >= era_size {
+            bail!(
+                "era starting at block {} is complete with {} blocks, block {} belongs to none",
+                starting_number,
+                era_size,
+                starting_number + self.indexes.len() as u64
+            );
+        }
 
-        let receipts = receipts.into_bytes();
+        let block_hash = keccak256(header).0;
+        let (header, body, receipts) = timed(
+            &mut self.timings.compression,
+            || -> Result<_, anyhow::Error> {
+                Ok((
+                    E2Store::compressed(E2StoreType::CompressedHeader, header)
+                        .stage("compress-header")?,
+                    E2Store::compressed(E2StoreType::CompressedBody, body)
+                        .stage("compress-body")?,
+                    E2Store::compressed(E2StoreType::CompressedReceipts, receipts)
+                        .stage("compress-receipts")?,
+                ))
+            },
+        )?;
 
-        self.writer.write_all(&receipts)?;
-        self.bytes_written += receipts.len() as u64;
+        self.indexes.push(self.bytes_written());
+        self.write(&header.into_bytes()).stage("write-header")?;
+        self.write(&body.into_bytes()).stage("write-body")?;
+        self.write(&receipts.into_bytes()).stage("write-receipts")?;
 
         let total_difficulty = encode_bigint(total_difficulty);
-        let total_difficulty = E2Store {
-            type_: E2StoreType::TotalDifficulty,
-            length: total_difficulty.len() as u32,
-            reserved: 0,
-            data: total_difficulty,
-        };
-        let total_difficulty = total_difficulty.into_bytes();
-        self.writer.write_all(&total_difficulty)?;
-        self.bytes_written += total_difficulty.len() as u64;
+        let encoded_total_difficulty: [u8; 32] = total_difficulty
+            .as_slice()
+            .try_into()
+            .context("total difficulty doesn't fit in 32 bytes")?;
+        let total_difficulty = E2Store::new(E2StoreType::TotalDifficulty, total_difficulty)?;
+        self.write(&total_difficulty.into_bytes())
+            .stage("write-total-difficulty")?;
+        self.accumulator.add(&block_hash, &encoded_total_difficulty);
+
+        Ok(())
+    }
+
+    /// Writes `entry` as is at the current position of the era. Block entries
+    /// would get no block index slot or accumulator record, so they only go
+    /// through `add_block_parts`. The Version, Accumulator and BlockIndex
+    /// entries belong to `start_era` and `finalize`, and the entries of
+    /// post-merge `.era` files have no place in an era1 file. All of these
+    /// are refused, which leaves no [`E2StoreType`] this writes yet.
+    pub fn add_raw_entry(&mut self, entry: E2Store) -> Result<(), anyhow::Error> {
+        if !matches!(self.phase, Phase::Building { .. }) {
+            bail!("cannot add an entry in phase {:?}", self.phase);
+        }
+        match entry.type_ {
+            E2StoreType::CompressedHeader
+            | E2StoreType::CompressedBody
+            | E2StoreType::CompressedReceipts
+            | E2StoreType::TotalDifficulty => bail!(
+                "{:?} entries belong to a block, add them with add_block_parts",
+                entry.type_
+            ),
+            E2StoreType::Version | E2StoreType::Accumulator | E2StoreType::BlockIndex => bail!(
+                "{:?} entries are written by the builder itself",
+                entry.type_
+            ),
+            type_ if type_.is_beacon() => {
+                bail!("{:?} entries only belong in post-merge .era files", type_)
+            }
+            _ => {}
+        }
+
+        self.write(&entry.into_bytes())?;
 
         Ok(())
     }
 
-    pub fn finalize(&mut self, header_accumulator: Vec<u8>) -> Result<(), anyhow::Error> {
-        let header_accumulator = E2Store {
-            type_: E2StoreType::Accumulator,
-            length: header_accumulator.len() as u32,
-            reserved: 0,
-            data: header_accumulator,
+    pub fn finalize(&mut self, header_accumulator: AccumulatorRoot) -> Result<(), anyhow::Error> {
+        let Phase::Building { starting_number } = self.phase else {
+            bail!("cannot finalize an era in phase {:?}", self.phase);
         };
+        if self.indexes.is_empty() {
+            bail!("cannot finalize an era without blocks");
+        }
+        // A block that failed to be written halfway leaves an index entry
+        // without an accumulator record.
+        if self.accumulator.len() != self.indexes.len() {
+            bail!(
+                "block index has {} entries but {} blocks were added",
+                self.indexes.len(),
+                self.accumulator.len()
+            );
+        }
+        let era_size = self.chain.era_size(get_epoch(starting_number));
+        if self.indexes.len() as u64 != era_size {
+            bail!(
+                "cannot finalize the era starting at block {} with {} of its {} blocks",
+                starting_number,
+                self.indexes.len(),
+                era_size
+            );
+        }
 
-        let header_accumulator = header_accumulator.into_bytes();
-        self.writer.write_all(&header_accumulator)?;
-        self.bytes_written += header_accumulator.len() as u64;
+        let accumulator_entry =
+            E2Store::new(E2StoreType::Accumulator, header_accumulator.to_vec())?;
+        self.write(&accumulator_entry.into_bytes())
+            .stage("write-accumulator")?;
 
         let count = self.indexes.len();
         let length = 16 + 8 * count;
         let mut buf = vec![0; length];
         let indexes_out = buf.as_mut_slice();
-        indexes_out[0..8].copy_from_slice(&(self.starting_number as u64).to_le_bytes());
+        indexes_out[0..8].copy_from_slice(&starting_number.to_le_bytes());
 
-        let base: i64 = self.bytes_written as i64 + 3 * 8; // skip e2store header (type, length) and start block
+        let base: i64 = self.bytes_written() as i64 + 3 * 8; // skip e2store header (type, length) and start block
         for (idx, offset) in self.indexes.iter().enumerate() {
             let relative: u64 = (*offset as i64 - base - idx as i64 * 8) as u64;
             let start_idx = 8 + idx * 8;
@@ -138,27 +358,722 @@ impl<W: Write> EraBuilder<W> {
 
         indexes_out[length - 8..].copy_from_slice(&(count as u64).to_le_bytes());
 
-        let indexes_out = E2Store {
-            type_: E2StoreType::BlockIndex,
-            length: length as u32,
-            reserved: 0,
-            data: indexes_out.to_vec(),
-        };
+        let indexes_out = E2Store::new(E2StoreType::BlockIndex, indexes_out.to_vec())?;
 
         let indexes_out = indexes_out.into_bytes();
-        self.writer.write_all(&indexes_out)?;
-        self.bytes_written += indexes_out.len() as u64;
+        self.write(&indexes_out).stage("write-block-index")?;
+        self.digest = Some(self.writer.finalize_reset().into());
+        self.phase = Phase::Finalized {
+            starting_number,
+            header_accumulator,
+        };
         Ok(())
     }
 
+    /// Accumulator root of the blocks added to the era so far, which is the
+    /// root of the whole era once all of its blocks are added.
+    pub fn accumulator_root(&self) -> AccumulatorRoot {
+        self.accumulator.root()
+    }
+
+    /// Sets the largest entries blocks may have, the [`EntryLimits::default`]
+    /// ones until then.
+    pub fn set_limits(&mut self, limits: EntryLimits) {
+        self.limits = limits;
+    }
+
+    /// Switches to a new writer, ready for the next `start_era`.
     pub fn reset(&mut self, writer: W) {
-        self.bytes_written = 0;
         self.indexes = Vec::new();
-        self.starting_number = -1;
-        self.writer = writer;
+        self.accumulator = EpochAccumulator::default();
+        self.phase = Phase::Idle;
+        self.timings = EraTimings::default();
+        self.digest = None;
+        self.writer = HashingWriter::new(CountingWriter::new(writer));
     }
 
+    /// First block of the era being built or just finalized.
+    pub fn starting_number(&self) -> Option<u64> {
+        match self.phase {
+            Phase::Idle => None,
+            Phase::Building { starting_number }
+            | Phase::Finalized {
+                starting_number, ..
+            } => Some(starting_number),
+        }
+    }
+
+    /// Accumulator root written into the era, once it is finalized.
+    pub fn header_accumulator(&self) -> Option<AccumulatorRoot> {
+        match self.phase {
+            Phase::Finalized {
+                header_accumulator, ..
+            } => Some(header_accumulator),
+            _ => None,
+        }
+    }
+
+    /// SHA-256 of the era file, once it is finalized.
+    pub fn digest(&self) -> Option<[u8; 32]> {
+        self.digest
+    }
+
+    /// Blocks added to the era being built.
     pub fn len(&self) -> usize {
         self.indexes.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    /// Whether every block of the era being built was added, which for the
+    /// era ending at the last proof-of-work block is fewer than an epoch.
+    pub fn is_complete(&self) -> bool {
+        match self.phase {
+            Phase::Building { starting_number } => {
+                self.indexes.len() as u64 == self.chain.era_size(get_epoch(starting_number))
+            }
+            _ => false,
+        }
+    }
+
+    /// Time spent on the era being built, since the last `reset`.
+    pub fn timings(&self) -> &EraTimings {
+        &self.timings
+    }
+
+    /// Lets the caller account for the stages that happen before a block
+    /// reaches the builder, such as receiving and decoding it.
+    pub fn timings_mut(&mut self) -> &mut EraTimings {
+        &mut self.timings
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        #[cfg(feature = "chaos")]
+        crate::chaos::inject_write_failure()?;
+
+        timed(&mut self.timings.write, || self.writer.write_all(bytes))
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.writer.get_ref().count()
+    }
+}
+
+impl EraBuilder<File> {
+    /// Reopens an era file that was interrupted mid-epoch so building can
+    /// continue right after `last_block`. Anything written past the entries of
+    /// `last_block` was never covered by a committed cursor and is truncated.
+    pub fn resume(
+        mut file: File,
+        chain: ChainConfig,
+        total_difficulty: Box<dyn TotalDifficultyProvider>,
+        starting_number: u64,
+        last_block: u64,
+        unknown_entries: UnknownEntryPolicy,
+    ) -> Result<Self, anyhow::Error> {
+        if last_block < starting_number {
+            bail!(
+                "cannot resume era starting at block {} from block {}",
+                starting_number,
+                last_block
+            );
+        }
+        let blocks = last_block - starting_number + 1;
+
+        let mut reader = BufReader::new(file.try_clone()?);
+        let mut offset = 0;
+        let mut indexes = Vec::new();
+        let mut accumulator = EpochAccumulator::default();
+        let mut last_header = (0, [0; 32]);
+        let mut completed_blocks = 0;
+        while completed_blocks < blocks {
+            let (type_, length) = read_entry_header(&mut reader).context(format!(
+                "era file ends after {} of {} committed blocks",
+                completed_blocks, blocks
+            ))?;
+            unknown_entries.check_era1(type_, offset)?;
+
+            // Headers and total difficulties are read back to rebuild the
+            // accumulator records of the kept blocks.
+            if type_ == E2StoreType::CompressedHeader as u16 {
+                indexes.push(offset);
+                let mut data = vec![0; length as usize];
+                reader.read_exact(&mut data)?;
+                let rlp = snap_decode(&data, MAX_HEADER_SIZE)?;
+                last_header = (
+                    Header::decode(&mut rlp.as_slice())?.number,
+                    keccak256(&rlp).0,
+                );
+            } else if type_ == E2StoreType::TotalDifficulty as u16 {
+                let mut total_difficulty = [0; 32];
+                if length as usize != total_difficulty.len() {
+                    bail!("total difficulty entry at offset {} isn't 32 bytes", offset);
+                }
+                reader.read_exact(&mut total_difficulty)?;
+                accumulator.add(&last_header.1, &total_difficulty);
+                completed_blocks += 1;
+            } else {
+                reader.seek_relative(length as i64)?;
+            }
+
+            offset += HEADER_SIZE + length as u64;
+        }
+
+        // Make sure the file and the cursor agree on the last block before
+        // throwing away the tail.
+        if last_header.0 != last_block {
+            bail!(
+                "era file ends with block {} but the cursor points at block {}",
+                last_header.0,
+                last_block
+            );
+        }
+
+        file.set_len(offset)?;
+        file.seek(SeekFrom::End(0))?;
+
+        // The kept part was written by an earlier run, so it has to be hashed
+        // once here for the digest to cover the whole file.
+        let mut hasher = Sha256::new();
+        reader.seek(SeekFrom::Start(0))?;
+        std::io::copy(&mut reader.take(offset), &mut hasher)?;
+
+        Ok(Self {
+            writer: HashingWriter::from_parts(CountingWriter::starting_at(file, offset), hasher),
+            chain,
+            total_difficulty,
+            limits: EntryLimits::default(),
+            indexes,
+            phase: Phase::Building { starting_number },
+            timings: EraTimings::default(),
+            digest: None,
+            accumulator,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::MAINNET;
+    use crate::e2store::check_block_index;
+    use crate::epoch::first_block;
+    use crate::model;
+    use crate::total_difficulty::FromHeader;
+    use std::io::Cursor;
+
+    const EMPTY_UNCLE_HASH: &str =
+        "1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347";
+    const EMPTY_TRIE_ROOT: &str =
+        "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421";
+    const GENESIS_HASH: &str = "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
+    const BLOCK_1_HASH: &str = "88e96d4537bea4d9c05d12549907b32561d3bf31f45aae734cdc119f13406cb6";
+    const LONDON_BLOCK: u64 = 12_965_000;
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        hex::decode(hex).unwrap()
+    }
+
+    /// Mainnet's genesis header.
+    fn genesis_header() -> model::Header {
+        model::Header {
+            parent_hash: vec![0; 32],
+            uncle_hash: bytes(EMPTY_UNCLE_HASH),
+            coinbase: vec![0; 20],
+            state_root: bytes("d7f8974fb5ac78d9ac099b9ad5018bedc2ce0a72dad1827a1709da30580f0544"),
+            transactions_root: bytes(EMPTY_TRIE_ROOT),
+            receipt_root: bytes(EMPTY_TRIE_ROOT),
+            logs_bloom: vec![0; 256],
+            difficulty: Some(bytes("0400000000")),
+            total_difficulty: Some(bytes("0400000000")),
+            number: 0,
+            gas_limit: 5000,
+            gas_used: 0,
+            timestamp: Some(0),
+            extra_data: bytes("11bbe8db4e347b4e8c937c1c8370e4b5ed33adb3db69cbdb7a38e1e50b1b82fa"),
+            mix_hash: vec![0; 32],
+            nonce: 0x42,
+            ..Default::default()
+        }
+    }
+
+    /// Mainnet's block 1 header.
+    fn block_1_header() -> model::Header {
+        model::Header {
+            parent_hash: bytes(GENESIS_HASH),
+            coinbase: bytes("05a56e2d52c817161883f50c441c3228cfe54d9f"),
+            state_root: bytes("d67e4d450343046425ae4271474353857ab860dbc0a1dde64b41b5cd3a532bf3"),
+            difficulty: Some(bytes("03ff800000")),
+            total_difficulty: Some(bytes("07ff800000")),
+            number: 1,
+            timestamp: Some(1438269988),
+            extra_data: b"Geth/v1.0.0/linux/go1.4.2".to_vec(),
+            mix_hash: bytes("969b900de27b6ac6a67742365dd65f55a0526c41fd18e1b16f1a1215c2e66f59"),
+            nonce: 0x539bd4979fef1ec4,
+            ..genesis_header()
+        }
+    }
+
+    /// A made up header of block `number`, with a base fee from London on.
+    fn header(number: u64) -> model::Header {
+        model::Header {
+            number,
+            difficulty: Some(bytes("0b9f5a0a1e8b4c")),
+            total_difficulty: Some((number + 1).to_be_bytes().to_vec()),
+            timestamp: Some(1_500_000_000 + number),
+            extra_data: number.to_be_bytes().to_vec(),
+            base_fee_per_gas: (number >= LONDON_BLOCK).then(|| bytes("01a13b8600")),
+            ..block_1_header()
+        }
+    }
+
+    fn block(header: model::Header, transactions: Vec<model::Transaction>) -> model::Block {
+        model::Block {
+            number: header.number,
+            hash: Vec::new(),
+            header,
+            uncles: Vec::new(),
+            transactions,
+        }
+    }
+
+    /// The empty blocks from `first` to `last`, both included.
+    fn empty_blocks(first: u64, last: u64) -> Vec<model::Block> {
+        (first..=last)
+            .map(|number| block(header(number), Vec::new()))
+            .collect()
+    }
+
+    /// A bloom with a few bits set.
+    fn bloom() -> Vec<u8> {
+        let mut bloom = vec![0; 256];
+        bloom[3] = 0x10;
+        bloom[100] = 0x02;
+        bloom[255] = 0x80;
+        bloom
+    }
+
+    /// A receipt with one log. Receipts only carry a state root before
+    /// Byzantium.
+    fn receipt(state_root: Vec<u8>, cumulative_gas_used: u64) -> model::Receipt {
+        model::Receipt {
+            state_root,
+            cumulative_gas_used,
+            logs_bloom: bloom(),
+            logs: vec![model::Log {
+                address: vec![0x66; 20],
+                topics: vec![vec![0x77; 32], vec![0x78; 32]],
+                data: vec![0, 0, 1],
+            }],
+        }
+    }
+
+    /// A transfer of one ether, signed with the EIP-155 `v` of mainnet.
+    fn transfer(nonce: u64, receipt: model::Receipt) -> model::Transaction {
+        model::Transaction {
+            tx_type: model::TxType::Legacy,
+            to: Some(vec![0x22; 20]),
+            nonce,
+            gas_price: Some(bytes("04a817c800")),
+            gas_limit: 21000,
+            value: Some(bytes("0de0b6b3a7640000")),
+            input: Vec::new(),
+            v: vec![0x25],
+            r: vec![0xaa; 32],
+            s: vec![0x3b; 32],
+            access_list: Vec::new(),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            hash: vec![nonce as u8; 32],
+            succeeded: true,
+            receipt: Some(receipt),
+        }
+    }
+
+    /// A transaction with an access list of one address and storage key.
+    fn access_list_transaction(nonce: u64, receipt: model::Receipt) -> model::Transaction {
+        model::Transaction {
+            tx_type: model::TxType::AccessList,
+            input: bytes("a9059cbb"),
+            gas_limit: 60000,
+            value: None,
+            v: vec![0x01],
+            access_list: vec![model::AccessTuple {
+                address: vec![0x33; 20],
+                storage_keys: vec![vec![0x44; 32]],
+            }],
+            ..transfer(nonce, receipt)
+        }
+    }
+
+    /// The entries of an era file, with their offsets.
+    fn entries(file: &[u8]) -> Vec<(u64, E2StoreType, Vec<u8>)> {
+        let mut reader = Cursor::new(file);
+        let mut entries = Vec::new();
+        while reader.position() < file.len() as u64 {
+            let offset = reader.position();
+            let (type_, length) = read_entry_header(&mut reader).unwrap();
+            let mut data = vec![0; length as usize];
+            reader.read_exact(&mut data).unwrap();
+            entries.push((offset, E2StoreType::try_from(type_).unwrap(), data));
+        }
+        entries
+    }
+
+    /// Decompressed header, body and receipts of block `number` in the era
+    /// starting at `starting_number`.
+    fn payloads(file: &[u8], starting_number: u64, number: u64) -> [Vec<u8>; 3] {
+        let entries = entries(file);
+        let first = 1 + 4 * (number - starting_number) as usize;
+        [
+            snap_decode(&entries[first].2, MAX_HEADER_SIZE).unwrap(),
+            snap_decode(&entries[first + 1].2, MAX_ENTRY_SIZE).unwrap(),
+            snap_decode(&entries[first + 2].2, MAX_ENTRY_SIZE).unwrap(),
+        ]
+    }
+
+    /// Keccak-256 of the header, body and receipts of block `number`, in hex.
+    fn payload_hashes(file: &[u8], starting_number: u64, number: u64) -> [String; 3] {
+        payloads(file, starting_number, number).map(|payload| hex::encode(keccak256(payload)))
+    }
+
+    /// SSZ hash tree root of the (block hash, total difficulty) records of an
+    /// era, computed apart from `EpochAccumulator`.
+    fn accumulator_root(records: &[([u8; 32], Vec<u8>)]) -> [u8; 32] {
+        let hash = |left: &[u8], right: &[u8]| -> [u8; 32] {
+            Sha256::new()
+                .chain_update(left)
+                .chain_update(right)
+                .finalize()
+                .into()
+        };
+        let mut layer: Vec<[u8; 32]> = records
+            .iter()
+            .map(|(block_hash, total_difficulty)| hash(block_hash, total_difficulty))
+            .collect();
+        let mut zero = [0; 32];
+        for _ in 0..13 {
+            if layer.len() % 2 == 1 {
+                layer.push(zero);
+            }
+            layer = layer
+                .chunks(2)
+                .map(|pair| hash(&pair[0], &pair[1]))
+                .collect();
+            zero = hash(&zero, &zero);
+        }
+        let mut length = [0; 32];
+        length[..8].copy_from_slice(&(records.len() as u64).to_le_bytes());
+        hash(&layer[0], &length)
+    }
+
+    /// A chain ending with proof-of-work block `last`, which makes the era
+    /// holding it short enough to build in a test.
+    fn chain_ending_at(last: u64) -> ChainConfig {
+        ChainConfig {
+            last_pow_block: Some(last),
+            ..MAINNET
+        }
+    }
+
+    /// Builds the era of `chain` starting at `starting_number` from `blocks`,
+    /// checks the layout, the block index and the accumulator of the file and
+    /// returns it.
+    fn build_and_check(
+        chain: ChainConfig,
+        starting_number: u64,
+        blocks: Vec<model::Block>,
+    ) -> Vec<u8> {
+        let count = blocks.len();
+        let total_difficulties: Vec<Vec<u8>> = blocks
+            .iter()
+            .map(|block| encode_bigint(block.header.total_difficulty.clone().unwrap()))
+            .collect();
+        let mut file = Vec::new();
+        let mut builder = EraBuilder::new(&mut file, chain, Box::new(FromHeader));
+        builder.start_era(starting_number).unwrap();
+        for block in blocks {
+            builder.add(block).unwrap();
+        }
+        let root = builder.accumulator_root();
+        builder.finalize(root).unwrap();
+        let digest = builder.digest();
+        assert_eq!(digest, Some(Sha256::digest(&file).into()));
+
+        let entries = entries(&file);
+        assert_eq!(&file[..8], &[0x65, 0x32, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(entries.len(), 1 + 4 * count + 2);
+        let mut records = Vec::new();
+        let mut header_offsets = Vec::new();
+        for (block, total_difficulty) in entries[1..].chunks(4).zip(total_difficulties) {
+            let types: Vec<E2StoreType> = block.iter().map(|(_, type_, _)| *type_).collect();
+            assert_eq!(
+                types,
+                [
+                    E2StoreType::CompressedHeader,
+                    E2StoreType::CompressedBody,
+                    E2StoreType::CompressedReceipts,
+                    E2StoreType::TotalDifficulty
+                ]
+            );
+            assert_eq!(block[3].2, total_difficulty);
+            let header = snap_decode(&block[0].2, MAX_HEADER_SIZE).unwrap();
+            header_offsets.push(block[0].0);
+            records.push((keccak256(header).0, total_difficulty));
+        }
+
+        let (_, accumulator_type, accumulator) = &entries[entries.len() - 2];
+        assert_eq!(*accumulator_type, E2StoreType::Accumulator);
+        assert_eq!(accumulator.as_slice(), accumulator_root(&records));
+
+        // Each offset is relative to the position right after it.
+        let (index_offset, index_type, index) = &entries[entries.len() - 1];
+        assert_eq!(*index_type, E2StoreType::BlockIndex);
+        let mut expected_index = starting_number.to_le_bytes().to_vec();
+        for (idx, header_offset) in header_offsets.iter().enumerate() {
+            let position = index_offset + HEADER_SIZE + 8 + 8 * idx as u64;
+            expected_index
+                .extend_from_slice(&(*header_offset as i64 - (position + 8) as i64).to_le_bytes());
+        }
+        expected_index.extend_from_slice(&(count as u64).to_le_bytes());
+        assert_eq!(index, &expected_index);
+        assert_eq!(
+            check_block_index(&mut Cursor::new(&file), count as u64).unwrap(),
+            starting_number
+        );
+
+        file
+    }
+
+    #[test]
+    fn genesis_era() {
+        let file = build_and_check(
+            chain_ending_at(1),
+            0,
+            vec![
+                block(genesis_header(), Vec::new()),
+                block(block_1_header(), Vec::new()),
+            ],
+        );
+
+        assert_eq!(payload_hashes(&file, 0, 0)[0], GENESIS_HASH);
+        assert_eq!(payload_hashes(&file, 0, 1)[0], BLOCK_1_HASH);
+        // Neither block has transactions or uncles.
+        assert_eq!(payloads(&file, 0, 1)[1..], [bytes("c2c0c0"), bytes("c0")]);
+        // The digest covers every byte of the file, snappy framing included.
+        assert_eq!(
+            hex::encode(Sha256::digest(&file)),
+            "3d85c7cbd6903bb11c0f3385d4dff672a8148b231d8440718d3a22c4a0a5475a"
+        );
+    }
+
+    #[test]
+    fn byzantium_boundary_era() {
+        let byzantium = MAINNET.byzantium_block;
+        let starting_number = first_block(get_epoch(byzantium));
+        let mut blocks = empty_blocks(starting_number, byzantium - 2);
+        // Receipts carry a state root before Byzantium and a status from it
+        // on, which is false for the failed transfer.
+        blocks.push(block(
+            header(byzantium - 1),
+            vec![transfer(0, receipt(vec![0x11; 32], 21000))],
+        ));
+        let mut failed = transfer(1, receipt(Vec::new(), 21000));
+        failed.succeeded = false;
+        let creation = model::Transaction {
+            to: None,
+            input: bytes("6080604052"),
+            gas_limit: 100_000,
+            value: None,
+            v: vec![0x1c],
+            ..transfer(2, receipt(Vec::new(), 74000))
+        };
+        let mut byzantium_block = block(header(byzantium), vec![failed, creation]);
+        byzantium_block.uncles.push(model::Header {
+            coinbase: vec![0x99; 20],
+            ..header(byzantium - 1)
+        });
+        blocks.push(byzantium_block);
+        blocks.extend(empty_blocks(byzantium + 1, byzantium + 1));
+        let file = build_and_check(chain_ending_at(byzantium + 1), starting_number, blocks);
+
+        assert_eq!(
+            payload_hashes(&file, starting_number, byzantium - 1),
+            [
+                "1eaf3ee1faaaa9b8ab2a7fd49fbb3375b7765e0b9025befc5855bcf84403d185",
+                "de8a32c0d3ac3e1e88e2de45caba0f7f033aab6a435f0dd633e59f9e32d07475",
+                "9d2437c3c956c05008e7925848b50dd54bf7953980097fe088ab07571db64acc"
+            ]
+        );
+        assert_eq!(
+            payload_hashes(&file, starting_number, byzantium),
+            [
+                "3e0caa962ff93c193dc2709938d092b511177b8cbaf5ce2f4e1493604910f58e",
+                "0b9af0ad673a459344db700e7372cfa8e170548498a1344fbab0141c8300664e",
+                "58408661e737c17b6414c1880190ac7fdc0fc48ec365f8f798c4a16da1880cab"
+            ]
+        );
+        assert_eq!(
+            hex::encode(Sha256::digest(&file)),
+            "436e974c89be6e63cdc3f9b0632f9f35f9ee43284758c50df58a29351062d20b"
+        );
+    }
+
+    #[test]
+    fn typed_transaction_era() {
+        let starting_number = first_block(get_epoch(LONDON_BLOCK));
+        let mut blocks = empty_blocks(starting_number, LONDON_BLOCK - 2);
+        blocks.push(block(
+            header(LONDON_BLOCK - 1),
+            vec![
+                transfer(0, receipt(Vec::new(), 21000)),
+                access_list_transaction(1, receipt(Vec::new(), 81000)),
+            ],
+        ));
+        let dynamic_fee = model::Transaction {
+            tx_type: model::TxType::DynamicFee,
+            gas_price: None,
+            max_fee_per_gas: Some(bytes("0ba43b7400")),
+            max_priority_fee_per_gas: Some(bytes("77359400")),
+            access_list: Vec::new(),
+            ..access_list_transaction(2, receipt(Vec::new(), 60000))
+        };
+        let mut failed = access_list_transaction(3, receipt(Vec::new(), 120000));
+        failed.succeeded = false;
+        blocks.push(block(header(LONDON_BLOCK), vec![dynamic_fee, failed]));
+        let file = build_and_check(chain_ending_at(LONDON_BLOCK), starting_number, blocks);
+
+        assert_eq!(
+            payload_hashes(&file, starting_number, LONDON_BLOCK - 1),
+            [
+                "583ce827e6665965b42c220922f6a0d3d3f00db95704a33cbebfcc36a5b2877c",
+                "db460fb9157b0b60bf7f00ebbc4b34fc0ac46cdd9fe288c902e769fa7c731ca5",
+                "871479c2c15cd27341939658943cb6cf719439d324411bd1c9b029fe672082ca"
+            ]
+        );
+        assert_eq!(
+            payload_hashes(&file, starting_number, LONDON_BLOCK),
+            [
+                "eab516a046319b3f695967ef2ad5f38e182e82faff0c2050316ab566944223b9",
+                "7d9fccc71a90e71daf0a839c9e92e4d18619fed48ed9fd60dd57c10ae11a5d39",
+                "fc3ac8a87329e93ea82956d5435f53f156a9cba491be03f10407ef97b7e75c0d"
+            ]
+        );
+        assert_eq!(
+            hex::encode(Sha256::digest(&file)),
+            "534e461f292b492c67f0ae50fc25d8909df3f82f6b3c649bdb8bc6c8bc9c4e7b"
+        );
+    }
+
+    #[test]
+    fn finalize_refuses_a_short_era() {
+        let mut builder = EraBuilder::new(Vec::new(), MAINNET, Box::new(FromHeader));
+        builder.start_era(0).unwrap();
+        builder.add(block(genesis_header(), Vec::new())).unwrap();
+        assert!(!builder.is_complete());
+        let err = builder.finalize([0; 32]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot finalize the era starting at block 0 with 1 of its 8192 blocks"
+        );
+    }
+
+    #[test]
+    fn add_refuses_blocks_past_the_era() {
+        let mut builder = EraBuilder::new(Vec::new(), chain_ending_at(0), Box::new(FromHeader));
+        builder.start_era(0).unwrap();
+        builder.add(block(genesis_header(), Vec::new())).unwrap();
+        assert!(builder.is_complete());
+        assert!(builder.add(block(block_1_header(), Vec::new())).is_err());
+    }
+
+    #[test]
+    fn add_block_parts_refuses_blocks_out_of_order() {
+        let mut builder = EraBuilder::new(Vec::new(), MAINNET, Box::new(FromHeader));
+        builder.start_era(0).unwrap();
+        let block_1 = alloy_rlp::encode(block_1_header());
+        let err = builder
+            .add_block_parts(&block_1, &[0xc2, 0xc0, 0xc0], &[0xc0], vec![4])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "expected block 0 but received block 1");
+        assert!(builder
+            .add_block_parts(&[0xc0], &[0xc2, 0xc0, 0xc0], &[0xc0], vec![4])
+            .is_err());
+        assert!(builder.is_empty());
+    }
+
+    #[test]
+    fn add_raw_entry_refuses_entries_with_a_place_in_the_era() {
+        let mut file = Vec::new();
+        let mut builder = EraBuilder::new(&mut file, MAINNET, Box::new(FromHeader));
+        builder.start_era(0).unwrap();
+        builder.add(block(genesis_header(), Vec::new())).unwrap();
+        let written = builder.bytes_written();
+        for type_ in [
+            E2StoreType::CompressedHeader,
+            E2StoreType::CompressedBody,
+            E2StoreType::CompressedReceipts,
+            E2StoreType::TotalDifficulty,
+            E2StoreType::Version,
+            E2StoreType::Accumulator,
+            E2StoreType::BlockIndex,
+            E2StoreType::CompressedSignedBeaconBlock,
+            E2StoreType::CompressedBeaconState,
+            E2StoreType::SlotIndex,
+        ] {
+            let entry = E2Store::new(type_, vec![0; 32]).unwrap();
+            assert!(builder.add_raw_entry(entry).is_err(), "{:?}", type_);
+        }
+        assert_eq!(builder.bytes_written(), written);
+        assert_eq!(builder.len(), 1);
+    }
+
+    /// Fails every write once `limit` bytes were written.
+    struct FailingWriter {
+        written: usize,
+        limit: usize,
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.written + buf.len() > self.limit {
+                return Err(std::io::Error::other("disk full"));
+            }
+            self.written += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finalize_refuses_an_index_without_accumulator_records() {
+        let mut genesis_only = Vec::new();
+        let mut builder = EraBuilder::new(&mut genesis_only, MAINNET, Box::new(FromHeader));
+        builder.start_era(0).unwrap();
+        builder.add(block(genesis_header(), Vec::new())).unwrap();
+
+        let mut builder = EraBuilder::new(
+            FailingWriter {
+                written: 0,
+                limit: genesis_only.len() + 100,
+            },
+            chain_ending_at(1),
+            Box::new(FromHeader),
+        );
+        builder.start_era(0).unwrap();
+        builder.add(block(genesis_header(), Vec::new())).unwrap();
+        // Block 1 gets its index entry and its header, then fails before its
+        // total difficulty is written.
+        assert!(builder.add(block(block_1_header(), Vec::new())).is_err());
+        let err = builder.finalize([0; 32]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "block index has 2 entries but 1 blocks were added"
+        );
+    }
 }
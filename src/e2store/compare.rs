@@ -0,0 +1,316 @@
+//! Compares two era1 files of the same epoch entry by entry, typically one
+//! written by geth's `era` tool and one written by the sink, to find where
+//! the encodings diverge.
+
+use crate::compression::{snap_decode, MAX_ENTRY_SIZE};
+use crate::e2store::builder::MAX_HEADER_SIZE;
+use crate::e2store::{read_entry_header, E2StoreType};
+use crate::encryption::{EncryptionKey, EraReader};
+use reth_primitives::Header;
+use reth_rlp::Decodable;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::io::Read;
+use std::path::Path;
+
+/// How an entry of the second file differs from the same entry of the first.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// The compressed bytes differ but decompress to the same content, so
+    /// only the snappy encoders disagree.
+    Compression,
+    /// The content differs.
+    Content,
+    /// The entries have different types, so the files are out of step from
+    /// here on and the comparison stops.
+    Type { expected: u16, actual: u16 },
+    /// One file has no entry here, the other file has `extra` more.
+    Length { extra: u64, in_expected: bool },
+}
+
+#[derive(Debug)]
+pub struct EntryDifference {
+    /// Position of the entry in the files, from 0.
+    pub index: u64,
+    pub type_: u16,
+    /// Block the entry belongs to, for block entries.
+    pub block: Option<u64>,
+    pub divergence: Divergence,
+}
+
+/// Outcome of [`compare_era_files`].
+#[derive(Debug, Default)]
+pub struct EraComparison {
+    /// Whether the files are equal byte for byte.
+    pub byte_equal: bool,
+    pub entries: u64,
+    /// The differing entries, up to the limit passed to
+    /// [`compare_era_files`].
+    pub differences: Vec<EntryDifference>,
+    /// Number of differing entries by entry type name, including those past
+    /// the limit.
+    pub by_type: BTreeMap<String, u64>,
+}
+
+impl EraComparison {
+    pub fn identical(&self) -> bool {
+        self.byte_equal
+    }
+
+    fn record(&mut self, difference: EntryDifference, limit: usize) {
+        *self.by_type.entry(type_name(difference.type_)).or_default() += 1;
+        if self.differences.len() < limit {
+            self.differences.push(difference);
+        }
+    }
+}
+
+impl Display for EntryDifference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {} ({}", self.index, type_name(self.type_))?;
+        if let Some(block) = self.block {
+            write!(f, " of block {}", block)?;
+        }
+        write!(f, "): ")?;
+        match &self.divergence {
+            Divergence::Compression => write!(f, "compressed differently, same content"),
+            Divergence::Content => write!(f, "content differs"),
+            Divergence::Type { expected, actual } => write!(
+                f,
+                "expected {}, found {}, the files are out of step from here on",
+                type_name(*expected),
+                type_name(*actual)
+            ),
+            Divergence::Length {
+                extra,
+                in_expected: true,
+            } => write!(
+                f,
+                "the second file ends here, the first has {} more entries",
+                extra
+            ),
+            Divergence::Length {
+                extra,
+                in_expected: false,
+            } => write!(
+                f,
+                "the first file ends here, the second has {} more entries",
+                extra
+            ),
+        }
+    }
+}
+
+/// Checks whether the era1 files at `expected` and `actual` are equal byte
+/// for byte and compares their entries in order, keeping the first `limit`
+/// differences. Both files are decrypted with `key` first if they are
+/// encrypted.
+///
+/// Entries are read in step, so a missing or extra entry shows up as a type
+/// difference and ends the comparison.
+pub fn compare_era_files(
+    expected: &Path,
+    actual: &Path,
+    key: Option<&EncryptionKey>,
+    limit: usize,
+) -> Result<EraComparison, anyhow::Error> {
+    let mut comparison = EraComparison {
+        byte_equal: same_bytes(expected, actual, key)?,
+        ..Default::default()
+    };
+    let mut expected = EntryReader::open(expected, key)?;
+    let mut actual = EntryReader::open(actual, key)?;
+    let mut first_block = None;
+    let mut blocks = 0;
+    loop {
+        let (expected_entry, actual_entry) = (expected.next()?, actual.next()?);
+        let index = comparison.entries;
+        let ((expected_type, expected_data), (actual_type, actual_data)) =
+            match (expected_entry, actual_entry) {
+                (Some(expected_entry), Some(actual_entry)) => (expected_entry, actual_entry),
+                (None, None) => break,
+                (Some((type_, _)), None) => {
+                    let extra = 1 + expected.count_rest()?;
+                    comparison.record(
+                        EntryDifference {
+                            index,
+                            type_,
+                            block: None,
+                            divergence: Divergence::Length {
+                                extra,
+                                in_expected: true,
+                            },
+                        },
+                        limit,
+                    );
+                    break;
+                }
+                (None, Some((type_, _))) => {
+                    let extra = 1 + actual.count_rest()?;
+                    comparison.record(
+                        EntryDifference {
+                            index,
+                            type_,
+                            block: None,
+                            divergence: Divergence::Length {
+                                extra,
+                                in_expected: false,
+                            },
+                        },
+                        limit,
+                    );
+                    break;
+                }
+            };
+        comparison.entries += 1;
+
+        if expected_type == E2StoreType::CompressedHeader as u16 {
+            if first_block.is_none() {
+                first_block = Some(header_number(&expected_data)?);
+            }
+            blocks += 1;
+        }
+        let block = match E2StoreType::try_from(expected_type) {
+            Ok(
+                E2StoreType::CompressedHeader
+                | E2StoreType::CompressedBody
+                | E2StoreType::CompressedReceipts
+                | E2StoreType::TotalDifficulty,
+            ) => first_block.map(|first| first + blocks - 1),
+            _ => None,
+        };
+
+        if expected_type != actual_type {
+            comparison.record(
+                EntryDifference {
+                    index,
+                    type_: expected_type,
+                    block,
+                    divergence: Divergence::Type {
+                        expected: expected_type,
+                        actual: actual_type,
+                    },
+                },
+                limit,
+            );
+            break;
+        }
+        if expected_data == actual_data {
+            continue;
+        }
+        let max_size = match E2StoreType::try_from(expected_type) {
+            Ok(E2StoreType::CompressedHeader) => Some(MAX_HEADER_SIZE),
+            Ok(E2StoreType::CompressedBody | E2StoreType::CompressedReceipts) => {
+                Some(MAX_ENTRY_SIZE)
+            }
+            _ => None,
+        };
+        let same_content = match max_size {
+            Some(max_size) => match (
+                snap_decode(&expected_data, max_size),
+                snap_decode(&actual_data, max_size),
+            ) {
+                (Ok(expected), Ok(actual)) => expected == actual,
+                _ => false,
+            },
+            None => false,
+        };
+        comparison.record(
+            EntryDifference {
+                index,
+                type_: expected_type,
+                block,
+                divergence: if same_content {
+                    Divergence::Compression
+                } else {
+                    Divergence::Content
+                },
+            },
+            limit,
+        );
+    }
+
+    Ok(comparison)
+}
+
+fn same_bytes(
+    expected: &Path,
+    actual: &Path,
+    key: Option<&EncryptionKey>,
+) -> Result<bool, anyhow::Error> {
+    let mut expected = EraReader::open(expected, key)?;
+    let mut actual = EraReader::open(actual, key)?;
+    let mut expected_buffer = vec![0; 1 << 16];
+    let mut actual_buffer = vec![0; 1 << 16];
+    loop {
+        let read = read_full(&mut expected, &mut expected_buffer)?;
+        if read_full(&mut actual, &mut actual_buffer)? != read
+            || expected_buffer[..read] != actual_buffer[..read]
+        {
+            return Ok(false);
+        }
+        if read < expected_buffer.len() {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fills `buffer` unless the end of `reader` comes first, returning the
+/// number of bytes read.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
+
+fn type_name(type_: u16) -> String {
+    match E2StoreType::try_from(type_) {
+        Ok(type_) => format!("{:?}", type_),
+        Err(_) => format!("{:#06x}", type_),
+    }
+}
+
+fn header_number(data: &[u8]) -> Result<u64, anyhow::Error> {
+    let header = snap_decode(data, MAX_HEADER_SIZE)?;
+    Ok(Header::decode(&mut header.as_slice())
+        .map_err(|err| anyhow::anyhow!("invalid header RLP: {:?}", err))?
+        .number)
+}
+
+/// Reads the entries of an era file one at a time.
+struct EntryReader {
+    reader: EraReader,
+}
+
+impl EntryReader {
+    fn open(path: &Path, key: Option<&EncryptionKey>) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            reader: EraReader::open(path, key)?,
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<(u16, Vec<u8>)>, anyhow::Error> {
+        let (type_, length) = match read_entry_header(&mut self.reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut data = vec![0; length as usize];
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some((type_, data)))
+    }
+
+    fn count_rest(&mut self) -> Result<u64, anyhow::Error> {
+        let mut count = 0;
+        while self.next()?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
@@ -1,17 +1,26 @@
-pub(crate) mod builder;
-mod utils;
+pub mod beacon;
+pub mod block;
+pub mod builder;
+pub mod compare;
+pub mod utils;
+pub mod verify;
 
-use crate::pb::acme::verifiable_block::v1::{BlockHeader, TransactionReceipt};
-use crate::snap::snap_encode;
+use crate::compression::snap_encode;
+use crate::model;
+use byteorder::{LittleEndian, ReadBytesExt};
 use bytes::BytesMut;
 use reth_primitives::{BlockBody as RethBlockBody, Header, ReceiptWithBloom};
 use reth_rlp::Encodable as RethEncodable;
-use rlp::{Encodable, RlpStream};
+use std::io::{Read, Seek, SeekFrom};
+use std::str::FromStr;
 
-const BYZANTIUM_HARDFORK: u64 = 4_370_000;
+/// Size of the type, length and reserved fields preceding every entry.
+pub const HEADER_SIZE: u64 = 8;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum E2StoreType {
+    CompressedSignedBeaconBlock = 0x01,
+    CompressedBeaconState = 0x02,
     CompressedHeader = 0x03,
     CompressedBody = 0x04,
     CompressedReceipts = 0x05,
@@ -19,6 +28,98 @@ pub enum E2StoreType {
     Accumulator = 0x07,
     Version = 0x3265,
     BlockIndex = 0x3266,
+    SlotIndex = 0x3269,
+}
+
+impl TryFrom<u16> for E2StoreType {
+    type Error = anyhow::Error;
+
+    fn try_from(type_: u16) -> Result<Self, Self::Error> {
+        match type_ {
+            0x01 => Ok(E2StoreType::CompressedSignedBeaconBlock),
+            0x02 => Ok(E2StoreType::CompressedBeaconState),
+            0x03 => Ok(E2StoreType::CompressedHeader),
+            0x04 => Ok(E2StoreType::CompressedBody),
+            0x05 => Ok(E2StoreType::CompressedReceipts),
+            0x06 => Ok(E2StoreType::TotalDifficulty),
+            0x07 => Ok(E2StoreType::Accumulator),
+            0x3265 => Ok(E2StoreType::Version),
+            0x3266 => Ok(E2StoreType::BlockIndex),
+            0x3269 => Ok(E2StoreType::SlotIndex),
+            _ => Err(anyhow::anyhow!("unknown e2store entry type {:#06x}", type_)),
+        }
+    }
+}
+
+impl E2StoreType {
+    /// Whether entries of this type belong in post-merge `.era` files rather
+    /// than in era1 files.
+    pub fn is_beacon(self) -> bool {
+        matches!(
+            self,
+            E2StoreType::CompressedSignedBeaconBlock
+                | E2StoreType::CompressedBeaconState
+                | E2StoreType::SlotIndex
+        )
+    }
+}
+
+/// What readers do with entry types they don't know. The e2store spec lets
+/// other tools add their own entries, which readers are expected to skip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownEntryPolicy {
+    /// Skip the entry and print a warning.
+    Warn,
+    /// Fail on the entry, for checking files that should only contain the
+    /// entries of their format.
+    Strict,
+}
+
+impl UnknownEntryPolicy {
+    /// Applies the policy to an entry of `type_` found at `offset`. Known
+    /// types always pass.
+    pub fn check(self, type_: u16, offset: u64) -> Result<(), anyhow::Error> {
+        match E2StoreType::try_from(type_) {
+            Ok(_) => Ok(()),
+            Err(err) if self == UnknownEntryPolicy::Strict => {
+                Err(err.context(format!("entry at offset {}", offset)))
+            }
+            Err(err) => {
+                println!("Warning: skipping entry at offset {}: {}", offset, err);
+                Ok(())
+            }
+        }
+    }
+
+    /// Like [`check`](Self::check), for readers of era1 files. Entries of
+    /// post-merge `.era` files are known, but have no place in an era1 file,
+    /// so they fail under either policy.
+    pub fn check_era1(self, type_: u16, offset: u64) -> Result<(), anyhow::Error> {
+        self.check(type_, offset)?;
+        match E2StoreType::try_from(type_) {
+            Ok(type_) if type_.is_beacon() => Err(anyhow::anyhow!(
+                "entry at offset {} is a {:?} entry, which only belongs in post-merge .era files",
+                offset,
+                type_
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for UnknownEntryPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(UnknownEntryPolicy::Warn),
+            "strict" => Ok(UnknownEntryPolicy::Strict),
+            _ => Err(anyhow::anyhow!(
+                "unknown entry policy '{}', expected 'warn' or 'strict'",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -40,78 +141,134 @@ impl E2Store {
     }
 }
 
-impl TryFrom<BlockHeader> for E2Store {
-    type Error = anyhow::Error;
+/// Reads the type and length of the next entry, leaving `reader` positioned
+/// at the start of the entry data.
+pub fn read_entry_header<R: Read>(reader: &mut R) -> Result<(u16, u32), std::io::Error> {
+    let type_ = reader.read_u16::<LittleEndian>()?;
+    let length = reader.read_u32::<LittleEndian>()?;
+    let _reserved = reader.read_u16::<LittleEndian>()?;
 
-    fn try_from(block_header: BlockHeader) -> Result<Self, Self::Error> {
-        // block_header.
-        let bytes = block_header.rlp_bytes();
+    Ok((type_, length))
+}
 
-        // Snappy compression
-        let data = snap_encode(bytes.as_ref())?;
+/// Checks that an era file ends with a block index of `blocks` entries, each
+/// pointing at a header entry, and returns the starting block number.
+pub fn check_block_index<R: Read + Seek>(
+    reader: &mut R,
+    blocks: u64,
+) -> Result<u64, anyhow::Error> {
+    reader.seek(SeekFrom::End(-8))?;
+    let count = reader.read_u64::<LittleEndian>()?;
+    if count != blocks {
+        anyhow::bail!("block index covers {} blocks, expected {}", count, blocks);
+    }
 
-        Ok(E2Store {
-            type_: E2StoreType::CompressedHeader,
-            length: data.len() as u32,
-            reserved: 0,
-            data,
-        })
+    let index_position = reader.seek(SeekFrom::End(-((HEADER_SIZE + 16 + 8 * count) as i64)))?;
+    let (type_, _) = read_entry_header(reader)?;
+    if type_ != E2StoreType::BlockIndex as u16 {
+        anyhow::bail!("era file doesn't end with a block index");
     }
-}
+    let starting_number = reader.read_u64::<LittleEndian>()?;
+    let offsets = (0..count)
+        .map(|_| reader.read_i64::<LittleEndian>())
+        .collect::<Result<Vec<i64>, std::io::Error>>()?;
 
-impl TryFrom<Header> for E2Store {
-    type Error = anyhow::Error;
+    // Offsets are stored relative to the start of the index data plus the
+    // position of the offset, mirroring `EraBuilder::finalize`.
+    let base = index_position as i64 + 3 * 8;
+    for (idx, relative) in offsets.into_iter().enumerate() {
+        let offset = relative + base + idx as i64 * 8;
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        let (type_, _) = read_entry_header(reader)?;
+        if type_ != E2StoreType::CompressedHeader as u16 {
+            anyhow::bail!(
+                "block index entry {} points at an entry of type {:#06x}, not a header",
+                idx,
+                type_
+            );
+        }
+    }
 
-    fn try_from(header: Header) -> Result<Self, Self::Error> {
-        let mut bytes = BytesMut::new();
-        header.encode(&mut bytes);
+    Ok(starting_number)
+}
 
-        let data = snap_encode(&bytes)?;
+impl E2Store {
+    /// An entry of type `type_` holding `data` as is. Fails if `data` is too
+    /// long for the `u32` length of the entry header.
+    pub fn new(type_: E2StoreType, data: Vec<u8>) -> Result<Self, anyhow::Error> {
+        let length = u32::try_from(data.len()).map_err(|_| {
+            anyhow::anyhow!(
+                "{:?} entry is {} bytes, above the e2store limit of {} bytes",
+                type_,
+                data.len(),
+                u32::MAX
+            )
+        })?;
 
         Ok(E2Store {
-            type_: E2StoreType::CompressedHeader,
-            length: data.len() as u32,
+            type_,
+            length,
             reserved: 0,
             data,
         })
     }
+
+    /// Snappy compresses an RLP payload into an entry of type `type_`.
+    pub fn compressed(type_: E2StoreType, rlp: &[u8]) -> Result<Self, anyhow::Error> {
+        E2Store::new(type_, snap_encode(rlp)?)
+    }
 }
 
-impl TryFrom<RethBlockBody> for E2Store {
-    type Error = anyhow::Error;
+/// RLP encodes any of the reth types stored in era files.
+pub fn rlp_encode<E: RethEncodable + ?Sized>(value: &E) -> BytesMut {
+    let mut bytes = BytesMut::new();
+    value.encode(&mut bytes);
+    bytes
+}
 
-    fn try_from(block_body: RethBlockBody) -> Result<Self, Self::Error> {
-        let mut bytes = BytesMut::new();
-        block_body.encode(&mut bytes);
+/// RLP encodes pre-Byzantium receipts, which carry a state root instead of a
+/// status and aren't supported by the reth encoder.
+pub fn rlp_encode_legacy_receipts(receipts: &[&model::Receipt]) -> BytesMut {
+    let mut bytes = BytesMut::new();
+    alloy_rlp::encode_list::<_, model::Receipt>(receipts, &mut bytes);
+    bytes
+}
 
-        let data = snap_encode(&bytes)?;
+impl TryFrom<model::Header> for E2Store {
+    type Error = anyhow::Error;
 
-        Ok(E2Store {
-            type_: E2StoreType::CompressedBody,
-            length: data.len() as u32,
-            reserved: 0,
-            data,
-        })
+    fn try_from(block_header: model::Header) -> Result<Self, Self::Error> {
+        E2Store::compressed(
+            E2StoreType::CompressedHeader,
+            &alloy_rlp::encode(&block_header),
+        )
     }
 }
 
-impl TryFrom<Vec<TransactionReceipt>> for E2Store {
+impl TryFrom<Header> for E2Store {
     type Error = anyhow::Error;
 
-    fn try_from(receipts: Vec<TransactionReceipt>) -> Result<Self, Self::Error> {
-        let mut rlp_encoded = RlpStream::new();
-        rlp_encoded.append_list(receipts.as_slice());
+    fn try_from(header: Header) -> Result<Self, Self::Error> {
+        E2Store::compressed(E2StoreType::CompressedHeader, &rlp_encode(&header))
+    }
+}
 
-        let bytes = rlp_encoded.out();
+impl TryFrom<RethBlockBody> for E2Store {
+    type Error = anyhow::Error;
 
-        let data = snap_encode(bytes.as_ref())?;
+    fn try_from(block_body: RethBlockBody) -> Result<Self, Self::Error> {
+        E2Store::compressed(E2StoreType::CompressedBody, &rlp_encode(&block_body))
+    }
+}
 
-        Ok(E2Store {
-            type_: E2StoreType::CompressedReceipts,
-            length: data.len() as u32,
-            reserved: 0,
-            data,
-        })
+impl TryFrom<Vec<model::Receipt>> for E2Store {
+    type Error = anyhow::Error;
+
+    fn try_from(receipts: Vec<model::Receipt>) -> Result<Self, Self::Error> {
+        E2Store::compressed(
+            E2StoreType::CompressedReceipts,
+            &rlp_encode_legacy_receipts(&receipts.iter().collect::<Vec<_>>()),
+        )
     }
 }
 
@@ -119,15 +276,6 @@ impl TryFrom<Vec<ReceiptWithBloom>> for E2Store {
     type Error = anyhow::Error;
 
     fn try_from(receipts: Vec<ReceiptWithBloom>) -> Result<Self, Self::Error> {
-        let mut bytes = BytesMut::new();
-        receipts.encode(&mut bytes);
-        let data = snap_encode(bytes.as_ref())?;
-
-        Ok(E2Store {
-            type_: E2StoreType::CompressedReceipts,
-            length: data.len() as u32,
-            reserved: 0,
-            data,
-        })
+        E2Store::compressed(E2StoreType::CompressedReceipts, &rlp_encode(&receipts))
     }
 }
@@ -1,9 +1,9 @@
-use crate::pb::acme::verifiable_block::v1::BigInt;
 use decoder::transactions::error::TransactionError;
 use reth_primitives::U128;
 
-pub fn encode_bigint(big_int: BigInt) -> Vec<u8> {
-    let mut bytes = big_int.bytes;
+/// Encodes a big endian integer as the 32 byte little endian value era files
+/// store.
+pub fn encode_bigint(mut bytes: Vec<u8>) -> Vec<u8> {
     bytes.reverse();
     if bytes.len() < 32 {
         bytes.append(&mut vec![0; 32 - bytes.len()]);
@@ -12,13 +12,8 @@ pub fn encode_bigint(big_int: BigInt) -> Vec<u8> {
     bytes
 }
 
-impl TryFrom<BigInt> for u128 {
-    type Error = TransactionError;
-
-    fn try_from(value: BigInt) -> Result<Self, Self::Error> {
-        let slice = value.bytes.as_slice();
-        let n = U128::try_from_be_slice(slice)
-            .ok_or(TransactionError::InvalidBigInt(hex::encode(slice)))?;
-        Ok(u128::from_le_bytes(n.to_le_bytes()))
-    }
+pub fn bigint_to_u128(slice: &[u8]) -> Result<u128, TransactionError> {
+    let n = U128::try_from_be_slice(slice)
+        .ok_or(TransactionError::InvalidBigInt(hex::encode(slice)))?;
+    Ok(u128::from_le_bytes(n.to_le_bytes()))
 }
@@ -0,0 +1,273 @@
+//! Reads an era1 file back and checks it against the era1 spec.
+
+use crate::compression::budget::DecompressionBudget;
+use crate::compression::MAX_ENTRY_SIZE;
+use crate::e2store::builder::MAX_HEADER_SIZE;
+use crate::e2store::{read_entry_header, E2StoreType, UnknownEntryPolicy, HEADER_SIZE};
+use crate::encryption::{EncryptionKey, EraReader};
+use crate::header_accumulator::EpochAccumulator;
+use crate::parallel::map_in_parallel;
+use anyhow::Context;
+use byteorder::{LittleEndian, ReadBytesExt};
+use reth_primitives::{keccak256, Header};
+use reth_rlp::Decodable;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Outcome of [`verify_era_file`].
+#[derive(Debug, Default)]
+pub struct EraFileReport {
+    pub starting_number: u64,
+    pub blocks: u64,
+    /// Every way the file breaks the spec, empty if it is valid.
+    pub problems: Vec<String>,
+}
+
+impl EraFileReport {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Entries of one block, in the order they must appear.
+const BLOCK_ENTRIES: [E2StoreType; 4] = [
+    E2StoreType::CompressedHeader,
+    E2StoreType::CompressedBody,
+    E2StoreType::CompressedReceipts,
+    E2StoreType::TotalDifficulty,
+];
+
+/// Checks that the era1 file at `path` holds a version entry, then complete
+/// blocks with consecutive numbers whose headers chain through their parent
+/// hashes and whose total difficulty keeps growing, then an accumulator
+/// matching the recomputed one, then a block index pointing at every header.
+///
+/// Every entry is decompressed and headers are decoded, so this catches
+/// corrupted data as well as structural mistakes. An error is only returned
+/// when the file can't be read at all. Encrypted files are decrypted with
+/// `key` first.
+pub fn verify_era_file(
+    path: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+    budget: &DecompressionBudget,
+) -> Result<EraFileReport, anyhow::Error> {
+    let mut reader = EraReader::open(path, key)?;
+    let mut report = EraFileReport::default();
+    let mut accumulator = EpochAccumulator::default();
+    let mut header_offsets = Vec::new();
+    let mut block_hash = [0; 32];
+    let mut parent_hash = None;
+    let mut last_total_difficulty = None;
+    let mut stored_root = None;
+    let mut entries = 0;
+    let mut offset = 0;
+    loop {
+        let entry_offset = offset;
+        let (type_, length) = match read_entry_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        unknown_entries.check_era1(type_, entry_offset)?;
+        offset += HEADER_SIZE + length as u64;
+        let mut data = vec![0; length as usize];
+        reader
+            .read_exact(&mut data)
+            .context(format!("read entry at offset {}", entry_offset))?;
+        let Ok(type_) = E2StoreType::try_from(type_) else {
+            continue;
+        };
+
+        let block = header_offsets.len() as u64;
+        let expected = if entries == 0 {
+            E2StoreType::Version
+        } else if stored_root.is_some() {
+            E2StoreType::BlockIndex
+        } else {
+            let position = (entries - 1) % BLOCK_ENTRIES.len();
+            if position == 0 && !header_offsets.is_empty() && type_ == E2StoreType::Accumulator {
+                E2StoreType::Accumulator
+            } else {
+                BLOCK_ENTRIES[position]
+            }
+        };
+        entries += 1;
+        if type_ != expected {
+            report.problems.push(format!(
+                "entry at offset {} is a {:?} entry, expected {:?}",
+                entry_offset, type_, expected
+            ));
+            return Ok(report);
+        }
+
+        match expected {
+            E2StoreType::Version => {}
+            E2StoreType::CompressedHeader => {
+                header_offsets.push(entry_offset);
+                let rlp = match budget.snap_decode(&data, MAX_HEADER_SIZE) {
+                    Ok(rlp) => rlp,
+                    Err(err) => {
+                        report.problems.push(format!("header {}: {:#}", block, err));
+                        return Ok(report);
+                    }
+                };
+                block_hash = keccak256(&*rlp).0;
+                let header = match Header::decode(&mut &*rlp) {
+                    Ok(header) => header,
+                    Err(err) => {
+                        report
+                            .problems
+                            .push(format!("header {}: invalid RLP: {:?}", block, err));
+                        return Ok(report);
+                    }
+                };
+
+                if block == 0 {
+                    report.starting_number = header.number;
+                } else if header.number != report.starting_number + block {
+                    report.problems.push(format!(
+                        "header {} has number {}, expected {}",
+                        block,
+                        header.number,
+                        report.starting_number + block
+                    ));
+                }
+                if let Some(parent_hash) = parent_hash {
+                    if header.parent_hash.0 != parent_hash {
+                        report.problems.push(format!(
+                            "block {} doesn't point at the hash of block {}",
+                            header.number,
+                            header.number.saturating_sub(1)
+                        ));
+                    }
+                }
+                parent_hash = Some(block_hash);
+            }
+            E2StoreType::CompressedBody | E2StoreType::CompressedReceipts => {
+                if let Err(err) = budget.snap_decode(&data, MAX_ENTRY_SIZE) {
+                    report.problems.push(format!(
+                        "{:?} of block {}: {:#}",
+                        expected,
+                        report.starting_number + block - 1,
+                        err
+                    ));
+                }
+            }
+            E2StoreType::TotalDifficulty => {
+                let Ok(total_difficulty) = <[u8; 32]>::try_from(data.as_slice()) else {
+                    report.problems.push(format!(
+                        "total difficulty of block {} is {} bytes long, expected 32",
+                        report.starting_number + block - 1,
+                        data.len()
+                    ));
+                    return Ok(report);
+                };
+                // Little endian, so compare from the most significant byte.
+                let mut big_endian = total_difficulty;
+                big_endian.reverse();
+                if last_total_difficulty.is_some_and(|last| big_endian <= last) {
+                    report.problems.push(format!(
+                        "total difficulty of block {} doesn't grow",
+                        report.starting_number + block - 1
+                    ));
+                }
+                last_total_difficulty = Some(big_endian);
+                accumulator.add(&block_hash, &total_difficulty);
+            }
+            E2StoreType::Accumulator => stored_root = Some(data),
+            E2StoreType::BlockIndex => {
+                report.blocks = block;
+                check_block_index(&mut report, &data, entry_offset, &header_offsets)?;
+                let root = accumulator.finish();
+                if stored_root.as_deref() != Some(root.as_slice()) {
+                    report.problems.push(format!(
+                        "stored accumulator doesn't match the recomputed {}",
+                        hex::encode(root)
+                    ));
+                }
+                if reader.read(&mut [0])? != 0 {
+                    report
+                        .problems
+                        .push("data follows the block index".to_string());
+                }
+
+                return Ok(report);
+            }
+            // Only era1 entries are ever expected, beacon entries are refused
+            // by `check_era1` above.
+            E2StoreType::CompressedSignedBeaconBlock
+            | E2StoreType::CompressedBeaconState
+            | E2StoreType::SlotIndex => unreachable!(),
+        }
+    }
+
+    report.problems.push(match stored_root {
+        Some(_) => "file ends without a block index".to_string(),
+        None => "file ends without an accumulator".to_string(),
+    });
+
+    Ok(report)
+}
+
+/// Runs [`verify_era_file`] on every file in `paths`, spread over all cores
+/// and sharing the memory `budget`, returning the results in order.
+pub fn verify_era_files(
+    paths: &[PathBuf],
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+    budget: &DecompressionBudget,
+) -> Vec<Result<EraFileReport, anyhow::Error>> {
+    map_in_parallel(paths, |path| {
+        verify_era_file(path, unknown_entries, key, budget)
+            .context(format!("verify '{}'", path.display()))
+    })
+}
+
+/// Checks that the block index in `data`, the entry at `index_offset`, lists
+/// the headers at `header_offsets`.
+fn check_block_index(
+    report: &mut EraFileReport,
+    mut data: &[u8],
+    index_offset: u64,
+    header_offsets: &[u64],
+) -> Result<(), anyhow::Error> {
+    let blocks = header_offsets.len();
+    if data.len() != 16 + 8 * blocks {
+        report.problems.push(format!(
+            "block index is {} bytes long, expected {} for {} blocks",
+            data.len(),
+            16 + 8 * blocks,
+            blocks
+        ));
+        return Ok(());
+    }
+
+    let starting_number = data.read_u64::<LittleEndian>()?;
+    if starting_number != report.starting_number {
+        report.problems.push(format!(
+            "block index starts at block {}, the first header is block {}",
+            starting_number, report.starting_number
+        ));
+    }
+    // Offsets are relative, mirroring `EraBuilder::finalize`.
+    let base = index_offset as i64 + 3 * 8;
+    for (idx, header_offset) in header_offsets.iter().enumerate() {
+        let offset = data.read_i64::<LittleEndian>()? + base + idx as i64 * 8;
+        if offset != *header_offset as i64 {
+            report.problems.push(format!(
+                "block index entry {} points at offset {}, the header is at {}",
+                idx, offset, header_offset
+            ));
+        }
+    }
+    let count = data.read_u64::<LittleEndian>()?;
+    if count != blocks as u64 {
+        report.problems.push(format!(
+            "block index count is {}, the file holds {} blocks",
+            count, blocks
+        ));
+    }
+
+    Ok(())
+}
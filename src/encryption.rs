@@ -0,0 +1,184 @@
+//! Optional encryption of finished era files, for archives that must be kept
+//! encrypted at rest.
+//!
+//! An encrypted file is named after the era file with an `.enc` suffix and
+//! holds a magic, a random nonce and the AES-256-GCM ciphertext of the whole
+//! era file. Readers given a key decrypt such files transparently.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{anyhow, bail, Context};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Appended to the name of an era file once it is encrypted.
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+/// Starts every encrypted file. Era files start with a version entry, whose
+/// type is `0x3265`, so the two can't be mistaken for each other.
+const MAGIC: &[u8; 8] = b"era1enc1";
+
+const NONCE_SIZE: usize = 12;
+
+pub struct EncryptionKey(Aes256Gcm);
+
+impl EncryptionKey {
+    /// The key from `ERA_ENCRYPTION_KEY`, as 64 hex characters, or printed by
+    /// the shell command in `ERA_ENCRYPTION_KEY_COMMAND`, such as a KMS
+    /// client. `None` if neither is set.
+    pub fn from_env() -> Result<Option<Self>, anyhow::Error> {
+        if let Ok(key) = env::var("ERA_ENCRYPTION_KEY") {
+            return Ok(Some(
+                Self::from_hex(&key).context("read ERA_ENCRYPTION_KEY")?,
+            ));
+        }
+        let Ok(command) = env::var("ERA_ENCRYPTION_KEY_COMMAND") else {
+            return Ok(None);
+        };
+
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .output()
+            .context("run ERA_ENCRYPTION_KEY_COMMAND")?;
+        if !output.status.success() {
+            bail!(
+                "ERA_ENCRYPTION_KEY_COMMAND failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let key = String::from_utf8(output.stdout)
+            .context("ERA_ENCRYPTION_KEY_COMMAND printed invalid UTF-8")?;
+
+        Ok(Some(Self::from_hex(&key).context(
+            "read the key printed by ERA_ENCRYPTION_KEY_COMMAND",
+        )?))
+    }
+
+    pub fn from_hex(key: &str) -> Result<Self, anyhow::Error> {
+        let key = hex::decode(key.trim()).context("key is not valid hex")?;
+        if key.len() != 32 {
+            bail!("key is {} bytes long, expected 32", key.len());
+        }
+
+        Ok(Self(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("encryption failed"))?;
+
+        let mut encrypted = Vec::with_capacity(MAGIC.len() + NONCE_SIZE + ciphertext.len());
+        encrypted.extend_from_slice(MAGIC);
+        encrypted.extend_from_slice(&nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        Ok(encrypted)
+    }
+
+    pub fn decrypt(&self, encrypted: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        if !encrypted.starts_with(MAGIC) || encrypted.len() < MAGIC.len() + NONCE_SIZE {
+            bail!("not an encrypted era file");
+        }
+        let (nonce, ciphertext) = encrypted[MAGIC.len()..].split_at(NONCE_SIZE);
+
+        self.0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("decryption failed, the key is wrong or the file is corrupted"))
+    }
+
+    /// Replaces the era file at `path` with an encrypted copy and returns the
+    /// path of the copy. The copy is synced before the original is removed.
+    pub fn encrypt_file(&self, path: &Path) -> Result<PathBuf, anyhow::Error> {
+        let plaintext = fs::read(path).context(format!("read '{}'", path.display()))?;
+        let encrypted_path = encrypted_path(path);
+        let mut temporary_path = encrypted_path.clone().into_os_string();
+        temporary_path.push(".tmp");
+
+        let mut file = File::create(&temporary_path)?;
+        file.write_all(&self.encrypt(&plaintext)?)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, &encrypted_path)?;
+        fs::remove_file(path)?;
+
+        Ok(encrypted_path)
+    }
+}
+
+/// Where the encrypted copy of the era file at `path` goes.
+pub fn encrypted_path(path: &Path) -> PathBuf {
+    let mut encrypted_path = path.as_os_str().to_owned();
+    encrypted_path.push(ENCRYPTED_SUFFIX);
+    PathBuf::from(encrypted_path)
+}
+
+/// Reads an era file, decrypting it first if it is encrypted.
+pub enum EraReader {
+    Plain(BufReader<File>),
+    /// Encrypted files are decrypted into memory as a whole, as AES-GCM only
+    /// authenticates the data once all of it is read.
+    Decrypted(Cursor<Vec<u8>>),
+}
+
+impl EraReader {
+    /// Opens the era file at `path`. Encrypted files are recognized by their
+    /// content and need `key`.
+    pub fn open(path: &Path, key: Option<&EncryptionKey>) -> Result<Self, anyhow::Error> {
+        let mut file = File::open(path).context(format!("open '{}'", path.display()))?;
+        let mut magic = [0; MAGIC.len()];
+        let encrypted = match file.read_exact(&mut magic) {
+            Ok(()) => &magic == MAGIC,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(err) => return Err(err.into()),
+        };
+        file.rewind()?;
+        if !encrypted {
+            return Ok(EraReader::Plain(BufReader::new(file)));
+        }
+
+        let key = key.ok_or(anyhow!(
+            "'{}' is encrypted, set ERA_ENCRYPTION_KEY or ERA_ENCRYPTION_KEY_COMMAND",
+            path.display()
+        ))?;
+        let mut encrypted = Vec::new();
+        file.read_to_end(&mut encrypted)?;
+
+        Ok(EraReader::Decrypted(Cursor::new(
+            key.decrypt(&encrypted)
+                .context(format!("decrypt '{}'", path.display()))?,
+        )))
+    }
+
+    /// Skips `offset` bytes forward or backward, keeping the read buffer
+    /// when possible.
+    pub fn seek_relative(&mut self, offset: i64) -> io::Result<()> {
+        match self {
+            EraReader::Plain(reader) => reader.seek_relative(offset),
+            EraReader::Decrypted(reader) => reader.seek(SeekFrom::Current(offset)).map(|_| ()),
+        }
+    }
+}
+
+impl Read for EraReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            EraReader::Plain(reader) => reader.read(buf),
+            EraReader::Decrypted(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for EraReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            EraReader::Plain(reader) => reader.seek(pos),
+            EraReader::Decrypted(reader) => reader.seek(pos),
+        }
+    }
+}
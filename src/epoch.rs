@@ -0,0 +1,19 @@
+//! Conversions between block numbers and the epochs that era files cover.
+
+/// Number of blocks in an epoch, and therefore in a full era file.
+pub const EPOCH_SIZE: u64 = 8192;
+
+/// Epoch containing `block_number`.
+pub fn get_epoch(block_number: u64) -> u64 {
+    block_number / EPOCH_SIZE
+}
+
+/// First block of `epoch`.
+pub fn first_block(epoch: u64) -> u64 {
+    epoch * EPOCH_SIZE
+}
+
+/// Whether `block_number` is the first block of its epoch.
+pub fn is_epoch_start(block_number: u64) -> bool {
+    block_number.is_multiple_of(EPOCH_SIZE)
+}
@@ -0,0 +1,41 @@
+use anyhow::Context;
+use std::fmt::Display;
+
+/// Context wrappers used across the pipeline so every surfaced error names the
+/// block, epoch and stage it came from, e.g.
+/// `block 4370001: stage map-receipts: transaction 0x…: invalid bloom`.
+pub trait PipelineContext<T> {
+    fn stage(self, stage: &'static str) -> anyhow::Result<T>;
+    fn with_block(self, block_number: u64) -> anyhow::Result<T>;
+    fn with_epoch(self, epoch: u64) -> anyhow::Result<T>;
+    fn with_transaction(self, hash: &[u8]) -> anyhow::Result<T>;
+}
+
+impl<T, E> PipelineContext<T> for Result<T, E>
+where
+    Result<T, E>: Context<T, E>,
+{
+    fn stage(self, stage: &'static str) -> anyhow::Result<T> {
+        self.with_context(|| Labelled("stage", stage))
+    }
+
+    fn with_block(self, block_number: u64) -> anyhow::Result<T> {
+        self.with_context(|| Labelled("block", block_number))
+    }
+
+    fn with_epoch(self, epoch: u64) -> anyhow::Result<T> {
+        self.with_context(|| Labelled("epoch", epoch))
+    }
+
+    fn with_transaction(self, hash: &[u8]) -> anyhow::Result<T> {
+        self.with_context(|| Labelled("transaction", format!("0x{}", hex::encode(hash))))
+    }
+}
+
+struct Labelled<V>(&'static str, V);
+
+impl<V: Display> Display for Labelled<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.0, self.1)
+    }
+}
@@ -0,0 +1,94 @@
+//! Finds the eras of a range that have no complete file in an output
+//! directory, for `fill-gaps` and for skipping finished eras on a rerun.
+
+use crate::cli::EraRange;
+use crate::naming::discover_epoch;
+use anyhow::Context;
+use era_file_sink::chain::ChainConfig;
+use era_file_sink::e2store::check_block_index;
+use era_file_sink::encryption::ENCRYPTED_SUFFIX;
+use era_file_sink::epoch::first_block;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// What the output directory holds for an epoch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EraFile {
+    /// A file ending with a block index of every block of the era, starting
+    /// at its first block. Encrypted files aren't looked into and count
+    /// as complete.
+    Complete,
+    /// Only files whose block index is missing, short or for another era.
+    Corrupt(Vec<PathBuf>),
+    Missing,
+}
+
+/// The era files of a chain in `output_dir`, by epoch.
+pub struct EraFiles {
+    files: HashMap<u64, EraFile>,
+}
+
+impl EraFiles {
+    pub fn scan(output_dir: &Path, chain: ChainConfig) -> Result<Self, anyhow::Error> {
+        let mut files = HashMap::new();
+        for entry in std::fs::read_dir(output_dir).context("list output directory")? {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|file_name| file_name.to_str()) else {
+                continue;
+            };
+            let (file_name, encrypted) = match file_name.strip_suffix(ENCRYPTED_SUFFIX) {
+                Some(file_name) => (file_name, true),
+                None => (file_name, false),
+            };
+            let Some(epoch) = discover_epoch(chain.network, file_name) else {
+                continue;
+            };
+
+            let complete = encrypted
+                || File::open(&path).ok().and_then(|file| {
+                    check_block_index(&mut BufReader::new(file), chain.era_size(epoch)).ok()
+                }) == Some(first_block(epoch));
+            let status = files.entry(epoch).or_insert(EraFile::Missing);
+            match status {
+                EraFile::Complete => {}
+                _ if complete => *status = EraFile::Complete,
+                EraFile::Corrupt(paths) => paths.push(path),
+                EraFile::Missing => *status = EraFile::Corrupt(vec![path]),
+            }
+        }
+
+        Ok(Self { files })
+    }
+
+    pub fn get(&self, epoch: u64) -> &EraFile {
+        self.files.get(&epoch).unwrap_or(&EraFile::Missing)
+    }
+
+    /// Number of consecutive complete eras from the start of `range` on.
+    pub fn complete_prefix(&self, range: EraRange) -> u64 {
+        (range.start..=range.stop)
+            .take_while(|epoch| self.get(*epoch) == &EraFile::Complete)
+            .count() as u64
+    }
+
+    /// The eras of `range` without a complete file, grouped into runs of
+    /// consecutive eras.
+    pub fn gaps(&self, range: EraRange) -> Vec<EraRange> {
+        let mut gaps: Vec<EraRange> = Vec::new();
+        for epoch in range.start..=range.stop {
+            if self.get(epoch) == &EraFile::Complete {
+                continue;
+            }
+            match gaps.last_mut() {
+                Some(gap) if gap.stop + 1 == epoch => gap.stop = epoch,
+                _ => gaps.push(EraRange {
+                    start: epoch,
+                    stop: epoch,
+                }),
+            }
+        }
+        gaps
+    }
+}
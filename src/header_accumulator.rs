@@ -1,18 +1,534 @@
+use crate::compression::budget::DecompressionBudget;
+use crate::e2store::builder::MAX_HEADER_SIZE;
+use crate::e2store::{read_entry_header, E2StoreType, UnknownEntryPolicy, HEADER_SIZE};
+use crate::encryption::{EncryptionKey, EraReader, ENCRYPTED_SUFFIX};
+use crate::epoch::{get_epoch, is_epoch_start, EPOCH_SIZE};
+use crate::parallel::map_in_parallel;
+use anyhow::{bail, Context};
 use embed_file::embed_string;
+use reth_primitives::keccak256;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-pub const EPOCH_SIZE: u64 = 8192;
+/// An epoch accumulator root.
+pub type AccumulatorRoot = [u8; 32];
 
-pub fn read_values() -> Vec<String> {
-    embed_string!("assets/acc_values.txt")
-        .lines()
-        .map(|line| line.to_string())
-        .collect()
+/// A source of the per-epoch header accumulator roots written into era files.
+///
+/// These values are the trust anchor of every file the sink produces, so the
+/// provider in use is reported at startup and can be swapped for one the
+/// operator trusts more than the list shipped with the binary.
+pub trait AccumulatorProvider {
+    /// Human readable origin of the values.
+    fn describe(&self) -> String;
+
+    /// Accumulator root of `epoch`, if the provider knows it.
+    fn value_for_epoch(&self, epoch: u64) -> Option<AccumulatorRoot>;
+
+    /// Number of consecutive epochs, starting at 0, that have a value.
+    fn epochs(&self) -> u64;
+}
+
+/// Accumulator roots held in memory, read from one hex value per epoch and
+/// line. Every value is decoded and length checked when the list is loaded, so
+/// a malformed value can't surface in the middle of an era.
+pub struct ValueList {
+    source: String,
+    values: Vec<AccumulatorRoot>,
+}
+
+impl ValueList {
+    /// The list embedded in the binary at build time.
+    pub fn embedded() -> Result<Self, anyhow::Error> {
+        Self::parse(
+            "embedded accumulator values",
+            &embed_string!("assets/acc_values.txt"),
+        )
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .context(format!("read accumulator values from '{}'", path))?;
+        Self::parse(path, &content)
+    }
+
+    #[cfg(feature = "client")]
+    pub async fn from_url(url: &str) -> Result<Self, anyhow::Error> {
+        let content = reqwest::get(url)
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+            .context(format!("fetch accumulator values from '{}'", url))?;
+        Self::parse(url, &content)
+    }
+
+    /// Picks a provider from `source`: `embedded`, an `http(s)://` URL or a
+    /// local file path.
+    pub async fn load(source: &str) -> Result<Self, anyhow::Error> {
+        match source {
+            "embedded" => Self::embedded(),
+            #[cfg(feature = "client")]
+            url if url.starts_with("http://") || url.starts_with("https://") => {
+                Self::from_url(url).await
+            }
+            #[cfg(not(feature = "client"))]
+            url if url.starts_with("http://") || url.starts_with("https://") => {
+                anyhow::bail!(
+                    "fetching accumulator values from '{}' needs the `client` feature",
+                    url
+                )
+            }
+            path => Self::from_file(path),
+        }
+    }
+
+    fn parse(source: &str, content: &str) -> Result<Self, anyhow::Error> {
+        let values = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(epoch, line)| {
+                let value = hex::decode(line.strip_prefix("0x").unwrap_or(line))
+                    .context(format!("accumulator value for epoch {} is not hex", epoch))?;
+                AccumulatorRoot::try_from(value.as_slice()).map_err(|_| {
+                    anyhow::anyhow!(
+                        "accumulator value for epoch {} is {} bytes long, expected 32",
+                        epoch,
+                        value.len()
+                    )
+                })
+            })
+            .collect::<Result<Vec<AccumulatorRoot>, anyhow::Error>>()
+            .context(format!("parse accumulator values from {}", source))?;
+
+        Ok(Self {
+            source: source.to_string(),
+            values,
+        })
+    }
+}
+
+impl AccumulatorProvider for ValueList {
+    fn describe(&self) -> String {
+        format!("{} ({} epochs)", self.source, self.values.len())
+    }
+
+    fn value_for_epoch(&self, epoch: u64) -> Option<AccumulatorRoot> {
+        self.values.get(epoch as usize).copied()
+    }
+
+    fn epochs(&self) -> u64 {
+        self.values.len() as u64
+    }
+}
+
+pub fn get_value_for_block(
+    provider: &dyn AccumulatorProvider,
+    block_number: u64,
+) -> Option<AccumulatorRoot> {
+    provider.value_for_epoch(get_epoch(block_number))
 }
 
-pub fn get_epoch(block_number: u64) -> u64 {
-    block_number / EPOCH_SIZE
+/// What happens to an era whose blocks are all written but whose accumulator
+/// value isn't available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingValuePolicy {
+    /// Stop with an error.
+    Fail,
+    /// Leave the era as a `.pending` file, to be completed by the
+    /// `finalize-pending` command once the value is available.
+    Defer,
+}
+
+impl FromStr for MissingValuePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(MissingValuePolicy::Fail),
+            "defer" => Ok(MissingValuePolicy::Defer),
+            _ => Err(anyhow::anyhow!(
+                "unknown missing accumulator policy '{}', expected 'fail' or 'defer'",
+                s
+            )),
+        }
+    }
+}
+
+/// Checks that `[start_block, stop_block)` is a non-empty run of eras, starting
+/// at an epoch boundary, that all have an accumulator value, so a bad range fails before streaming
+/// rather than when its first era is finalized. Under
+/// [`MissingValuePolicy::Defer`] missing values only produce a warning.
+pub fn validate_range(
+    provider: &dyn AccumulatorProvider,
+    start_block: u64,
+    stop_block: u64,
+    missing_values: MissingValuePolicy,
+) -> anyhow::Result<()> {
+    if !is_epoch_start(start_block) {
+        bail!("start block {} does not begin an epoch", start_block);
+    }
+    if stop_block <= start_block {
+        bail!("requested range [{}, {}) is empty", start_block, stop_block);
+    }
+
+    let last_requested = get_epoch(stop_block - 1);
+    if last_requested < provider.epochs() {
+        return Ok(());
+    }
+
+    let message = format!(
+        "requested range ends at epoch {} but header accumulator values are only available for {} epochs",
+        last_requested,
+        provider.epochs()
+    );
+    match missing_values {
+        MissingValuePolicy::Fail => bail!(message),
+        MissingValuePolicy::Defer => {
+            println!("Warning: {}, those eras will be left pending", message);
+            Ok(())
+        }
+    }
+}
+
+/// Reads the Accumulator entry of every era1 file in `dir` and returns the
+/// roots in epoch order, in the format of `assets/acc_values.txt`.
+///
+/// The epoch of a file is taken from the starting block of its block index
+/// rather than from its name, so archives from other networks or with other
+/// naming schemes work too. Every epoch from 0 up to the last one found must
+/// be present. Encrypted files are decrypted with `key`.
+pub fn extract_values(
+    dir: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let mut roots = BTreeMap::new();
+    for path in era_files(dir)? {
+        let (epoch, root) = read_era_accumulator(&path, unknown_entries, key)
+            .context(format!("read '{}'", path.display()))?;
+        match roots.insert(epoch, root.clone()) {
+            Some(previous) if previous != root => {
+                bail!("found conflicting accumulator roots for epoch {}", epoch)
+            }
+            _ => {}
+        }
+    }
+
+    let mut values = Vec::with_capacity(roots.len());
+    for (expected, (epoch, root)) in roots.into_iter().enumerate() {
+        if epoch != expected as u64 {
+            bail!("no era file found for epoch {}", expected);
+        }
+        values.push(hex::encode(root));
+    }
+
+    Ok(values)
+}
+
+/// Epoch and accumulator root of a single era1 file.
+fn read_era_accumulator(
+    path: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+) -> Result<(u64, Vec<u8>), anyhow::Error> {
+    let mut reader = EraReader::open(path, key)?;
+    let mut root = None;
+    let mut offset = 0;
+    loop {
+        let (type_, length) = match read_entry_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        unknown_entries.check_era1(type_, offset)?;
+        offset += HEADER_SIZE + length as u64;
+
+        if type_ == E2StoreType::Accumulator as u16 {
+            let mut data = vec![0; length as usize];
+            reader.read_exact(&mut data)?;
+            root = Some(data);
+        } else if type_ == E2StoreType::BlockIndex as u16 {
+            let mut starting_number = [0; 8];
+            reader.read_exact(&mut starting_number)?;
+            let root = root.ok_or(anyhow::anyhow!("block index precedes the accumulator"))?;
+
+            return Ok((get_epoch(u64::from_le_bytes(starting_number)), root));
+        } else {
+            reader.seek_relative(length as i64)?;
+        }
+    }
+
+    bail!("no block index found")
 }
 
-pub fn get_value_for_block(data: &[String], block_number: u64) -> Option<&String> {
-    data.get(get_epoch(block_number) as usize)
+/// Era1 files in `dir`, encrypted or not, in no particular order.
+fn era_files(dir: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).context(format!("list directory '{}'", dir.display()))? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if name.ends_with(".era1") || name.ends_with(&format!(".era1{}", ENCRYPTED_SUFFIX)) {
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Outcome of checking a list of accumulator values against an archive.
+#[derive(Default)]
+pub struct VerificationReport {
+    pub matching: u64,
+    pub mismatching: u64,
+    pub unchecked: u64,
+}
+
+/// Recomputes the accumulator root of every era1 file in `dir` from its
+/// headers and total difficulties, spreading the files over all cores within
+/// the memory `budget`, and prints how each epoch compares with `provider`.
+///
+/// Unlike `extract_values`, this doesn't trust the Accumulator entry stored in
+/// the files, so it can audit the values a provider hands out. Encrypted
+/// files are decrypted with `key`.
+pub fn verify_values(
+    provider: &dyn AccumulatorProvider,
+    dir: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+    budget: &DecompressionBudget,
+) -> Result<VerificationReport, anyhow::Error> {
+    let paths = era_files(dir)?;
+    let results = map_in_parallel(&paths, |path| {
+        recompute_era_accumulator(path, unknown_entries, key, budget)
+            .context(format!("recompute '{}'", path.display()))
+    })
+    .into_iter()
+    .collect::<Result<Vec<(u64, AccumulatorRoot)>, anyhow::Error>>()?;
+
+    let mut recomputed = BTreeMap::new();
+    for (epoch, root) in results {
+        if recomputed
+            .insert(epoch, root)
+            .is_some_and(|previous| previous != root)
+        {
+            bail!(
+                "found era files with different contents for epoch {}",
+                epoch
+            );
+        }
+    }
+
+    let mut report = VerificationReport::default();
+    let epochs = provider
+        .epochs()
+        .max(recomputed.keys().next_back().map_or(0, |epoch| epoch + 1));
+    for epoch in 0..epochs {
+        match (provider.value_for_epoch(epoch), recomputed.get(&epoch)) {
+            (Some(value), Some(root)) if value == *root => report.matching += 1,
+            (Some(value), Some(root)) => {
+                println!(
+                    "epoch {}: {} has {}, recomputed {}",
+                    epoch,
+                    provider.describe(),
+                    hex::encode(value),
+                    hex::encode(root)
+                );
+                report.mismatching += 1;
+            }
+            (Some(_), None) => {
+                println!("epoch {}: no era file to check against", epoch);
+                report.unchecked += 1;
+            }
+            (None, Some(root)) => {
+                println!(
+                    "epoch {}: missing from {}, recomputed {}",
+                    epoch,
+                    provider.describe(),
+                    hex::encode(root)
+                );
+                report.unchecked += 1;
+            }
+            (None, None) => {}
+        }
+    }
+
+    Ok(report)
+}
+
+/// Epoch of a single era1 file and the accumulator root of its headers: the
+/// SSZ hash tree root of a list of up to `EPOCH_SIZE` header records, each
+/// made of a block hash and a total difficulty.
+fn recompute_era_accumulator(
+    path: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+    budget: &DecompressionBudget,
+) -> Result<(u64, AccumulatorRoot), anyhow::Error> {
+    let mut reader = EraReader::open(path, key)?;
+    let mut block_hashes = Vec::new();
+    let mut accumulator = EpochAccumulator::default();
+    let mut offset = 0;
+    loop {
+        let (type_, length) = match read_entry_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        unknown_entries.check_era1(type_, offset)?;
+        offset += HEADER_SIZE + length as u64;
+
+        if type_ == E2StoreType::CompressedHeader as u16 {
+            let mut data = vec![0; length as usize];
+            reader.read_exact(&mut data)?;
+            block_hashes.push(keccak256(&*budget.snap_decode(&data, MAX_HEADER_SIZE)?).0);
+        } else if type_ == E2StoreType::TotalDifficulty as u16 {
+            // Stored as a 32 byte little endian integer, just like SSZ wants it.
+            let mut total_difficulty = [0; 32];
+            reader.read_exact(&mut total_difficulty)?;
+            let block_hash = block_hashes
+                .get(accumulator.len())
+                .ok_or(anyhow::anyhow!("total difficulty precedes its header"))?;
+            accumulator.add(block_hash, &total_difficulty);
+        } else if type_ == E2StoreType::BlockIndex as u16 {
+            let mut starting_number = [0; 8];
+            reader.read_exact(&mut starting_number)?;
+            let epoch = get_epoch(u64::from_le_bytes(starting_number));
+            if accumulator.is_empty() || accumulator.len() != block_hashes.len() {
+                bail!(
+                    "found {} headers and {} total difficulties",
+                    block_hashes.len(),
+                    accumulator.len()
+                );
+            }
+
+            return Ok((epoch, accumulator.finish()));
+        } else {
+            reader.seek_relative(length as i64)?;
+        }
+    }
+
+    bail!("no block index found")
+}
+
+/// Decides the accumulator root written into each era: looked up in a list
+/// of values, or computed from the blocks of the era, optionally checked
+/// against a list.
+pub struct AccumulatorRoots {
+    /// Values looked up by epoch, or the reference for cross-checking when
+    /// roots are computed.
+    pub values: Option<ValueList>,
+    /// Whether roots are computed from the blocks instead of looked up.
+    pub computed: bool,
+    /// Whether a computed root must match the listed value when there is one.
+    pub cross_check: bool,
+}
+
+impl AccumulatorRoots {
+    /// The root of the era starting at `starting_number`, given the one
+    /// `computed` from its blocks. `None` if roots are looked up and the list
+    /// has no value for the era.
+    pub fn root_for_era(
+        &self,
+        starting_number: u64,
+        computed: AccumulatorRoot,
+    ) -> Result<Option<AccumulatorRoot>, anyhow::Error> {
+        let listed = self
+            .values
+            .as_ref()
+            .and_then(|values| get_value_for_block(values, starting_number));
+        if self.cross_check {
+            if let Some(listed) = listed.filter(|listed| *listed != computed) {
+                bail!(
+                    "accumulator root computed for epoch {} is {}, but {} lists {}",
+                    get_epoch(starting_number),
+                    hex::encode(computed),
+                    self.values
+                        .as_ref()
+                        .map_or(String::new(), |values| values.describe()),
+                    hex::encode(listed)
+                );
+            }
+        }
+
+        Ok(if self.computed {
+            Some(computed)
+        } else {
+            listed
+        })
+    }
+}
+
+/// Computes the accumulator root of one epoch from its blocks, added in
+/// order: the SSZ hash tree root of a list of (block hash, total difficulty)
+/// records.
+#[derive(Default)]
+pub struct EpochAccumulator {
+    records: Vec<[u8; 32]>,
+}
+
+impl EpochAccumulator {
+    /// Adds the next block, with its total difficulty as a 32 byte little
+    /// endian integer.
+    pub fn add(&mut self, block_hash: &[u8; 32], total_difficulty: &[u8; 32]) {
+        self.records.push(hash_pair(block_hash, total_difficulty));
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Root of the blocks added so far.
+    pub fn root(&self) -> AccumulatorRoot {
+        let count = self.records.len() as u64;
+        let root = merkleize(self.records.clone(), EPOCH_SIZE.trailing_zeros());
+        let mut length = [0; 32];
+        length[..8].copy_from_slice(&count.to_le_bytes());
+
+        hash_pair(&root, &length)
+    }
+
+    /// Root of the blocks added so far, starting over for the next epoch.
+    pub fn finish(&mut self) -> AccumulatorRoot {
+        let root = self.root();
+        self.records.clear();
+        root
+    }
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root of a binary merkle tree of `depth` levels over the chunks in `layer`,
+/// padded with zero chunks.
+fn merkleize(mut layer: Vec<[u8; 32]>, depth: u32) -> [u8; 32] {
+    let mut zero = [0; 32];
+    for _ in 0..depth {
+        if layer.len() % 2 == 1 {
+            layer.push(zero);
+        }
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+        zero = hash_pair(&zero, &zero);
+    }
+
+    layer.first().copied().unwrap_or(zero)
 }
@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Context};
+use object_store::{BackoffConfig, ClientOptions, RetryConfig};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Proxy, RequestBuilder, Response, StatusCode};
+use std::env;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tokio_retry::strategy::{jitter, ExponentialBackoff};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RETRIES: usize = 3;
+const DEFAULT_RETRY_BUDGET: Duration = Duration::from_secs(180);
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// HTTP settings shared by every client of the sink, read from the
+/// environment so they can work behind corporate proxies:
+///
+/// - `HTTP_PROXY_URL`: proxy for all requests, on top of the standard
+///   `HTTP_PROXY`/`HTTPS_PROXY` variables reqwest already honors.
+/// - `HTTP_TIMEOUT_SECS`: timeout of a single attempt, 30 by default.
+/// - `HTTP_RETRIES`: retries after a connection error, a timeout or a
+///   transient status (429, 5xx), 3 by default.
+/// - `HTTP_RETRY_BUDGET_SECS`: time after the first attempt past which a
+///   request is no longer retried, 180 by default.
+/// - `HTTP_POOL_IDLE_SECS`: how long an idle connection is kept open for the
+///   next request, 90 by default.
+/// - `HTTP_HEADERS`: extra headers as `Name: value` pairs separated by `;`.
+#[derive(Clone, Debug)]
+pub struct HttpSettings {
+    timeout: Duration,
+    retries: usize,
+    retry_budget: Duration,
+    pool_idle_timeout: Duration,
+    proxy: Option<String>,
+    headers: HeaderMap,
+}
+
+impl HttpSettings {
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let retries = match env::var("HTTP_RETRIES") {
+            Ok(retries) => retries
+                .parse::<usize>()
+                .context("HTTP_RETRIES is not a valid integer")?,
+            Err(_) => DEFAULT_RETRIES,
+        };
+
+        let mut headers = HeaderMap::new();
+        if let Ok(pairs) = env::var("HTTP_HEADERS") {
+            for pair in pairs.split(';').filter(|pair| !pair.trim().is_empty()) {
+                let (name, value) = pair.split_once(':').ok_or(anyhow!(
+                    "HTTP_HEADERS entry '{}' is not 'Name: value'",
+                    pair
+                ))?;
+                headers.insert(
+                    HeaderName::from_bytes(name.trim().as_bytes())?,
+                    HeaderValue::from_str(value.trim())?,
+                );
+            }
+        }
+
+        Ok(Self {
+            timeout: secs_from_env("HTTP_TIMEOUT_SECS", DEFAULT_TIMEOUT)?,
+            retries,
+            retry_budget: secs_from_env("HTTP_RETRY_BUDGET_SECS", DEFAULT_RETRY_BUDGET)?,
+            pool_idle_timeout: secs_from_env("HTTP_POOL_IDLE_SECS", DEFAULT_POOL_IDLE_TIMEOUT)?,
+            proxy: env::var("HTTP_PROXY_URL").ok(),
+            headers,
+        })
+    }
+
+    /// The same settings for the object store clients of [`crate::sink`],
+    /// which pool connections and retry on their own. `HTTP_HEADERS` is left
+    /// out, extra headers would break the request signatures of the stores.
+    pub fn object_store_options(&self) -> (ClientOptions, RetryConfig) {
+        let mut options = ClientOptions::new()
+            .with_timeout(self.timeout)
+            .with_pool_idle_timeout(self.pool_idle_timeout);
+        if let Some(proxy) = &self.proxy {
+            options = options.with_proxy_url(proxy);
+        }
+        let retry = RetryConfig {
+            backoff: BackoffConfig::default(),
+            max_retries: self.retries,
+            retry_timeout: self.retry_budget,
+        };
+
+        (options, retry)
+    }
+}
+
+fn secs_from_env(name: &str, default: Duration) -> Result<Duration, anyhow::Error> {
+    match env::var(name) {
+        Ok(secs) => Ok(Duration::from_secs(
+            secs.parse::<u64>()
+                .context(format!("{} is not a valid integer", name))?,
+        )),
+        Err(_) => Ok(default),
+    }
+}
+
+/// HTTP client for the package download, the StreamingFast auth call and the
+/// JSON-RPC lookups, configured by [`HttpSettings`]. Clones share the
+/// connection pool.
+#[derive(Clone, Debug)]
+pub struct HttpClient {
+    client: Client,
+    retries: usize,
+    retry_budget: Duration,
+}
+
+impl HttpClient {
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        Self::new(&HttpSettings::from_env()?)
+    }
+
+    pub fn new(settings: &HttpSettings) -> Result<Self, anyhow::Error> {
+        let mut builder = Client::builder()
+            .timeout(settings.timeout)
+            .pool_idle_timeout(settings.pool_idle_timeout)
+            .default_headers(settings.headers.clone())
+            .gzip(true)
+            .deflate(true);
+        if let Some(proxy) = &settings.proxy {
+            builder = builder.proxy(Proxy::all(proxy).context("HTTP_PROXY_URL is invalid")?);
+        }
+
+        Ok(Self {
+            client: builder.build()?,
+            retries: settings.retries,
+            retry_budget: settings.retry_budget,
+        })
+    }
+
+    /// Sends the request built by `request`, retrying transient failures with
+    /// exponential backoff until the retries or the retry budget run out. A
+    /// rate limited request waits at least as long as its `Retry-After`
+    /// header asks. Other error statuses are returned as responses for the
+    /// caller to handle.
+    pub async fn send(
+        &self,
+        request: impl Fn(&Client) -> RequestBuilder,
+    ) -> Result<Response, reqwest::Error> {
+        let mut delays = ExponentialBackoff::from_millis(10)
+            .factor(50)
+            .max_delay(Duration::from_secs(10))
+            .map(jitter)
+            .take(self.retries);
+        let started = Instant::now();
+
+        loop {
+            let (result, retry_after) = match request(&self.client).send().await {
+                Ok(response) if is_transient(response.status()) => {
+                    let retry_after = retry_after(&response);
+                    (response.error_for_status(), retry_after)
+                }
+                result => (result, None),
+            };
+
+            match (result, delays.next()) {
+                (Err(err), Some(delay))
+                    if (err.is_connect() || err.is_timeout() || err.status().is_some())
+                        && started.elapsed() + delay.max(retry_after.unwrap_or_default())
+                            < self.retry_budget =>
+                {
+                    sleep(delay.max(retry_after.unwrap_or_default())).await
+                }
+                (result, _) => return result,
+            }
+        }
+    }
+}
+
+/// How long a rate limited `response` asks to wait, given in seconds or as
+/// an HTTP date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get("retry-after")?.to_str().ok()?;
+    match value.trim().parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()?
+            .duration_since(std::time::SystemTime::now())
+            .ok(),
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
@@ -0,0 +1,65 @@
+//! Jobs files, listing era ranges to rebuild that don't form one range, such
+//! as the epochs affected by an encoder bug.
+//!
+//! Each line holds a range written like the `sink` argument, optionally
+//! followed by a priority. Ranges with a higher priority are written first,
+//! others in the order of the file. Blank lines and text after `#` are
+//! ignored:
+//!
+//! ```text
+//! # epochs with the broken receipts encoding
+//! 120:134
+//! 901     10
+//! 1500:1510 5
+//! ```
+
+use crate::cli::EraRange;
+use anyhow::{anyhow, bail, Context};
+use std::fs;
+use std::path::Path;
+
+/// The ranges of the jobs file at `path`, in the order they should be
+/// written.
+pub fn load(path: &Path) -> Result<Vec<EraRange>, anyhow::Error> {
+    let content =
+        fs::read_to_string(path).context(format!("read jobs file '{}'", path.display()))?;
+    let mut jobs = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let job = parse_job(line).context(format!(
+            "line {} of jobs file '{}'",
+            index + 1,
+            path.display()
+        ))?;
+        jobs.push(job);
+    }
+    if jobs.is_empty() {
+        bail!("jobs file '{}' lists no ranges", path.display());
+    }
+    // A stable sort keeps the file order among equal priorities.
+    jobs.sort_by_key(|(_, priority)| -priority);
+
+    Ok(jobs.into_iter().map(|(range, _)| range).collect())
+}
+
+fn parse_job(line: &str) -> Result<(EraRange, i64), anyhow::Error> {
+    let mut fields = line.split_whitespace();
+    let range = fields
+        .next()
+        .ok_or(anyhow!("missing range"))?
+        .parse::<EraRange>()?;
+    let priority = match fields.next() {
+        Some(priority) => priority
+            .parse::<i64>()
+            .context(format!("priority '{}' is not a valid integer", priority))?,
+        None => 0,
+    };
+    if let Some(extra) = fields.next() {
+        bail!("unexpected '{}' after the priority", extra);
+    }
+
+    Ok((range, priority))
+}
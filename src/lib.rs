@@ -0,0 +1,33 @@
+//! Building blocks for writing era1 files, the e2store archives of
+//! pre-merge Ethereum history.
+//!
+//! [`e2store::builder::EraBuilder`] turns [`model::Block`]s into an era file,
+//! one epoch of blocks at a time. Blocks come from the Substreams protobuf in
+//! [`pb`] or from any other source mapped into [`model`]. Entries can also be
+//! read back and written one by one through [`e2store::E2Store`], and
+//! [`compression`] holds the snappy framing they use.
+//!
+//! The `sink` binary streams blocks from a Substreams endpoint into these
+//! types. It needs the default `client` feature, which also brings the gRPC
+//! clients in [`pb`] and fetching accumulator values over HTTP. Without it,
+//! the crate reads, writes and verifies era files without gRPC, HTTP or TLS
+//! dependencies.
+
+pub mod chain;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod cid;
+pub mod compression;
+pub mod e2store;
+pub mod encryption;
+pub mod epoch;
+pub mod error;
+pub mod header_accumulator;
+pub mod model;
+pub mod parallel;
+pub mod pb;
+pub mod reth_mappings;
+pub mod rlp;
+pub mod timings;
+pub mod total_difficulty;
+pub mod writers;
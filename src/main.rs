@@ -1,93 +1,1165 @@
 use anyhow::{format_err, Context, Error};
+use clap::Parser;
+use era_file_sink::pb::sf::substreams::rpc::v2::BlockScopedData;
+use era_file_sink::pb::sf::substreams::v1::Package;
+use futures03::stream::FuturesUnordered;
 use futures03::StreamExt;
-use pb::sf::substreams::rpc::v2::BlockScopedData;
-use pb::sf::substreams::v1::Package;
-use std::io::Write;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use crate::e2store::builder::EraBuilder;
-use crate::header_accumulator::{get_epoch, EPOCH_SIZE};
-use crate::pb::acme::verifiable_block::v1::VerifiableBlock;
+use crate::cli::{AccumulatorsCommand, Cli, Command, EraRange, MathCommand, StreamArgs};
+use crate::cursor::{CommitPolicy, Cursor};
+use crate::gaps::{EraFile, EraFiles};
+use crate::http::{HttpClient, HttpSettings};
+use crate::naming::{
+    discover_epoch, in_progress_file_name, parse_pending_epoch, pending_file_name, FileNamer,
+    NamingScheme, TEMPORARY_SUFFIX,
+};
+use crate::permissions::OutputPermissions;
+use crate::provenance::{Provenance, Source, PROVENANCE_SUFFIX};
+use crate::receipts::ReceiptSource;
+use crate::recording::Tee;
+use crate::shutdown::{Interrupted, Shutdown};
+use crate::sink::{Sink, UploadQueue, UploadSettings};
+use crate::throttle::Throttle;
+use crate::uncles::UncleSource;
+use era_file_sink::chain::ChainConfig;
+use era_file_sink::cid::{self, CidSettings};
+use era_file_sink::compression::budget::DecompressionBudget;
+use era_file_sink::e2store::beacon::{is_beacon_era_file, verify_beacon_era_file};
+use era_file_sink::e2store::builder::{EntryLimits, EraBuilder};
+use era_file_sink::e2store::compare::compare_era_files;
+use era_file_sink::e2store::utils::encode_bigint;
+use era_file_sink::e2store::verify::{verify_era_file, verify_era_files};
+use era_file_sink::e2store::{check_block_index, UnknownEntryPolicy};
+use era_file_sink::encryption::{EncryptionKey, ENCRYPTED_SUFFIX};
+use era_file_sink::epoch::{first_block, get_epoch, is_epoch_start, EPOCH_SIZE};
+use era_file_sink::error::PipelineContext;
+use era_file_sink::header_accumulator::{
+    AccumulatorProvider, AccumulatorRoots, EpochAccumulator, MissingValuePolicy, ValueList,
+};
+use era_file_sink::model::protobuf::HeaderOnlyBlock;
+use era_file_sink::model::Block;
+use era_file_sink::parallel::InOrder;
+use era_file_sink::pb::acme::verifiable_block::v1::VerifiableBlock;
+use era_file_sink::timings::timed;
+use era_file_sink::total_difficulty::{FromHeader, TotalDifficultyProvider};
+use era_file_sink::{chain, header_accumulator, total_difficulty};
 use prost::Message;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
 use std::{env, process::exit, sync::Arc};
-use substreams::SubstreamsEndpoint;
-use substreams_stream::{BlockResponse, SubstreamsStream};
-
-mod e2store;
-mod header_accumulator;
-mod pb;
-mod reth_mappings;
-mod rlp;
-mod snap;
+use substreams::{Credentials, SubstreamsEndpoint};
+use substreams_stream::{describe_progress, BlockResponse, SubstreamsStream};
+
+mod cat;
+mod cli;
+mod completeness;
+mod cursor;
+mod doctor;
+mod gaps;
+mod http;
+mod jobs;
+mod math;
+mod naming;
+mod permissions;
+#[cfg(feature = "profiling")]
+mod profiling;
+mod provenance;
+mod receipts;
+mod recording;
+mod rpc_check;
+mod shutdown;
+mod sink;
 mod substreams;
 mod substreams_stream;
+mod throttle;
+mod uncles;
+mod version_check;
+
+const DEFAULT_PACKAGE_FILE: &str = "https://spkg.io/semiotic-ai/era-file-substream-v1.0.1.spkg";
+const DEFAULT_MODULE_NAME: &str = "map_block";
+
+/// Minimum delay between two progress reports while waiting for data.
+const PROGRESS_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long the stream may stay silent before it is considered stalled.
+const DEFAULT_RECEIVE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Number of eras above which a run asks for confirmation before starting.
+const DEFAULT_CONFIRMATION_THRESHOLD: u64 = 100;
+
+/// Work directory of `fill-gaps`, inside the output directory.
+const FILL_GAPS_DIR: &str = ".fill-gaps";
+
+/// Work directory of `sink --jobs-file`, inside the output directory.
+const JOBS_DIR: &str = ".jobs";
+
+/// Work directory of `sink --workers`, inside the output directory.
+const WORKERS_DIR: &str = ".workers";
+
+/// Eras handed to a worker at a time when `WORKER_CHUNK_ERAS` isn't set.
+const DEFAULT_WORKER_CHUNK_ERAS: u64 = 10;
+
+/// Rough average size of a mainnet era1 file, for estimating disk usage.
+const ESTIMATED_ERA_SIZE: u64 = 230 * 1024 * 1024;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let args = env::args();
-    if args.len() < 2 || args.len() > 3 {
-        println!("usage: stream <output_dir> <start_era>:<stop_era>");
-        println!();
-        println!("The environment variable SUBSTREAMS_API_KEY must also be set");
-        println!("and should contain a valid Substream API token.");
-        exit(1);
+    #[cfg(feature = "profiling")]
+    let _profiler = dhat::Profiler::new_heap();
+
+    if env::var("CHECK_FOR_UPDATES").is_ok_and(|check| check == "true") {
+        version_check::spawn();
     }
 
-    const ENDPOINT_URL: &str = "https://mainnet.eth.streamingfast.io:443";
-    const PACKAGE_FILE: &str = "https://spkg.io/semiotic-ai/era-file-substream-v1.0.1.spkg";
-    const MODULE_NAME: &str = "map_block";
+    let unknown_entries = match env::var("E2STORE_UNKNOWN_ENTRIES") {
+        Ok(policy) => policy.parse::<UnknownEntryPolicy>()?,
+        Err(_) => UnknownEntryPolicy::Warn,
+    };
 
-    let output_dir = env::args().nth(1).expect("output_dir not provided");
+    let cli = Cli::parse();
+    let encryption_key = EncryptionKey::from_env()?;
+    let chain = match env::var("ERA_NETWORK") {
+        Ok(network) => network.parse::<ChainConfig>()?,
+        Err(_) => chain::MAINNET,
+    };
 
-    let api_key = env::var("SUBSTREAMS_API_KEY").expect("SUBSTREAMS_API_KEY not set");
-    if api_key.is_empty() {
-        println!("The environment variable SUBSTREAMS_API_KEY must be set and contain a valid Substream API token.");
-        exit(1);
+    match cli.command {
+        Command::Sink {
+            output_dir,
+            range,
+            jobs_file,
+            workers,
+            yes,
+            overwrite,
+            output,
+            stream,
+        } => {
+            let destination = match &output {
+                Some(output) => output.clone(),
+                None => output_dir.to_string_lossy().into_owned(),
+            };
+            if let Some(jobs_file) = jobs_file {
+                let ranges = jobs::load(&jobs_file)?;
+                return build_ranges(
+                    &output_dir.join(JOBS_DIR),
+                    &destination,
+                    &ranges,
+                    workers as usize,
+                    yes,
+                    &stream,
+                    chain,
+                    unknown_entries,
+                    encryption_key.as_ref(),
+                )
+                .await;
+            }
+            let range = range.ok_or(format_err!("no range given"))?;
+            if workers > 1 {
+                range.blocks(chain)?;
+                confirm_range(range, yes)?;
+                let ranges = if overwrite {
+                    vec![range]
+                } else {
+                    EraFiles::scan(&output_dir, chain)?.gaps(range)
+                };
+                let chunk_eras = match env::var("WORKER_CHUNK_ERAS") {
+                    Ok(eras) => eras
+                        .parse::<u64>()
+                        .context("WORKER_CHUNK_ERAS is not a valid integer")?
+                        .max(1),
+                    Err(_) => DEFAULT_WORKER_CHUNK_ERAS,
+                };
+                return build_ranges(
+                    &output_dir.join(WORKERS_DIR),
+                    &destination,
+                    &split_ranges(&ranges, chunk_eras),
+                    workers as usize,
+                    true,
+                    &stream,
+                    chain,
+                    unknown_entries,
+                    encryption_key.as_ref(),
+                )
+                .await;
+            }
+            sink(
+                &output_dir,
+                range,
+                yes,
+                overwrite,
+                &stream,
+                chain,
+                unknown_entries,
+                encryption_key.as_ref(),
+                open_sink(output, &output_dir)?,
+            )
+            .await
+        }
+        Command::FillGaps {
+            output_dir,
+            range,
+            list,
+            workers,
+            yes,
+            stream,
+        } => {
+            fill_gaps(
+                &output_dir,
+                range,
+                list,
+                workers as usize,
+                yes,
+                &stream,
+                chain,
+                unknown_entries,
+                encryption_key.as_ref(),
+            )
+            .await
+        }
+        Command::FinalizePending { output_dir, output } => {
+            let header_accumulator_values = load_accumulator_values(chain).await?;
+            finalize_pending(
+                &output_dir,
+                chain,
+                &header_accumulator_values,
+                file_namer(chain)?.as_ref(),
+                &OutputPermissions::from_env()?,
+                unknown_entries,
+                encryption_key.as_ref(),
+                open_sink(output, &output_dir)?.as_ref(),
+            )
+            .await
+        }
+        Command::Doctor {
+            output_dir,
+            range,
+            stream,
+        } => {
+            if !doctor::run(&output_dir, range, &stream, chain).await? {
+                exit(1);
+            }
+            Ok(())
+        }
+        Command::Verify {
+            files,
+            against_rpc,
+            epoch,
+            samples,
+            exhaustive,
+        } => {
+            let budget = DecompressionBudget::from_env()?;
+            let (beacon_files, files): (Vec<PathBuf>, Vec<PathBuf>) =
+                files.into_iter().partition(|file| is_beacon_era_file(file));
+            let mut failed = false;
+            for file in &beacon_files {
+                let report = verify_beacon_era_file(
+                    file,
+                    unknown_entries,
+                    encryption_key.as_ref(),
+                    &budget,
+                )?;
+                if report.passed() {
+                    println!(
+                        "[ok]   {}: era {}, {} blocks",
+                        file.display(),
+                        report.era,
+                        report.blocks
+                    );
+                } else {
+                    failed = true;
+                    println!("[FAIL] {}", file.display());
+                    for problem in report.problems {
+                        println!("       {}", problem);
+                    }
+                }
+            }
+            let reports =
+                verify_era_files(&files, unknown_entries, encryption_key.as_ref(), &budget);
+            for (file, report) in files.iter().zip(reports) {
+                let report = report?;
+                if report.passed() {
+                    println!(
+                        "[ok]   {}: blocks {} to {}",
+                        file.display(),
+                        report.starting_number,
+                        report.starting_number + report.blocks.saturating_sub(1)
+                    );
+                } else {
+                    failed = true;
+                    println!("[FAIL] {}", file.display());
+                    for problem in report.problems {
+                        println!("       {}", problem);
+                    }
+                }
+            }
+            println!("Decompression: {}", budget);
+            if let (Some(url), Some(epoch)) = (against_rpc, epoch) {
+                let report = rpc_check::check_epoch(
+                    &url,
+                    epoch,
+                    &files,
+                    (!exhaustive).then_some(samples),
+                    unknown_entries,
+                    encryption_key.as_ref(),
+                )
+                .await?;
+                if report.problems.is_empty() {
+                    println!(
+                        "[ok]   {}: {} blocks match {}",
+                        report.file.display(),
+                        report.checked,
+                        url
+                    );
+                } else {
+                    failed = true;
+                    println!(
+                        "[FAIL] {}: {} blocks compared with {}",
+                        report.file.display(),
+                        report.checked,
+                        url
+                    );
+                    for problem in report.problems {
+                        println!("       {}", problem);
+                    }
+                }
+            }
+            if failed {
+                exit(1);
+            }
+            Ok(())
+        }
+        Command::Accumulators(AccumulatorsCommand::Extract { era_dir }) => {
+            for value in header_accumulator::extract_values(
+                &era_dir,
+                unknown_entries,
+                encryption_key.as_ref(),
+            )? {
+                println!("{}", value);
+            }
+            Ok(())
+        }
+        Command::Accumulators(AccumulatorsCommand::Verify { era_dir }) => {
+            let source =
+                env::var("HEADER_ACCUMULATOR_SOURCE").unwrap_or_else(|_| "embedded".to_string());
+            let values = ValueList::load(&source).await?;
+            let budget = DecompressionBudget::from_env()?;
+            let report = header_accumulator::verify_values(
+                &values,
+                &era_dir,
+                unknown_entries,
+                encryption_key.as_ref(),
+                &budget,
+            )?;
+            println!("Decompression: {}", budget);
+            println!(
+                "{} epochs match, {} differ, {} could not be checked",
+                report.matching, report.mismatching, report.unchecked
+            );
+            if report.mismatching > 0 {
+                exit(1);
+            }
+            Ok(())
+        }
+        Command::Accumulators(AccumulatorsCommand::Generate {
+            range,
+            values_file,
+            stream,
+        }) => generate_accumulators(&stream, chain, range.blocks(chain)?, &values_file).await,
+        Command::Compare {
+            expected,
+            actual,
+            max_differences,
+        } => {
+            let comparison =
+                compare_era_files(&expected, &actual, encryption_key.as_ref(), max_differences)?;
+            if comparison.identical() {
+                println!(
+                    "[ok]   {} and {} are identical, {} entries",
+                    expected.display(),
+                    actual.display(),
+                    comparison.entries
+                );
+                return Ok(());
+            }
+            println!(
+                "[FAIL] {} and {} differ",
+                expected.display(),
+                actual.display()
+            );
+            if comparison.by_type.is_empty() {
+                println!(
+                    "       all {} entries match, the files differ in reserved fields or trailing bytes",
+                    comparison.entries
+                );
+            }
+            for (type_, count) in &comparison.by_type {
+                println!("       {} {} entries differ", count, type_);
+            }
+            for difference in &comparison.differences {
+                println!("       {}", difference);
+            }
+            exit(1);
+        }
+        Command::Cat {
+            files,
+            from,
+            to,
+            output,
+        } => {
+            let blocks = match output {
+                Some(output) => {
+                    let mut out = BufWriter::new(
+                        File::create(&output).context(format!("create '{}'", output.display()))?,
+                    );
+                    cat::write_blocks(
+                        &files,
+                        from,
+                        to,
+                        unknown_entries,
+                        encryption_key.as_ref(),
+                        &mut out,
+                    )?
+                }
+                None => cat::write_blocks(
+                    &files,
+                    from,
+                    to,
+                    unknown_entries,
+                    encryption_key.as_ref(),
+                    &mut std::io::stdout().lock(),
+                )?,
+            };
+            eprintln!("Wrote {} blocks", blocks);
+            Ok(())
+        }
+        Command::Math(command) => {
+            let source =
+                env::var("HEADER_ACCUMULATOR_SOURCE").unwrap_or_else(|_| "embedded".to_string());
+            let values = match source.as_str() {
+                "computed" => None,
+                "embedded" if !chain.embedded_accumulators => None,
+                source => Some(ValueList::load(source).await?),
+            };
+            let query = match command {
+                MathCommand::Block { number } => math::Query::Block(number),
+                MathCommand::Epoch { epoch } => math::Query::Epoch(epoch),
+                MathCommand::File { name } => math::Query::File(name),
+            };
+            math::run(query, chain, values.as_ref(), file_namer(chain)?.as_ref())
+        }
     }
+}
 
-    let api_key: Option<String> = Some(api_key);
+/// Loads the accumulator values from HEADER_ACCUMULATOR_SOURCE, the ones
+/// embedded in the binary by default. With `computed`, roots are computed
+/// from the blocks instead, and checked against the embedded values when
+/// CROSS_CHECK_ACCUMULATORS is set.
+async fn load_accumulator_values(chain: ChainConfig) -> Result<AccumulatorRoots, Error> {
+    let accumulator_source =
+        env::var("HEADER_ACCUMULATOR_SOURCE").unwrap_or_else(|_| "embedded".to_string());
+    if accumulator_source == "computed" {
+        let cross_check = match env::var("CROSS_CHECK_ACCUMULATORS") {
+            Ok(cross_check) => cross_check
+                .parse::<bool>()
+                .context("CROSS_CHECK_ACCUMULATORS must be true or false")?,
+            Err(_) => false,
+        };
+        if cross_check && !chain.embedded_accumulators {
+            return Err(format_err!(
+                "no accumulator values are embedded for {} to cross-check against",
+                chain.network
+            ));
+        }
+        let values = match cross_check {
+            true => Some(ValueList::embedded()?),
+            false => None,
+        };
+        match &values {
+            Some(values) => println!(
+                "Computing header accumulator values, cross-checked against {}",
+                values.describe()
+            ),
+            None => println!("Computing header accumulator values from the blocks"),
+        }
 
-    let package = read_package(&PACKAGE_FILE).await?;
-    let block_range = read_block_range()?;
-    let endpoint = Arc::new(SubstreamsEndpoint::new(&ENDPOINT_URL, api_key).await?);
+        return Ok(AccumulatorRoots {
+            values,
+            computed: true,
+            cross_check,
+        });
+    }
+    if accumulator_source == "embedded" && !chain.embedded_accumulators {
+        return Err(format_err!(
+            "no accumulator values are embedded for {}, set HEADER_ACCUMULATOR_SOURCE",
+            chain.network
+        ));
+    }
+    let header_accumulator_values = ValueList::load(&accumulator_source).await?;
+    println!(
+        "Using header accumulator values from {}",
+        header_accumulator_values.describe()
+    );
 
-    let cursor: Option<String> = load_persisted_cursor()?;
+    Ok(AccumulatorRoots {
+        values: Some(header_accumulator_values),
+        computed: false,
+        cross_check: false,
+    })
+}
 
-    let mut stream = SubstreamsStream::new(
-        endpoint.clone(),
-        cursor,
-        package.modules.clone(),
-        MODULE_NAME.to_string(),
-        block_range.0,
-        block_range.1,
-    );
+fn file_namer(chain: ChainConfig) -> Result<Box<dyn FileNamer>, Error> {
+    let naming_scheme = match env::var("ERA_FILE_NAMING") {
+        Ok(scheme) => scheme.parse::<NamingScheme>()?,
+        Err(_) => NamingScheme::Spec,
+    };
 
-    let header_accumulator_values = header_accumulator::read_values();
+    Ok(naming_scheme.namer(chain.network))
+}
 
-    let mut writer = std::fs::File::create(format!(
-        "{}/era-{}.era1",
-        output_dir,
-        get_epoch(block_range.0 as u64)
-    ))?;
-    let mut builder = EraBuilder::new(writer.try_clone()?);
+/// Streams the eras of `range` into era files in `output_dir`, resuming from
+/// the cursor persisted there if any.
+#[allow(clippy::too_many_arguments)]
+async fn sink(
+    output_dir: &Path,
+    range: EraRange,
+    assume_yes: bool,
+    overwrite: bool,
+    stream_args: &StreamArgs,
+    chain: ChainConfig,
+    unknown_entries: UnknownEntryPolicy,
+    encryption_key: Option<&EncryptionKey>,
+    remote: Option<Sink>,
+) -> Result<(), Error> {
+    let header_accumulator_values = load_accumulator_values(chain).await?;
+    let missing_values = match env::var("MISSING_ACCUMULATOR") {
+        Ok(policy) => policy.parse::<MissingValuePolicy>()?,
+        Err(_) => MissingValuePolicy::Fail,
+    };
+    let namer = file_namer(chain)?;
+    let permissions = OutputPermissions::from_env()?;
+    let cid_settings = CidSettings::from_env()?;
+    let entry_limits = EntryLimits::from_env()?;
+
+    let block_range = range.blocks(chain)?;
+    if let (false, Some(values)) = (
+        header_accumulator_values.computed,
+        &header_accumulator_values.values,
+    ) {
+        header_accumulator::validate_range(
+            values,
+            block_range.0 as u64,
+            block_range.1,
+            missing_values,
+        )?;
+    }
+    confirm_range(range, assume_yes)?;
+    let commit_policy = match env::var("CURSOR_COMMIT_POLICY") {
+        Ok(policy) => policy.parse::<CommitPolicy>()?,
+        Err(_) => CommitPolicy::PerEra,
+    };
+    let total_difficulty = total_difficulty::load(
+        &env::var("TOTAL_DIFFICULTY_SOURCE").unwrap_or_else(|_| "header".to_string()),
+    )?;
+    let mut uploads = match remote {
+        Some(remote) => {
+            cursor::restore(output_dir, &remote).await?;
+            let leftovers = finished_files(output_dir, chain.network)?;
+            if !leftovers.is_empty() {
+                println!(
+                    "Moving {} finished files left by an earlier run to {}",
+                    leftovers.len(),
+                    remote.describe()
+                );
+                remote.publish(&leftovers).await?;
+            }
+            Some(UploadQueue::new(
+                remote,
+                UploadSettings::from_env()?,
+                cursor::CURSOR_FILE,
+            ))
+        }
+        None => None,
+    };
+    let persisted_cursor = cursor::load(output_dir)?;
+    let start_block = match &persisted_cursor {
+        Some(persisted) => persisted.block_number + 1,
+        None if overwrite => block_range.0 as u64,
+        None => {
+            let complete = EraFiles::scan(output_dir, chain)?.complete_prefix(range);
+            if complete > 0 {
+                println!(
+                    "Skipping eras {} to {}, their files in {} are complete, pass --overwrite to write them again",
+                    range.start,
+                    range.start + complete - 1,
+                    output_dir.display()
+                );
+            }
+            first_block(range.start + complete)
+        }
+    };
+    if start_block < block_range.0 as u64 || start_block > block_range.1 {
+        return Err(format_err!(
+            "persisted cursor at block {} is outside the requested range, remove {} to start over",
+            start_block - 1,
+            cursor::path(output_dir).display()
+        ));
+    }
+    if start_block == block_range.1 {
+        println!("Requested range was already streamed");
+        return completeness::check_range(
+            output_dir,
+            chain,
+            range,
+            uploads.as_ref().map(UploadQueue::sink),
+            missing_values == MissingValuePolicy::Defer,
+        )
+        .await;
+    }
+
+    let tee = Tee::from_env();
+    let uncle_source = match env::var("UNCLE_HEADER_SOURCE") {
+        Ok(url) => Some(UncleSource::new(url, HttpClient::from_env()?)),
+        Err(_) => None,
+    };
+    let receipt_source = match env::var("RECEIPT_SOURCE") {
+        Ok(url) => {
+            let cache_dir = env::var_os("RECEIPT_CACHE_DIR").map(PathBuf::from);
+            if let Some(cache_dir) = &cache_dir {
+                std::fs::create_dir_all(cache_dir)?;
+            }
+            Some(ReceiptSource::new(url, HttpClient::from_env()?, cache_dir))
+        }
+        Err(_) => None,
+    };
+    let (mut stream, source) = open_stream(
+        stream_args,
+        chain,
+        persisted_cursor.map(|persisted| persisted.cursor),
+        start_block,
+        block_range,
+    )
+    .await?;
+
+    warn_about_foreign_names(output_dir, chain.network, |name| {
+        namer.parse_epoch(name).is_some()
+    })?;
+
+    // Eras are written under a temporary name and only get their final name
+    // once finalized, so a file under a final name is always complete.
+    let era_path = |epoch: u64| output_dir.join(in_progress_file_name(chain.network, epoch));
+    let mut era_file = era_path(get_epoch(start_block));
+    let resumed = !is_epoch_start(start_block);
+    let keep = if resumed {
+        vec![era_file.clone(), provenance::in_progress_path(&era_file)?]
+    } else {
+        Vec::new()
+    };
+    remove_stale_files(output_dir, &keep)?;
+    if resumed && !era_file.exists() {
+        // Earlier versions wrote the era in progress under its final or
+        // pending name.
+        let epoch = get_epoch(start_block);
+        let earlier = header_accumulator_values
+            .values
+            .as_ref()
+            .and_then(|values| values.value_for_epoch(epoch))
+            .map(|root| output_dir.join(namer.file_name(epoch, &root)))
+            .into_iter()
+            .chain([output_dir.join(pending_file_name(chain.network, epoch))])
+            .find(|path| path.exists());
+        if let Some(earlier) = earlier {
+            std::fs::rename(&earlier, &era_file)?;
+        }
+    }
+    let (mut writer, mut builder) = if is_epoch_start(start_block) {
+        let writer = File::create(&era_file)?;
+        let mut builder = EraBuilder::new(writer.try_clone()?, chain, total_difficulty);
+        builder.start_era(start_block)?;
+        (writer, builder)
+    } else {
+        let epoch = get_epoch(start_block);
+        let writer = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&era_file)
+            .context("reopen partially written era file")?;
+        let builder = EraBuilder::resume(
+            writer.try_clone()?,
+            chain,
+            total_difficulty,
+            first_block(epoch),
+            start_block - 1,
+            unknown_entries,
+        )?;
+        (writer, builder)
+    };
+    builder.set_limits(entry_limits);
+    let mut provenance = Provenance::new(source);
+    provenance.start_era(&era_file)?;
+    let mut last_progress_report = Instant::now();
+    #[cfg(feature = "profiling")]
+    let mut era_allocations = profiling::EraAllocations::new();
+    let budget = DecompressionBudget::from_env()?;
+    let mut shutdown = Shutdown::listen()?;
+    // Cursor of the last block written, while it isn't persisted yet.
+    let mut unsaved_cursor = None;
     loop {
-        match process_iteration(&mut stream, &mut builder, header_accumulator_values.clone()).await
+        let started = Instant::now();
+        let response = tokio::select! {
+            response = stream.next() => Ok(response),
+            signal = shutdown.requested() => Err(signal),
+        };
+        builder.timings_mut().receive += started.elapsed();
+        let response = match response {
+            Ok(response) => response,
+            Err(signal) => {
+                println!("Received {}, saving the era in progress", signal);
+                writer.sync_data()?;
+                if let Some(cursor) = unsaved_cursor.take() {
+                    cursor::persist(output_dir, &cursor)?;
+                }
+                if let Some(uploads) = uploads.as_mut() {
+                    println!(
+                        "Waiting for uploads to finish, send {} again to leave them to the next run",
+                        signal
+                    );
+                    tokio::select! {
+                        result = uploads.finish() => result?,
+                        _ = shutdown.requested() => {}
+                    }
+                }
+
+                return Err(Interrupted(signal).into());
+            }
+        };
+        match process_iteration(
+            response,
+            &mut builder,
+            &header_accumulator_values,
+            &mut last_progress_report,
+            &tee,
+            uncle_source.as_ref(),
+            receipt_source.as_ref(),
+            &mut provenance,
+            missing_values,
+        )
+        .await
         {
-            Ok(finished_era) => {
+            Ok(None) => {}
+            Ok(Some((finished_era, cursor))) => {
+                let mut published = Vec::new();
                 if finished_era {
-                    writer = std::fs::File::create(format!(
-                        "{}/era-{}.era1",
-                        output_dir,
-                        get_epoch(builder.starting_number as u64 + EPOCH_SIZE)
-                    ))?;
+                    permissions.apply(&writer)?;
+                    writer.sync_data()?;
+                    let epoch = get_epoch(
+                        builder
+                            .starting_number()
+                            .ok_or(format_err!("no era in progress"))?,
+                    );
+                    let finished = match builder.header_accumulator() {
+                        Some(root) => output_dir.join(namer.file_name(epoch, &root)),
+                        None => output_dir.join(pending_file_name(chain.network, epoch)),
+                    };
+                    if builder.digest().is_some() {
+                        verify_finalized(&era_file, unknown_entries, &budget).with_epoch(epoch)?;
+                    }
+                    std::fs::rename(&era_file, &finished)?;
+                    era_file = finished;
+                    let provenance_file = provenance.finish(&era_file)?;
+                    if let Some(digest) = builder.digest() {
+                        let checksum = write_checksum(&era_file, &digest)?;
+                        let finished = match encryption_key {
+                            Some(key) => {
+                                let encrypted = key.encrypt_file(&era_file)?;
+                                permissions.apply(&File::open(&encrypted)?)?;
+                                encrypted
+                            }
+                            None => era_file.clone(),
+                        };
+                        let cid = match cid_settings {
+                            Some(settings) => Some(write_cid(&finished, settings)?),
+                            None => None,
+                        };
+                        published = [Some(finished), Some(checksum), cid, Some(provenance_file)]
+                            .into_iter()
+                            .flatten()
+                            .collect();
+                    }
+                }
+                if finished_era || commit_policy == CommitPolicy::PerBlock {
+                    writer.sync_data()?;
+                    cursor::persist(output_dir, &cursor)?;
+                    unsaved_cursor = None;
+                } else {
+                    unsaved_cursor = Some(cursor);
+                }
+                if let (true, Some(uploads)) = (finished_era, uploads.as_mut()) {
+                    uploads
+                        .push(
+                            std::mem::take(&mut published),
+                            std::fs::read(cursor::path(output_dir))?,
+                        )
+                        .await?;
+                    let (queued, uploading) = uploads.depth();
+                    println!(
+                        "Uploads: {} eras waiting to be stored, {} uploading",
+                        queued, uploading
+                    );
+                }
+
+                if finished_era {
+                    let starting_number = builder
+                        .starting_number()
+                        .ok_or(format_err!("no era in progress"))?;
+                    #[cfg(feature = "profiling")]
+                    era_allocations.report(get_epoch(starting_number));
+                    println!(
+                        "Era {} timings: {}",
+                        get_epoch(starting_number),
+                        builder.timings()
+                    );
+
+                    let next_starting_number = starting_number + EPOCH_SIZE;
+                    if next_starting_number >= block_range.1 {
+                        break;
+                    }
+
+                    era_file = era_path(get_epoch(next_starting_number));
+                    writer = File::create(&era_file)?;
                     builder.reset(writer.try_clone()?);
+                    builder.start_era(next_starting_number)?;
+                    provenance.start_era(&era_file)?;
                 }
             }
             Err(err) => {
-                if !err.to_string().is_empty() {
-                    println!("Error: {}", err);
+                // The stream ending is reported as an empty error.
+                if err.to_string().is_empty() {
+                    break;
                 }
 
+                return Err(err);
+            }
+        }
+    }
+    if let Some(uploads) = uploads.as_mut() {
+        uploads.finish().await?;
+    }
+
+    completeness::check_range(
+        output_dir,
+        chain,
+        range,
+        uploads.as_ref().map(UploadQueue::sink),
+        missing_values == MissingValuePolicy::Defer,
+    )
+    .await
+}
+
+/// Lists the eras of `range` without a complete file in `output_dir` and,
+/// unless `list_only`, streams each run of consecutive missing eras. Corrupt
+/// files are renamed with a `.corrupt` suffix first. Runs are built in a work
+/// directory with its own cursor, so the cursor of `output_dir` is left alone,
+/// and each finished era is moved into `output_dir`.
+#[allow(clippy::too_many_arguments)]
+async fn fill_gaps(
+    output_dir: &Path,
+    range: EraRange,
+    list_only: bool,
+    workers: usize,
+    assume_yes: bool,
+    stream_args: &StreamArgs,
+    chain: ChainConfig,
+    unknown_entries: UnknownEntryPolicy,
+    encryption_key: Option<&EncryptionKey>,
+) -> Result<(), Error> {
+    // Checks that the range doesn't go past the last era of the chain.
+    range.blocks(chain)?;
+    let files = EraFiles::scan(output_dir, chain)?;
+    for epoch in range.start..=range.stop {
+        match files.get(epoch) {
+            EraFile::Complete => {}
+            EraFile::Missing => println!("Era {}: missing", epoch),
+            EraFile::Corrupt(paths) => {
+                for path in paths {
+                    println!("Era {}: corrupt, {}", epoch, path.display());
+                }
+            }
+        }
+    }
+    let gaps = files.gaps(range);
+    if gaps.is_empty() {
+        println!("Eras {} to {} are complete", range.start, range.stop);
+        return Ok(());
+    }
+    if list_only {
+        return Ok(());
+    }
+
+    for epoch in range.start..=range.stop {
+        if let EraFile::Corrupt(paths) = files.get(epoch) {
+            for path in paths {
+                let mut corrupt = path.as_os_str().to_owned();
+                corrupt.push(".corrupt");
+                std::fs::rename(path, &corrupt)?;
+            }
+        }
+    }
+
+    build_ranges(
+        &output_dir.join(FILL_GAPS_DIR),
+        &output_dir.to_string_lossy(),
+        &gaps,
+        workers,
+        assume_yes,
+        stream_args,
+        chain,
+        unknown_entries,
+        encryption_key,
+    )
+    .await
+}
+
+/// Streams `ranges` in their own directory inside `work_dir`, each with its
+/// own cursor, and moves each finished era to `destination`, whose cursor is
+/// left alone. Up to `workers` ranges are streamed at the same time, each over
+/// its own connection, started in the order of `ranges`. An interrupted run
+/// resumes each range where it stopped.
+#[allow(clippy::too_many_arguments)]
+async fn build_ranges(
+    work_dir: &Path,
+    destination: &str,
+    ranges: &[EraRange],
+    workers: usize,
+    assume_yes: bool,
+    stream_args: &StreamArgs,
+    chain: ChainConfig,
+    unknown_entries: UnknownEntryPolicy,
+    encryption_key: Option<&EncryptionKey>,
+) -> Result<(), Error> {
+    let build = |range: EraRange| async move {
+        let range_dir = work_dir.join(format!("{}-{}", range.start, range.stop));
+        std::fs::create_dir_all(&range_dir)?;
+        println!("Writing eras {} to {}", range.start, range.stop);
+        let destination =
+            Sink::from_url(destination, &range_dir, &HttpSettings::from_env()?)?.without_cursor();
+        sink(
+            &range_dir,
+            range,
+            assume_yes,
+            true,
+            stream_args,
+            chain,
+            unknown_entries,
+            encryption_key,
+            Some(destination),
+        )
+        .await
+    };
+
+    let mut pending = ranges.iter().copied().enumerate();
+    let mut running = FuturesUnordered::new();
+    // Ranges finish in any order, they are reported in the order of `ranges`.
+    let mut finished = InOrder::starting_at(0);
+    loop {
+        while running.len() < workers {
+            let Some((index, range)) = pending.next() else {
                 break;
+            };
+            running.push(async move { build(range).await.map(|()| (index, range)) });
+        }
+        let Some(result) = running.next().await else {
+            break;
+        };
+        let (index, range) = match result {
+            Ok(finished) => finished,
+            Err(err) if err.is::<Interrupted>() => {
+                // The other workers got the signal too, let them save their
+                // eras before leaving.
+                while running.next().await.is_some() {}
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
+        finished.insert(index as u64, range)?;
+        while let Some(range) = finished.pop() {
+            if workers > 1 {
+                println!(
+                    "Eras {} to {} are done, {} of {} ranges, {} more done out of order",
+                    range.start,
+                    range.stop,
+                    finished.next_sequence(),
+                    ranges.len(),
+                    finished.waiting()
+                );
+            }
+        }
+    }
+    if work_dir.exists() {
+        std::fs::remove_dir_all(work_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Cuts `ranges` into ranges of at most `chunk_eras` eras, for workers.
+fn split_ranges(ranges: &[EraRange], chunk_eras: u64) -> Vec<EraRange> {
+    ranges
+        .iter()
+        .flat_map(|range| {
+            (range.start..=range.stop)
+                .step_by(chunk_eras as usize)
+                .map(move |start| EraRange {
+                    start,
+                    stop: (start + chunk_eras - 1).min(range.stop),
+                })
+        })
+        .collect()
+}
+
+/// Asks before streaming a range above `CONFIRMATION_THRESHOLD_ERAS` eras,
+/// unless `assume_yes`, and exits if the answer is no.
+fn confirm_range(range: EraRange, assume_yes: bool) -> Result<(), Error> {
+    let confirmation_threshold = match env::var("CONFIRMATION_THRESHOLD_ERAS") {
+        Ok(eras) => eras
+            .parse::<u64>()
+            .context("CONFIRMATION_THRESHOLD_ERAS is not a valid integer")?,
+        Err(_) => DEFAULT_CONFIRMATION_THRESHOLD,
+    };
+    let eras = range.eras();
+    if eras > confirmation_threshold && !assume_yes && !confirm_large_range(eras)? {
+        println!("Aborted");
+        exit(1);
+    }
+
+    Ok(())
+}
+
+/// Opens the Substreams stream of `block_range`, or replays the recording in
+/// `REPLAY_DIR` from `start_block` on when it is set, along with where its
+/// blocks come from.
+async fn open_stream(
+    stream_args: &StreamArgs,
+    chain: ChainConfig,
+    cursor: Option<String>,
+    start_block: u64,
+    block_range: (i64, u64),
+) -> Result<(SubstreamsStream, Source), Error> {
+    if let Some(replay_dir) = env::var_os("REPLAY_DIR") {
+        let recording = PathBuf::from(replay_dir);
+        return Ok((
+            SubstreamsStream::replay(&recording, start_block)?,
+            Source::Replay { recording },
+        ));
+    }
+
+    let receive_timeout = match env::var("STREAM_RECEIVE_TIMEOUT_SECS") {
+        Ok(secs) => Duration::from_secs(
+            secs.parse::<u64>()
+                .context("STREAM_RECEIVE_TIMEOUT_SECS is not a valid integer")?,
+        ),
+        Err(_) => DEFAULT_RECEIVE_TIMEOUT,
+    };
+
+    let credentials = match &stream_args.token {
+        Some(token) => Credentials::Token(token.clone()),
+        None => {
+            let api_key = env::var("SUBSTREAMS_API_KEY").unwrap_or_default();
+            if api_key.is_empty() {
+                println!("The environment variable SUBSTREAMS_API_KEY must be set and contain a valid Substream API token, or SUBSTREAMS_JWT a session token.");
+                exit(1);
+            }
+            Credentials::ApiKey(api_key)
+        }
+    };
+
+    let http = HttpClient::from_env()?;
+    let (package, package_sha256) =
+        read_package(&stream_args.spkg, &http, expected_package_sha256()?).await?;
+    let endpoint_url = stream_args.endpoint_url(chain)?;
+    let endpoint =
+        Arc::new(SubstreamsEndpoint::new(&endpoint_url, Some(credentials), &http).await?);
+
+    let stream = SubstreamsStream::new(
+        endpoint.clone(),
+        cursor,
+        package.modules.clone(),
+        stream_args.module.clone(),
+        block_range.0,
+        block_range.1,
+        receive_timeout,
+    );
+
+    Ok((
+        stream,
+        Source::Substreams {
+            endpoint: endpoint_url,
+            package_sha256,
+            module: stream_args.module.clone(),
+        },
+    ))
+}
+
+/// Streams `block_range` and appends the accumulator root of each of its
+/// epochs to `values_file`, one per line like `accumulators extract` prints
+/// them.
+///
+/// Only the header of each block is decoded. Bodies and receipts are never
+/// mapped, encoded or compressed, and nothing else is written, which makes
+/// this far faster than writing era files when only the roots are needed.
+async fn generate_accumulators(
+    stream_args: &StreamArgs,
+    chain: ChainConfig,
+    block_range: (i64, u64),
+    values_file: &Path,
+) -> Result<(), Error> {
+    let mut total_difficulty = total_difficulty::load(
+        &env::var("TOTAL_DIFFICULTY_SOURCE").unwrap_or_else(|_| "header".to_string()),
+    )?;
+    let mut values = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(values_file)
+        .context(format!("open '{}'", values_file.display()))?;
+    let mut stream = open_stream(stream_args, chain, None, block_range.0 as u64, block_range)
+        .await?
+        .0;
+    let mut accumulator = EpochAccumulator::default();
+    let mut last_progress_report = Instant::now();
+    while let Some(response) = stream.next().await {
+        match response.stage("stream")? {
+            BlockResponse::New(data) => {
+                let block_number = data.clock.as_ref().map_or(0, |clock| clock.number);
+                add_header(&data, &mut accumulator, total_difficulty.as_mut())
+                    .with_block(block_number)?;
+
+                if chain.is_era_end(block_number) {
+                    let epoch = get_epoch(block_number);
+                    if accumulator.len() as u64 != chain.era_size(epoch) {
+                        return Err(format_err!(
+                            "epoch {} ended after {} blocks",
+                            epoch,
+                            accumulator.len()
+                        ));
+                    }
+                    writeln!(values, "{}", hex::encode(accumulator.finish()))?;
+                    println!("Epoch {} accumulator computed", epoch);
+                }
+            }
+            BlockResponse::Progress(progress) => {
+                if last_progress_report.elapsed() >= PROGRESS_REPORT_INTERVAL {
+                    println!("Progress {}", describe_progress(&progress));
+                    if let Some(throttle) = Throttle::global().describe() {
+                        println!("Throttle: {}", throttle);
+                    }
+                    last_progress_report = Instant::now();
+                }
+            }
+            BlockResponse::Session(_) => {}
+            BlockResponse::Undo(_) => {
+                return Err(anyhow::anyhow!("Error, undo signal not supported"))
             }
         }
     }
@@ -95,103 +1167,462 @@ async fn main() -> Result<(), Error> {
     Ok(())
 }
 
+fn add_header(
+    data: &BlockScopedData,
+    accumulator: &mut EpochAccumulator,
+    total_difficulty: &mut dyn TotalDifficultyProvider,
+) -> Result<(), Error> {
+    let output = data
+        .output
+        .as_ref()
+        .and_then(|output| output.map_output.as_ref())
+        .ok_or(anyhow::anyhow!("No module output"))
+        .stage("read-output")?;
+    let block = HeaderOnlyBlock::decode(output.value.as_slice()).stage("decode-header")?;
+    let block = Block::try_from(block).stage("convert-block")?;
+
+    let block_hash: [u8; 32] = block
+        .hash
+        .as_slice()
+        .try_into()
+        .map_err(|_| format_err!("block hash is {} bytes long", block.hash.len()))?;
+    let total_difficulty: [u8; 32] = encode_bigint(total_difficulty.total_difficulty(&block)?)
+        .try_into()
+        .map_err(|_| format_err!("total difficulty is too large"))?;
+    accumulator.add(&block_hash, &total_difficulty);
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn process_iteration<W: Write>(
-    stream: &mut SubstreamsStream,
+    response: Option<Result<BlockResponse, Error>>,
     builder: &mut EraBuilder<W>,
-    header_accumulator_values: Vec<String>,
-) -> Result<bool, anyhow::Error> {
-    match stream.next().await {
+    header_accumulator_values: &AccumulatorRoots,
+    last_progress_report: &mut Instant,
+    tee: &Tee,
+    uncle_source: Option<&UncleSource>,
+    receipt_source: Option<&ReceiptSource>,
+    provenance: &mut Provenance,
+    missing_values: MissingValuePolicy,
+) -> Result<Option<(bool, Cursor)>, anyhow::Error> {
+    match response {
         None => Err(anyhow::anyhow!("")),
         Some(Ok(BlockResponse::New(data))) => {
-            process_block_scoped_data(&data, builder)?;
+            let cursor = Cursor {
+                cursor: data.cursor.clone(),
+                block_number: data.clock.as_ref().map_or(0, |clock| clock.number),
+            };
+            tee.write(&data).with_block(cursor.block_number)?;
+            process_block_scoped_data(&data, builder, uncle_source, receipt_source, provenance)
+                .await
+                .with_block(cursor.block_number)?;
 
-            if builder.len() == EPOCH_SIZE as usize {
-                match header_accumulator::get_value_for_block(
-                    &header_accumulator_values,
-                    builder.starting_number as u64,
-                ) {
+            if builder.is_complete() {
+                let starting_number = builder
+                    .starting_number()
+                    .ok_or(anyhow::anyhow!("no era in progress"))?;
+                let epoch = get_epoch(starting_number);
+                match header_accumulator_values
+                    .root_for_era(starting_number, builder.accumulator_root())
+                    .with_epoch(epoch)?
+                {
                     Some(value) => {
-                        let header_accumulator_value = hex::decode(value)?;
-                        builder.finalize(header_accumulator_value)?;
+                        builder
+                            .finalize(value)
+                            .stage("finalize")
+                            .with_epoch(epoch)?;
+
+                        Ok(Some((true, cursor)))
+                    }
+                    None if missing_values == MissingValuePolicy::Defer => {
+                        println!(
+                            "Era {} has no header accumulator value yet, left pending until finalize-pending is run",
+                            epoch
+                        );
 
-                        Ok(true)
+                        Ok(Some((true, cursor)))
                     }
                     None => Err(anyhow::anyhow!(
-                        "Error, no header acc value found for block: {}",
-                        builder.starting_number
+                        "no header accumulator value for epoch {}, set MISSING_ACCUMULATOR=defer to keep its blocks in a pending file",
+                        epoch
                     )),
                 }
             } else {
-                Ok(false)
+                Ok(Some((false, cursor)))
             }
         }
+        Some(Ok(BlockResponse::Session(session))) => {
+            println!(
+                "Session {} started, resolved start block {}, linear handoff at block {}",
+                session.trace_id, session.resolved_start_block, session.linear_handoff_block
+            );
+            provenance.session(&session.trace_id);
+
+            Ok(None)
+        }
+        Some(Ok(BlockResponse::Progress(progress))) => {
+            if last_progress_report.elapsed() >= PROGRESS_REPORT_INTERVAL {
+                println!("Progress {}", describe_progress(&progress));
+                if let Some(throttle) = Throttle::global().describe() {
+                    println!("Throttle: {}", throttle);
+                }
+                *last_progress_report = Instant::now();
+            }
+
+            Ok(None)
+        }
         Some(Ok(BlockResponse::Undo(_))) => {
             Err(anyhow::anyhow!("Error, undo signal not supported"))
         }
-        Some(Err(err)) => Err(anyhow::anyhow!(
-            "Error, stream terminated with error, {}",
-            err
-        )),
+        Some(Err(err)) => Err(err).stage("stream"),
     }
 }
 
-fn process_block_scoped_data<W: Write>(
+async fn process_block_scoped_data<W: Write>(
     data: &BlockScopedData,
     builder: &mut EraBuilder<W>,
+    uncle_source: Option<&UncleSource>,
+    receipt_source: Option<&ReceiptSource>,
+    provenance: &mut Provenance,
 ) -> Result<(), Error> {
-    let output = data.output.as_ref().unwrap().map_output.as_ref().unwrap();
+    let output = data
+        .output
+        .as_ref()
+        .and_then(|output| output.map_output.as_ref())
+        .ok_or(anyhow::anyhow!("No module output"))
+        .stage("read-output")?;
 
-    let block = VerifiableBlock::decode(output.value.as_slice())?;
+    let block = timed(&mut builder.timings_mut().decode, || {
+        VerifiableBlock::decode(output.value.as_slice())
+    })
+    .stage("decode-block")?;
+    let mut block = Block::try_from(block).stage("convert-block")?;
+    provenance
+        .block(block.number, &data.cursor)
+        .stage("provenance")?;
+    let fetched = uncles::complete_uncles(&mut block, uncle_source)
+        .await
+        .stage("complete-uncles")?;
+    if let (true, Some(source)) = (fetched > 0, uncle_source) {
+        provenance
+            .uncles_fetched(block.number, source.url())
+            .stage("provenance")?;
+    }
+    let fetched = receipts::complete_receipts(&mut block, receipt_source)
+        .await
+        .stage("complete-receipts")?;
+    if let (true, Some(source)) = (fetched > 0, receipt_source) {
+        provenance
+            .receipts_fetched(block.number, source.url())
+            .stage("provenance")?;
+    }
     builder.add(block)?;
 
     Ok(())
 }
 
-fn load_persisted_cursor() -> Result<Option<String>, anyhow::Error> {
-    // FIXME: Handling of the cursor is missing here. It should be loaded from
-    // somewhere (local file, database, cloud storage) and then `SubstreamStream` will
-    // be able correctly resume from the right block.
-    Ok(None)
+/// Shows what a range of `eras` eras amounts to and asks whether to go ahead,
+/// so a mistyped range doesn't silently start a months long job.
+fn confirm_large_range(eras: u64) -> Result<bool, anyhow::Error> {
+    println!(
+        "The requested range covers {} era files, roughly {} GiB of disk space.",
+        eras,
+        eras * ESTIMATED_ERA_SIZE / (1024 * 1024 * 1024)
+    );
+    print!("Continue? [y/N] ");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
 }
 
-fn read_block_range() -> Result<(i64, u64), anyhow::Error> {
-    let input: String = env::args().nth(2).expect("Era range not provided");
-    let (prefix, suffix) = match input.split_once(':') {
-        Some((prefix, suffix)) => (prefix.to_string(), suffix.to_string()),
-        None => ("".to_string(), input),
-    };
+/// Completes the pending era files in `output_dir` whose accumulator value
+/// has become available, or is computed from their blocks, and renames them
+/// to their final names.
+#[allow(clippy::too_many_arguments)]
+async fn finalize_pending(
+    output_dir: &Path,
+    chain: ChainConfig,
+    header_accumulator_values: &AccumulatorRoots,
+    namer: &dyn FileNamer,
+    permissions: &OutputPermissions,
+    unknown_entries: UnknownEntryPolicy,
+    encryption_key: Option<&EncryptionKey>,
+    remote: Option<&Sink>,
+) -> Result<(), anyhow::Error> {
+    let cid_settings = CidSettings::from_env()?;
+    let budget = DecompressionBudget::from_env()?;
+    for entry in std::fs::read_dir(output_dir).context("list output directory")? {
+        let path = entry?.path();
+        let Some(epoch) = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| parse_pending_epoch(chain.network, file_name))
+        else {
+            continue;
+        };
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .context(format!("open pending era file '{}'", path.display()))?;
+        let mut builder = EraBuilder::resume(
+            file.try_clone()?,
+            chain,
+            Box::new(FromHeader),
+            first_block(epoch),
+            first_block(epoch) + chain.era_size(epoch) - 1,
+            unknown_entries,
+        )
+        .with_epoch(epoch)?;
+        let Some(root) = header_accumulator_values
+            .root_for_era(first_block(epoch), builder.accumulator_root())
+            .with_epoch(epoch)?
+        else {
+            println!("Era {} still has no header accumulator value", epoch);
+            continue;
+        };
+        builder.finalize(root).stage("finalize").with_epoch(epoch)?;
+        permissions.apply(&file)?;
+        file.sync_data()?;
+        verify_finalized(&path, unknown_entries, &budget).with_epoch(epoch)?;
 
-    let start: i64 = match prefix.as_str() {
-        "" => 0,
-        x => x
-            .parse::<i64>()
-            .context("argument <start> is not a valid integer")?,
-    };
+        let starting_number = check_block_index(
+            &mut BufReader::new(File::open(&path)?),
+            chain.era_size(epoch),
+        )
+        .context(format!("verify finalized era file '{}'", path.display()))?;
+        if starting_number != first_block(epoch) {
+            return Err(format_err!(
+                "finalized era file '{}' starts at block {}, expected {}",
+                path.display(),
+                starting_number,
+                first_block(epoch)
+            ));
+        }
+
+        let finished = output_dir.join(namer.file_name(epoch, &root));
+        std::fs::rename(&path, &finished)?;
+        let pending_provenance = provenance::companion_path(&path);
+        let provenance_file = if pending_provenance.exists() {
+            let provenance_file = provenance::companion_path(&finished);
+            std::fs::rename(&pending_provenance, &provenance_file)?;
+            Some(provenance_file)
+        } else {
+            None
+        };
+        let checksum = match builder.digest() {
+            Some(digest) => Some(write_checksum(&finished, &digest)?),
+            None => None,
+        };
+        let finished = match encryption_key {
+            Some(key) => {
+                let encrypted = key.encrypt_file(&finished)?;
+                permissions.apply(&File::open(&encrypted)?)?;
+                encrypted
+            }
+            None => finished,
+        };
+        println!("Finalized {}", finished.display());
+        let cid = match cid_settings {
+            Some(settings) => Some(write_cid(&finished, settings)?),
+            None => None,
+        };
+        if let Some(remote) = remote {
+            remote
+                .publish(
+                    &[Some(finished), checksum, cid, provenance_file]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>(),
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Finished era files of `network` in `output_dir` and their companion files,
+/// left there when a run stopped before they were uploaded.
+fn finished_files(output_dir: &Path, network: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(output_dir).context("list output directory")? {
+        let path = entry?.path();
+        let Some(mut file_name) = path.file_name().and_then(|file_name| file_name.to_str()) else {
+            continue;
+        };
+        for suffix in [".cid", ".sha256", PROVENANCE_SUFFIX, ENCRYPTED_SUFFIX] {
+            file_name = file_name.strip_suffix(suffix).unwrap_or(file_name);
+        }
+        if path.is_file() && discover_epoch(network, file_name).is_some() {
+            files.push(path);
+        }
+    }
+    files.sort();
 
-    let stop: u64 = suffix
-        .parse::<u64>()
-        .context("argument <stop> is not a valid integer")?;
+    Ok(files)
+}
 
-    let start = start * EPOCH_SIZE as i64;
-    let stop = (stop + 1) * EPOCH_SIZE;
+/// Removes the temporary files an interrupted run left in `output_dir`, except
+/// `keep`, the files of the era a resumed run continues.
+fn remove_stale_files(output_dir: &Path, keep: &[PathBuf]) -> Result<(), anyhow::Error> {
+    for entry in std::fs::read_dir(output_dir).context("list output directory")? {
+        let path = entry?.path();
+        let temporary = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .is_some_and(|file_name| file_name.ends_with(TEMPORARY_SUFFIX));
+        if temporary && !keep.contains(&path) && path.is_file() {
+            println!("Removing stale temporary file {}", path.display());
+            std::fs::remove_file(&path)?;
+        }
+    }
 
-    Ok((start, stop))
+    Ok(())
 }
 
-async fn read_package(input: &str) -> Result<Package, anyhow::Error> {
-    if input.starts_with("http") {
-        return read_http_package(input).await;
+/// Reads the finalized era file at `path` back before it gets its final name,
+/// failing if it breaks the era1 spec.
+fn verify_finalized(
+    path: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    budget: &DecompressionBudget,
+) -> Result<(), anyhow::Error> {
+    let report = verify_era_file(path, unknown_entries, None, budget)?;
+    if !report.passed() {
+        return Err(format_err!(
+            "era file '{}' fails verification: {}",
+            path.display(),
+            report.problems.join("; ")
+        ));
     }
 
-    // Assume it's a local file
+    Ok(())
+}
+
+/// Writes the SHA-256 of `era_file` next to it, in the format `sha256sum -c`
+/// reads.
+fn write_checksum(era_file: &Path, digest: &[u8; 32]) -> Result<PathBuf, anyhow::Error> {
+    let file_name = era_file
+        .file_name()
+        .ok_or(format_err!("'{}' has no file name", era_file.display()))?;
+    let mut checksum_file = era_file.as_os_str().to_owned();
+    checksum_file.push(".sha256");
+    std::fs::write(
+        &checksum_file,
+        format!("{}  {}\n", hex::encode(digest), file_name.to_string_lossy()),
+    )
+    .context(format!("write checksum of '{}'", era_file.display()))?;
+
+    Ok(PathBuf::from(checksum_file))
+}
+
+/// Computes the CID of `file` and writes it next to it, as
+/// `<cid>  <file name>` like the checksum.
+fn write_cid(file: &Path, settings: CidSettings) -> Result<PathBuf, anyhow::Error> {
+    let file_name = file
+        .file_name()
+        .ok_or(format_err!("'{}' has no file name", file.display()))?;
+    let cid = cid::file_cid(file, settings)?;
+    let mut cid_file = file.as_os_str().to_owned();
+    cid_file.push(".cid");
+    std::fs::write(
+        &cid_file,
+        format!("{}  {}\n", cid, file_name.to_string_lossy()),
+    )
+    .context(format!("write CID of '{}'", file.display()))?;
+
+    Ok(PathBuf::from(cid_file))
+}
+
+/// Opens the destination of `--output`, if given.
+fn open_sink(url: Option<String>, output_dir: &Path) -> Result<Option<Sink>, Error> {
+    let Some(url) = url else {
+        return Ok(None);
+    };
+    let sink = Sink::from_url(&url, output_dir, &HttpSettings::from_env()?)?;
+    println!("Moving finished era files to {}", sink.describe());
 
-    let content =
-        std::fs::read(input).context(format_err!("read package from file '{}'", input))?;
-    Package::decode(content.as_ref()).context("decode command")
+    Ok(Some(sink))
 }
 
-async fn read_http_package(input: &str) -> Result<Package, anyhow::Error> {
-    let body = reqwest::get(input).await?.bytes().await?;
+/// Points out era files that belong to another naming scheme, which would
+/// otherwise end up mixed with the files written by this run.
+fn warn_about_foreign_names(
+    output_dir: &Path,
+    network: &str,
+    is_own_name: impl Fn(&str) -> bool,
+) -> Result<(), anyhow::Error> {
+    for entry in std::fs::read_dir(output_dir).context("list output directory")? {
+        let file_name = entry?.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        if !is_own_name(file_name) && discover_epoch(network, file_name).is_some() {
+            println!(
+                "Warning: {} uses a different naming scheme, set ERA_FILE_NAMING to keep names consistent",
+                file_name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The sha256 the package must have, from SUBSTREAMS_PACKAGE_SHA256.
+fn expected_package_sha256() -> Result<Option<[u8; 32]>, anyhow::Error> {
+    let Ok(expected) = env::var("SUBSTREAMS_PACKAGE_SHA256") else {
+        return Ok(None);
+    };
+    let expected = hex::decode(expected.trim().trim_start_matches("0x"))
+        .context("SUBSTREAMS_PACKAGE_SHA256 is not hex")?;
+    let expected = <[u8; 32]>::try_from(expected.as_slice()).map_err(|_| {
+        format_err!(
+            "SUBSTREAMS_PACKAGE_SHA256 is {} bytes long, expected 32",
+            expected.len()
+        )
+    })?;
+
+    Ok(Some(expected))
+}
+
+/// Reads the package from a URL or a local file, along with its sha256,
+/// refusing it if the sha256 isn't `expected_sha256`.
+async fn read_package(
+    input: &str,
+    http: &HttpClient,
+    expected_sha256: Option<[u8; 32]>,
+) -> Result<(Package, [u8; 32]), anyhow::Error> {
+    let content = if input.starts_with("http") {
+        http.send(|client| client.get(input))
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec()
+    } else {
+        // Assume it's a local file
+        std::fs::read(input).context(format_err!("read package from file '{}'", input))?
+    };
+
+    let actual: [u8; 32] = Sha256::digest(&content).into();
+    if let Some(expected) = expected_sha256 {
+        if actual != expected {
+            return Err(format_err!(
+                "package '{}' has sha256 {} but SUBSTREAMS_PACKAGE_SHA256 expects {}",
+                input,
+                hex::encode(actual),
+                hex::encode(expected)
+            ));
+        }
+    }
 
-    Package::decode(body).context("decode command")
+    Ok((
+        Package::decode(content.as_slice()).context("decode command")?,
+        actual,
+    ))
 }
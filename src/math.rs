@@ -0,0 +1,85 @@
+//! Answers which era file holds a block, and which blocks a file holds.
+
+use crate::naming::{discover_epoch, FileNamer};
+use anyhow::{anyhow, bail};
+use era_file_sink::chain::ChainConfig;
+use era_file_sink::epoch::{first_block, get_epoch, EPOCH_SIZE};
+use era_file_sink::header_accumulator::{AccumulatorProvider, ValueList};
+
+/// What the operator is asking about.
+pub enum Query {
+    Block(u64),
+    Epoch(u64),
+    File(String),
+}
+
+/// Prints the epoch, block range and file name matching `query` on `chain`.
+/// File names need the accumulator root of the epoch, looked up in `values`.
+pub fn run(
+    query: Query,
+    chain: ChainConfig,
+    values: Option<&ValueList>,
+    namer: &dyn FileNamer,
+) -> Result<(), anyhow::Error> {
+    let epoch = match query {
+        Query::Block(number) => {
+            if let Some(last) = chain.last_pow_block.filter(|last| number > *last) {
+                bail!(
+                    "block {} is past the last proof-of-work block of {}, {}, and in no era file",
+                    number,
+                    chain.network,
+                    last
+                );
+            }
+            println!("Block:  {}", number);
+            get_epoch(number)
+        }
+        Query::Epoch(epoch) => epoch,
+        Query::File(name) => discover_epoch(chain.network, &name).ok_or(anyhow!(
+            "'{}' is not a {} era file name",
+            name,
+            chain.network
+        ))?,
+    };
+
+    let first = first_block(epoch);
+    let last = first + EPOCH_SIZE - 1;
+    let last = match chain.last_pow_block {
+        Some(last_pow_block) if first > last_pow_block => bail!(
+            "epoch {} starts after the last proof-of-work block of {}, {}",
+            epoch,
+            chain.network,
+            last_pow_block
+        ),
+        Some(last_pow_block) if last > last_pow_block => last_pow_block,
+        _ => last,
+    };
+    println!("Epoch:  {}", epoch);
+    println!(
+        "Blocks: {} to {} ({} blocks{})",
+        first,
+        last,
+        last - first + 1,
+        if last - first + 1 < EPOCH_SIZE {
+            ", the partial final era"
+        } else {
+            ""
+        }
+    );
+    match values.and_then(|values| values.value_for_epoch(epoch)) {
+        Some(root) => println!("File:   {}", namer.file_name(epoch, &root)),
+        None => println!(
+            "File:   unknown, no accumulator value for epoch {} to name it with",
+            epoch
+        ),
+    }
+
+    Ok(())
+}
+
+/// Parses a block or epoch number, allowing `,` and `_` as digit separators.
+pub fn parse_number(s: &str) -> Result<u64, anyhow::Error> {
+    s.replace([',', '_'], "")
+        .parse()
+        .map_err(|_| anyhow!("'{}' is not a valid number", s))
+}
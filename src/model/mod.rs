@@ -0,0 +1,94 @@
+//! The sink's own representation of a block, holding only what era files
+//! need.
+//!
+//! Everything past the stream works on these types, so a change in an
+//! upstream schema is absorbed by its adapter, such as the one for the
+//! Substreams protobuf in [`protobuf`], instead of rippling through the
+//! mapping and encoding code. Integers wider than 64 bits are kept as their
+//! big endian bytes.
+
+pub mod protobuf;
+
+pub struct Block {
+    pub number: u64,
+    pub hash: Vec<u8>,
+    pub header: Header,
+    pub uncles: Vec<Header>,
+    pub transactions: Vec<Transaction>,
+}
+
+/// A block header. Fields some sources leave out are optional, so incomplete
+/// headers can be detected instead of encoded.
+#[derive(Clone, Debug, Default)]
+pub struct Header {
+    pub parent_hash: Vec<u8>,
+    pub uncle_hash: Vec<u8>,
+    pub coinbase: Vec<u8>,
+    pub state_root: Vec<u8>,
+    pub transactions_root: Vec<u8>,
+    pub receipt_root: Vec<u8>,
+    pub logs_bloom: Vec<u8>,
+    pub difficulty: Option<Vec<u8>>,
+    pub total_difficulty: Option<Vec<u8>>,
+    pub number: u64,
+    pub gas_limit: u64,
+    pub gas_used: u64,
+    /// Seconds since the unix epoch.
+    pub timestamp: Option<u64>,
+    pub extra_data: Vec<u8>,
+    pub mix_hash: Vec<u8>,
+    pub nonce: u64,
+    pub hash: Vec<u8>,
+    /// Only set from London on.
+    pub base_fee_per_gas: Option<Vec<u8>>,
+    /// Only set from Shanghai on.
+    pub withdrawals_root: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxType {
+    Legacy,
+    /// EIP-2930.
+    AccessList,
+    /// EIP-1559.
+    DynamicFee,
+}
+
+pub struct Transaction {
+    pub tx_type: TxType,
+    /// `None` for contract creations.
+    pub to: Option<Vec<u8>>,
+    pub nonce: u64,
+    pub gas_price: Option<Vec<u8>>,
+    pub gas_limit: u64,
+    pub value: Option<Vec<u8>>,
+    pub input: Vec<u8>,
+    pub v: Vec<u8>,
+    pub r: Vec<u8>,
+    pub s: Vec<u8>,
+    pub access_list: Vec<AccessTuple>,
+    pub max_fee_per_gas: Option<Vec<u8>>,
+    pub max_priority_fee_per_gas: Option<Vec<u8>>,
+    pub hash: Vec<u8>,
+    pub succeeded: bool,
+    pub receipt: Option<Receipt>,
+}
+
+pub struct AccessTuple {
+    pub address: Vec<u8>,
+    pub storage_keys: Vec<Vec<u8>>,
+}
+
+pub struct Receipt {
+    /// Post-transaction state root, only set before Byzantium.
+    pub state_root: Vec<u8>,
+    pub cumulative_gas_used: u64,
+    pub logs_bloom: Vec<u8>,
+    pub logs: Vec<Log>,
+}
+
+pub struct Log {
+    pub address: Vec<u8>,
+    pub topics: Vec<Vec<u8>>,
+    pub data: Vec<u8>,
+}
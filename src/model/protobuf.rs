@@ -0,0 +1,143 @@
+//! Adapter from the `acme.verifiable_block.v1` protobuf sent by the
+//! Substreams module.
+
+use crate::model::{AccessTuple, Block, Header, Log, Receipt, Transaction, TxType};
+use crate::pb::acme::verifiable_block::v1 as pb;
+use crate::pb::acme::verifiable_block::v1::transaction::Type;
+use crate::pb::acme::verifiable_block::v1::TransactionTraceStatus;
+use anyhow::anyhow;
+
+impl TryFrom<pb::VerifiableBlock> for Block {
+    type Error = anyhow::Error;
+
+    fn try_from(block: pb::VerifiableBlock) -> Result<Self, Self::Error> {
+        Ok(Block {
+            number: block.number,
+            hash: block.hash,
+            header: block.header.ok_or(anyhow!("No header"))?.into(),
+            uncles: block.uncles.into_iter().map(Header::from).collect(),
+            transactions: block
+                .transactions
+                .into_iter()
+                .map(Transaction::try_from)
+                .collect::<Result<Vec<Transaction>, anyhow::Error>>()?,
+        })
+    }
+}
+
+/// The fields of `VerifiableBlock` that identify a block, without its
+/// uncles and transactions, for work that only needs headers. Decoding skips
+/// over the other fields without parsing them, and the resulting [`Block`]
+/// has no uncles or transactions.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HeaderOnlyBlock {
+    #[prost(bytes = "vec", tag = "2")]
+    pub hash: Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub number: u64,
+    #[prost(message, optional, tag = "5")]
+    pub header: Option<pb::BlockHeader>,
+}
+
+impl TryFrom<HeaderOnlyBlock> for Block {
+    type Error = anyhow::Error;
+
+    fn try_from(block: HeaderOnlyBlock) -> Result<Self, Self::Error> {
+        Ok(Block {
+            number: block.number,
+            hash: block.hash,
+            header: block.header.ok_or(anyhow!("No header"))?.into(),
+            uncles: Vec::new(),
+            transactions: Vec::new(),
+        })
+    }
+}
+
+impl From<pb::BlockHeader> for Header {
+    fn from(header: pb::BlockHeader) -> Self {
+        Header {
+            parent_hash: header.parent_hash,
+            uncle_hash: header.uncle_hash,
+            coinbase: header.coinbase,
+            state_root: header.state_root,
+            transactions_root: header.transactions_root,
+            receipt_root: header.receipt_root,
+            logs_bloom: header.logs_bloom,
+            difficulty: header.difficulty.map(|difficulty| difficulty.bytes),
+            total_difficulty: header
+                .total_difficulty
+                .map(|total_difficulty| total_difficulty.bytes),
+            number: header.number,
+            gas_limit: header.gas_limit,
+            gas_used: header.gas_used,
+            timestamp: header.timestamp.map(|timestamp| timestamp.seconds as u64),
+            extra_data: header.extra_data,
+            mix_hash: header.mix_hash,
+            nonce: header.nonce,
+            hash: header.hash,
+            base_fee_per_gas: header
+                .base_fee_per_gas
+                .map(|base_fee_per_gas| base_fee_per_gas.bytes)
+                .filter(|bytes| !bytes.is_empty()),
+            withdrawals_root: Some(header.withdrawals_root).filter(|root| !root.is_empty()),
+        }
+    }
+}
+
+impl TryFrom<pb::Transaction> for Transaction {
+    type Error = anyhow::Error;
+
+    fn try_from(trace: pb::Transaction) -> Result<Self, Self::Error> {
+        let tx_type = match Type::from_i32(trace.r#type) {
+            Some(Type::TrxTypeLegacy) => TxType::Legacy,
+            Some(Type::TrxTypeAccessList) => TxType::AccessList,
+            Some(Type::TrxTypeDynamicFee) => TxType::DynamicFee,
+            None => return Err(anyhow!("unknown transaction type {}", trace.r#type)),
+        };
+
+        Ok(Transaction {
+            tx_type,
+            to: Some(trace.to).filter(|to| !to.is_empty()),
+            nonce: trace.nonce,
+            gas_price: trace.gas_price.map(|gas_price| gas_price.bytes),
+            gas_limit: trace.gas_limit,
+            value: trace.value.map(|value| value.bytes),
+            input: trace.input,
+            v: trace.v,
+            r: trace.r,
+            s: trace.s,
+            access_list: trace
+                .access_list
+                .into_iter()
+                .map(|tuple| AccessTuple {
+                    address: tuple.address,
+                    storage_keys: tuple.storage_keys,
+                })
+                .collect(),
+            max_fee_per_gas: trace.max_fee_per_gas.map(|fee| fee.bytes),
+            max_priority_fee_per_gas: trace.max_priority_fee_per_gas.map(|fee| fee.bytes),
+            hash: trace.hash,
+            succeeded: trace.status == TransactionTraceStatus::Succeeded as i32,
+            receipt: trace.receipt.map(Receipt::from),
+        })
+    }
+}
+
+impl From<pb::TransactionReceipt> for Receipt {
+    fn from(receipt: pb::TransactionReceipt) -> Self {
+        Receipt {
+            state_root: receipt.state_root,
+            cumulative_gas_used: receipt.cumulative_gas_used,
+            logs_bloom: receipt.logs_bloom,
+            logs: receipt
+                .logs
+                .into_iter()
+                .map(|log| Log {
+                    address: log.address,
+                    topics: log.topics,
+                    data: log.data,
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::anyhow;
+use std::str::FromStr;
+
+/// Decides how era files are named on disk, and recognizes the names it
+/// produces so existing archives can be discovered again.
+pub trait FileNamer {
+    /// Name of the file holding `epoch`, whose accumulator root is `root`.
+    fn file_name(&self, epoch: u64, root: &[u8]) -> String;
+
+    /// Epoch of a file produced by this namer, or `None` if the name doesn't
+    /// follow its scheme.
+    fn parse_epoch(&self, file_name: &str) -> Option<u64>;
+}
+
+/// The era1 spec layout, `<network>-<epoch>-<short root>.era1`, where the
+/// epoch is zero padded to five digits and the short root is the first four
+/// bytes of the epoch accumulator root in hex.
+pub struct SpecNamer {
+    pub network: String,
+}
+
+impl FileNamer for SpecNamer {
+    fn file_name(&self, epoch: u64, root: &[u8]) -> String {
+        let short_root = &root[..root.len().min(4)];
+        format!(
+            "{}-{:05}-{}.era1",
+            self.network,
+            epoch,
+            hex::encode(short_root)
+        )
+    }
+
+    fn parse_epoch(&self, file_name: &str) -> Option<u64> {
+        let stem = file_name.strip_suffix(".era1")?;
+        let rest = stem.strip_prefix(&self.network)?.strip_prefix('-')?;
+        let (epoch, short_root) = rest.split_once('-')?;
+        if epoch.len() < 5 || short_root.len() != 8 || hex::decode(short_root).is_err() {
+            return None;
+        }
+
+        epoch.parse().ok()
+    }
+}
+
+/// The `era-<epoch>.era1` layout produced by earlier versions of the sink.
+pub struct LegacyNamer;
+
+impl FileNamer for LegacyNamer {
+    fn file_name(&self, epoch: u64, _root: &[u8]) -> String {
+        format!("era-{}.era1", epoch)
+    }
+
+    fn parse_epoch(&self, file_name: &str) -> Option<u64> {
+        file_name
+            .strip_prefix("era-")?
+            .strip_suffix(".era1")?
+            .parse()
+            .ok()
+    }
+}
+
+/// Naming scheme selected by the operator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NamingScheme {
+    Spec,
+    Legacy,
+}
+
+impl NamingScheme {
+    pub fn namer(self, network: &str) -> Box<dyn FileNamer> {
+        match self {
+            NamingScheme::Spec => Box::new(SpecNamer {
+                network: network.to_string(),
+            }),
+            NamingScheme::Legacy => Box::new(LegacyNamer),
+        }
+    }
+}
+
+impl FromStr for NamingScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "spec" => Ok(NamingScheme::Spec),
+            "legacy" => Ok(NamingScheme::Legacy),
+            _ => Err(anyhow!(
+                "unknown naming scheme '{}', expected 'spec' or 'legacy'",
+                s
+            )),
+        }
+    }
+}
+
+/// Name of the era file being written, renamed once the era is finalized so
+/// an interrupted run can't leave a truncated file under a final name.
+pub fn in_progress_file_name(network: &str, epoch: u64) -> String {
+    format!("{}-{:05}.era1{}", network, epoch, TEMPORARY_SUFFIX)
+}
+
+/// Suffix of files that are still being written, by the sink or by the
+/// encryption, and are removed when a run starts.
+pub const TEMPORARY_SUFFIX: &str = ".tmp";
+
+/// Name of an era file whose blocks are complete but which still lacks its
+/// accumulator, and therefore the short root the final name needs.
+pub fn pending_file_name(network: &str, epoch: u64) -> String {
+    format!("{}-{:05}.era1.pending", network, epoch)
+}
+
+/// Epoch of a file named by [`pending_file_name`].
+pub fn parse_pending_epoch(network: &str, file_name: &str) -> Option<u64> {
+    file_name
+        .strip_suffix(".era1.pending")?
+        .strip_prefix(network)?
+        .strip_prefix('-')?
+        .parse()
+        .ok()
+}
+
+/// Epoch of an era file named under any supported scheme.
+pub fn discover_epoch(network: &str, file_name: &str) -> Option<u64> {
+    [NamingScheme::Spec, NamingScheme::Legacy]
+        .into_iter()
+        .find_map(|scheme| scheme.namer(network).parse_epoch(file_name))
+}
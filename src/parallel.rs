@@ -0,0 +1,192 @@
+//! Spreading independent work, such as one era file each, over all cores.
+//!
+//! Work finishes in whatever order the workers get to it, but era files, their
+//! entries and the cursors that follow them must come out in block order.
+//! Results either come back in the order of their inputs, as with
+//! [`map_in_parallel`], or carry a sequence number and go through
+//! [`InOrder`].
+
+use anyhow::bail;
+use std::collections::BTreeMap;
+
+/// Applies `f` to every item, splitting `items` into one contiguous chunk per
+/// core, and returns the results in the order of `items`.
+pub fn map_in_parallel<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let chunk_size = items.len().div_ceil(threads).max(1);
+    let f = &f;
+    std::thread::scope(|scope| {
+        let workers = items
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Releases items tagged with consecutive sequence numbers in sequence order,
+/// holding back those that arrive early.
+#[derive(Debug)]
+pub struct InOrder<T> {
+    next: u64,
+    waiting: BTreeMap<u64, T>,
+}
+
+impl<T> InOrder<T> {
+    /// Expects `first` as the first sequence number.
+    pub fn starting_at(first: u64) -> Self {
+        Self {
+            next: first,
+            waiting: BTreeMap::new(),
+        }
+    }
+
+    /// Takes the item numbered `sequence`. A number that was already released
+    /// or is already waiting means a worker ran twice, and is refused.
+    pub fn insert(&mut self, sequence: u64, item: T) -> Result<(), anyhow::Error> {
+        if sequence < self.next || self.waiting.contains_key(&sequence) {
+            bail!("sequence number {} was already seen", sequence);
+        }
+        self.waiting.insert(sequence, item);
+
+        Ok(())
+    }
+
+    /// The next item in sequence order, if it has arrived.
+    pub fn pop(&mut self) -> Option<T> {
+        let item = self.waiting.remove(&self.next)?;
+        self.next += 1;
+        Some(item)
+    }
+
+    /// Sequence number of the next item to release.
+    pub fn next_sequence(&self) -> u64 {
+        self.next
+    }
+
+    /// Items held back until the ones before them arrive.
+    pub fn waiting(&self) -> usize {
+        self.waiting.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// The numbers from `first` to `first + count`, excluded, scrambled by
+    /// stepping through them with a stride coprime with `count`.
+    fn scrambled(first: u64, count: u64, stride: u64) -> Vec<u64> {
+        (0..count).map(|i| first + i * stride % count).collect()
+    }
+
+    /// Inserts `sequences` one at a time, draining after each insert, and
+    /// returns the released items.
+    fn release(first: u64, sequences: &[u64]) -> Vec<u64> {
+        let mut in_order = InOrder::starting_at(first);
+        let mut released = Vec::new();
+        for sequence in sequences {
+            in_order.insert(*sequence, *sequence).unwrap();
+            while let Some(item) = in_order.pop() {
+                released.push(item);
+            }
+        }
+        assert_eq!(in_order.waiting(), 0);
+        released
+    }
+
+    #[test]
+    fn releases_scrambled_items_in_sequence() {
+        let count = 1000;
+        let expected: Vec<u64> = (500..500 + count).collect();
+        for stride in [1, 7, 389, 997] {
+            assert_eq!(release(500, &scrambled(500, count, stride)), expected);
+        }
+        let reversed: Vec<u64> = expected.iter().rev().copied().collect();
+        assert_eq!(release(500, &reversed), expected);
+    }
+
+    #[test]
+    fn releases_items_from_racing_workers_in_sequence() {
+        let count = 2000;
+        let workers = 8;
+        let (sender, receiver) = mpsc::channel();
+        let mut in_order = InOrder::starting_at(0);
+        let mut released = Vec::new();
+        std::thread::scope(|scope| {
+            for worker in 0..workers {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    // Each worker sends its share back to front, so items
+                    // arrive far out of order.
+                    for sequence in (0..count).rev().filter(|n| n % workers == worker) {
+                        if sequence % 13 == 0 {
+                            std::thread::yield_now();
+                        }
+                        sender.send(sequence).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+
+            for sequence in receiver {
+                in_order.insert(sequence, sequence).unwrap();
+                while let Some(item) = in_order.pop() {
+                    released.push(item);
+                }
+            }
+        });
+        assert_eq!(released, (0..count).collect::<Vec<u64>>());
+        assert_eq!(in_order.waiting(), 0);
+    }
+
+    #[test]
+    fn refuses_duplicate_and_stale_numbers() {
+        let mut in_order = InOrder::starting_at(10);
+        assert!(in_order.insert(9, "before the start").is_err());
+        in_order.insert(12, "twelve").unwrap();
+        assert_eq!(
+            in_order.insert(12, "twelve again").unwrap_err().to_string(),
+            "sequence number 12 was already seen"
+        );
+        in_order.insert(10, "ten").unwrap();
+        assert_eq!(in_order.pop(), Some("ten"));
+        assert!(in_order.insert(10, "ten again").is_err());
+        assert_eq!(in_order.pop(), None);
+        assert_eq!(in_order.waiting(), 1);
+    }
+
+    #[test]
+    fn counts_after_partial_drains() {
+        let mut in_order = InOrder::starting_at(0);
+        for sequence in [1, 2, 4, 5] {
+            in_order.insert(sequence, sequence).unwrap();
+        }
+        assert_eq!((in_order.next_sequence(), in_order.waiting()), (0, 4));
+        assert_eq!(in_order.pop(), None);
+
+        in_order.insert(0, 0).unwrap();
+        assert_eq!(in_order.pop(), Some(0));
+        assert_eq!((in_order.next_sequence(), in_order.waiting()), (1, 4));
+        assert_eq!(in_order.pop(), Some(1));
+        assert_eq!(in_order.pop(), Some(2));
+        assert_eq!(in_order.pop(), None);
+        assert_eq!((in_order.next_sequence(), in_order.waiting()), (3, 2));
+
+        in_order.insert(3, 3).unwrap();
+        let rest: Vec<u64> = std::iter::from_fn(|| in_order.pop()).collect();
+        assert_eq!(rest, [3, 4, 5]);
+        assert_eq!((in_order.next_sequence(), in_order.waiting()), (6, 0));
+    }
+
+    #[test]
+    fn map_in_parallel_keeps_the_order_of_items() {
+        let items: Vec<u64> = (0..10_000).collect();
+        let squares = map_in_parallel(&items, |n| n * n);
+        assert_eq!(squares, items.iter().map(|n| n * n).collect::<Vec<u64>>());
+    }
+}
@@ -4,6 +4,24 @@ use crate::pb::sf::substreams::rpc::v2::BlockRange;
 
 include!("pb.rs");
 
+/// The generated gRPC clients of the Substreams services, which need tonic
+/// and are only built with the `client` feature.
+#[cfg(feature = "client")]
+pub mod client {
+    pub mod rpc {
+        pub mod v2 {
+            pub use crate::pb::sf::substreams::rpc::v2::*;
+            include!("sf.substreams.rpc.v2.tonic.rs");
+        }
+    }
+    pub mod internal {
+        pub mod v2 {
+            pub use crate::pb::sf::substreams::internal::v2::*;
+            include!("sf.substreams.internal.v2.tonic.rs");
+        }
+    }
+}
+
 impl Display for BlockRange {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "({}-{})", self.start_block, self.end_block)
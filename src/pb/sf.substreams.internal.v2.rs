@@ -167,5 +167,4 @@ pub struct BlockRange {
     #[prost(uint64, tag="3")]
     pub end_block: u64,
 }
-include!("sf.substreams.internal.v2.tonic.rs");
 // @@protoc_insertion_point(module)
\ No newline at end of file
@@ -289,5 +289,4 @@ pub mod store_delta {
         }
     }
 }
-include!("sf.substreams.rpc.v2.tonic.rs");
 // @@protoc_insertion_point(module)
\ No newline at end of file
@@ -0,0 +1,61 @@
+use anyhow::{bail, Context};
+use std::env;
+use std::fs::File;
+
+/// Mode and group given to finished era files, so archives written by one
+/// service user can be served by another regardless of the umask the sink
+/// runs under.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputPermissions {
+    /// Permission bits, read from `ERA_FILE_MODE` in octal, e.g. `0644`.
+    mode: Option<u32>,
+    /// Numeric group id, read from `ERA_FILE_GID`.
+    gid: Option<u32>,
+}
+
+impl OutputPermissions {
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let mode = match env::var("ERA_FILE_MODE") {
+            Ok(mode) => Some(
+                u32::from_str_radix(&mode, 8).context("ERA_FILE_MODE is not a valid octal mode")?,
+            ),
+            Err(_) => None,
+        };
+        let gid = match env::var("ERA_FILE_GID") {
+            Ok(gid) => Some(
+                gid.parse::<u32>()
+                    .context("ERA_FILE_GID is not a valid group id")?,
+            ),
+            Err(_) => None,
+        };
+
+        let permissions = Self { mode, gid };
+        if !cfg!(unix) && permissions != Self::default() {
+            bail!("ERA_FILE_MODE and ERA_FILE_GID are only supported on unix");
+        }
+
+        Ok(permissions)
+    }
+
+    /// Applies the configured mode and group to `file`, leaving anything
+    /// that isn't configured untouched.
+    #[cfg(unix)]
+    pub fn apply(&self, file: &File) -> Result<(), anyhow::Error> {
+        use std::os::unix::fs::{fchown, PermissionsExt};
+
+        if let Some(mode) = self.mode {
+            file.set_permissions(std::fs::Permissions::from_mode(mode))
+                .context("set era file mode")?;
+        }
+        if let Some(gid) = self.gid {
+            fchown(file, None, Some(gid)).context("set era file group")?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, _file: &File) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+//! Heap profiling hooks, compiled in with the `profiling` feature.
+
+#[global_allocator]
+static ALLOC: dhat::Alloc = dhat::Alloc;
+
+/// Reports what was allocated while building each era, as the difference
+/// between consecutive heap snapshots.
+pub struct EraAllocations {
+    last: dhat::HeapStats,
+}
+
+impl EraAllocations {
+    pub fn new() -> Self {
+        Self {
+            last: dhat::HeapStats::get(),
+        }
+    }
+
+    pub fn report(&mut self, epoch: u64) {
+        let stats = dhat::HeapStats::get();
+        println!(
+            "Era {} allocations: {} blocks, {} bytes, {} bytes live, {} bytes peak",
+            epoch,
+            stats.total_blocks - self.last.total_blocks,
+            stats.total_bytes - self.last.total_bytes,
+            stats.curr_bytes,
+            stats.max_bytes
+        );
+        self.last = stats;
+    }
+}
@@ -0,0 +1,170 @@
+//! Provenance sidecars, recording which upstream supplied the blocks of an
+//! era file, so a discrepancy found later can be attributed to it.
+//!
+//! Every finished era file gets a `<file name>.provenance` companion of JSON
+//! lines. A line with `first_block` names the source of that block and of the
+//! blocks after it, up to the next such line. One is written at the start of
+//! each era, when a resumed run picks the era up again and when a new session
+//! starts after a reconnect. A line with `block` and `uncle_headers_from` or
+//! `receipts_from` names the JSON-RPC node the uncle headers or receipts of
+//! that block were fetched from.
+
+use crate::naming::TEMPORARY_SUFFIX;
+use anyhow::{format_err, Context};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the provenance sidecar of an era file.
+pub const PROVENANCE_SUFFIX: &str = ".provenance";
+
+/// Where the blocks of a run come from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    Substreams {
+        endpoint: String,
+        package_sha256: [u8; 32],
+        module: String,
+    },
+    /// A recording replayed from `REPLAY_DIR`.
+    Replay { recording: PathBuf },
+}
+
+/// Writes the provenance sidecar of the era in progress.
+pub struct Provenance {
+    source: Source,
+    session: Option<String>,
+    file: Option<(File, PathBuf)>,
+    // Whether the next block starts a new line with the source.
+    changed: bool,
+}
+
+impl Provenance {
+    pub fn new(source: Source) -> Self {
+        Self {
+            source,
+            session: None,
+            file: None,
+            changed: true,
+        }
+    }
+
+    /// Starts the sidecar of the era written to `era_file`, appending to the
+    /// one a resumed era already has.
+    pub fn start_era(&mut self, era_file: &Path) -> Result<(), anyhow::Error> {
+        let path = in_progress_path(era_file)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .context(format!("open provenance file '{}'", path.display()))?;
+        self.file = Some((file, path));
+        self.changed = true;
+
+        Ok(())
+    }
+
+    /// Records that the blocks from now on come from the session `trace_id`.
+    pub fn session(&mut self, trace_id: &str) {
+        self.session = Some(trace_id.to_string());
+        self.changed = true;
+    }
+
+    /// Records block `number`, received with `cursor`.
+    pub fn block(&mut self, number: u64, cursor: &str) -> Result<(), anyhow::Error> {
+        if !self.changed {
+            return Ok(());
+        }
+        let line = match &self.source {
+            Source::Substreams {
+                endpoint,
+                package_sha256,
+                module,
+            } => serde_json::json!({
+                "first_block": number,
+                "source": "substreams",
+                "endpoint": endpoint,
+                "package_sha256": hex::encode(package_sha256),
+                "module": module,
+                "session": self.session,
+                "cursor": cursor,
+            }),
+            Source::Replay { recording } => serde_json::json!({
+                "first_block": number,
+                "source": "replay",
+                "recording": recording.display().to_string(),
+                "cursor": cursor,
+            }),
+        };
+        self.write(&line)?;
+        self.changed = false;
+
+        Ok(())
+    }
+
+    /// Records that the uncle headers of block `number` were fetched from
+    /// the JSON-RPC node at `url`.
+    pub fn uncles_fetched(&mut self, number: u64, url: &str) -> Result<(), anyhow::Error> {
+        self.write(&serde_json::json!({
+            "block": number,
+            "uncle_headers_from": url,
+        }))
+    }
+
+    /// Records that receipts of block `number` were fetched from the
+    /// JSON-RPC node at `url`.
+    pub fn receipts_fetched(&mut self, number: u64, url: &str) -> Result<(), anyhow::Error> {
+        self.write(&serde_json::json!({
+            "block": number,
+            "receipts_from": url,
+        }))
+    }
+
+    /// Closes the sidecar and moves it next to `era_file`, the finished era,
+    /// returning its new path.
+    pub fn finish(&mut self, era_file: &Path) -> Result<PathBuf, anyhow::Error> {
+        let (file, path) = self
+            .file
+            .take()
+            .ok_or(format_err!("no provenance file in progress"))?;
+        file.sync_data()?;
+        let finished = companion_path(era_file);
+        std::fs::rename(&path, &finished)?;
+
+        Ok(finished)
+    }
+
+    fn write(&mut self, line: &serde_json::Value) -> Result<(), anyhow::Error> {
+        let (file, path) = self
+            .file
+            .as_mut()
+            .ok_or(format_err!("no provenance file in progress"))?;
+        writeln!(file, "{}", line).context(format!("write '{}'", path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// The provenance sidecar of the finished era file `era_file`.
+pub fn companion_path(era_file: &Path) -> PathBuf {
+    let mut path = era_file.as_os_str().to_owned();
+    path.push(PROVENANCE_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// The sidecar of the era in progress in `era_file`, temporary like the era
+/// itself.
+pub fn in_progress_path(era_file: &Path) -> Result<PathBuf, anyhow::Error> {
+    let file_name = era_file
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .ok_or(format_err!("'{}' has no file name", era_file.display()))?;
+    let file_name = file_name
+        .strip_suffix(TEMPORARY_SUFFIX)
+        .unwrap_or(file_name);
+
+    Ok(era_file.with_file_name(format!(
+        "{}{}{}",
+        file_name, PROVENANCE_SUFFIX, TEMPORARY_SUFFIX
+    )))
+}
@@ -0,0 +1,213 @@
+use crate::http::HttpClient;
+use crate::uncles::{data, quantity};
+use anyhow::{anyhow, bail, Context};
+use era_file_sink::model::{Block, Log, Receipt};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Most requests sent in one batch, nodes commonly refuse larger batches.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// Fetches receipts from an Ethereum JSON-RPC endpoint, for streams that
+/// supply headers and bodies but leave receipts out.
+///
+/// With a cache directory, the receipts fetched for a block are kept there
+/// in `<block hash>.json`, so rebuilding the era doesn't fetch them again.
+pub struct ReceiptSource {
+    url: String,
+    http: HttpClient,
+    cache_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    id: u64,
+    result: Option<serde_json::Value>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcReceipt {
+    transaction_hash: String,
+    /// Only set from Byzantium on.
+    status: Option<String>,
+    /// Only set before Byzantium.
+    root: Option<String>,
+    cumulative_gas_used: String,
+    logs_bloom: String,
+    logs: Vec<RpcLog>,
+}
+
+#[derive(Deserialize)]
+struct RpcLog {
+    address: String,
+    topics: Vec<String>,
+    data: String,
+}
+
+impl ReceiptSource {
+    pub fn new(url: String, http: HttpClient, cache_dir: Option<PathBuf>) -> Self {
+        Self {
+            url,
+            http,
+            cache_dir,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Receipts of the transactions `hashes`, in the same order, fetched in
+    /// batches.
+    async fn fetch(&self, hashes: &[String]) -> Result<Vec<serde_json::Value>, anyhow::Error> {
+        let mut receipts = Vec::with_capacity(hashes.len());
+        for batch in hashes.chunks(MAX_BATCH_SIZE) {
+            let requests: Vec<serde_json::Value> = batch
+                .iter()
+                .enumerate()
+                .map(|(id, hash)| {
+                    serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "method": "eth_getTransactionReceipt",
+                        "params": [hash],
+                    })
+                })
+                .collect();
+            let mut responses: Vec<RpcResponse> = self
+                .http
+                .send(|client| client.post(&self.url).json(&requests))
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if responses.len() != batch.len() {
+                bail!(
+                    "receipt source answered {} of {} requests",
+                    responses.len(),
+                    batch.len()
+                );
+            }
+            // Batch responses may come in any order.
+            responses.sort_by_key(|response| response.id);
+            for (hash, response) in batch.iter().zip(responses) {
+                if let Some(error) = response.error {
+                    bail!("receipt source returned an error for {}: {}", hash, error);
+                }
+                receipts.push(
+                    response
+                        .result
+                        .ok_or(anyhow!("receipt source doesn't know transaction {}", hash))?,
+                );
+            }
+        }
+
+        Ok(receipts)
+    }
+
+    fn cache_file(&self, block_hash: &[u8]) -> Option<PathBuf> {
+        self.cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("0x{}.json", hex::encode(block_hash))))
+    }
+}
+
+/// Fills in the receipts missing from `block` with those from `source`,
+/// returning how many were fetched rather than read from the cache. Without a
+/// source, a missing receipt is an error.
+///
+/// Receipts from Byzantium on also set whether their transaction succeeded.
+pub async fn complete_receipts(
+    block: &mut Block,
+    source: Option<&ReceiptSource>,
+) -> Result<usize, anyhow::Error> {
+    let missing: Vec<usize> = (0..block.transactions.len())
+        .filter(|index| block.transactions[*index].receipt.is_none())
+        .collect();
+    let Some(first_missing) = missing.first() else {
+        return Ok(0);
+    };
+    let Some(source) = source else {
+        bail!(
+            "transaction 0x{} has no receipt, set RECEIPT_SOURCE to fetch receipts from a JSON-RPC node",
+            hex::encode(&block.transactions[*first_missing].hash)
+        );
+    };
+
+    let cache_file = source.cache_file(&block.hash);
+    let mut cache: HashMap<String, serde_json::Value> = match &cache_file {
+        Some(path) if path.exists() => serde_json::from_slice(&std::fs::read(path)?)
+            .context(format!("read receipt cache '{}'", path.display()))?,
+        _ => HashMap::new(),
+    };
+    let hashes: Vec<String> = missing
+        .iter()
+        .map(|index| format!("0x{}", hex::encode(&block.transactions[*index].hash)))
+        .collect();
+    let uncached: Vec<String> = hashes
+        .iter()
+        .filter(|hash| !cache.contains_key(*hash))
+        .cloned()
+        .collect();
+    if !uncached.is_empty() {
+        let receipts = source.fetch(&uncached).await.context("fetch receipts")?;
+        cache.extend(uncached.iter().cloned().zip(receipts));
+        if let Some(path) = &cache_file {
+            std::fs::write(path, serde_json::to_vec(&cache)?)
+                .context(format!("write receipt cache '{}'", path.display()))?;
+        }
+    }
+
+    for (index, hash) in missing.into_iter().zip(hashes) {
+        let receipt: RpcReceipt = serde_json::from_value(cache[&hash].clone())
+            .context(format!("receipt of transaction {}", hash))?;
+        let transaction = &mut block.transactions[index];
+        if data(&receipt.transaction_hash)? != transaction.hash {
+            bail!(
+                "receipt source returned the receipt of {} for transaction {}",
+                receipt.transaction_hash,
+                hash
+            );
+        }
+        if let Some(status) = &receipt.status {
+            transaction.succeeded = quantity(status)? == 1;
+        }
+        transaction.receipt =
+            Some(Receipt::try_from(receipt).context(format!("receipt of transaction {}", hash))?);
+    }
+
+    Ok(uncached.len())
+}
+
+impl TryFrom<RpcReceipt> for Receipt {
+    type Error = anyhow::Error;
+
+    fn try_from(receipt: RpcReceipt) -> Result<Self, Self::Error> {
+        Ok(Receipt {
+            state_root: match receipt.root {
+                Some(root) => data(&root)?,
+                None => Vec::new(),
+            },
+            cumulative_gas_used: quantity(&receipt.cumulative_gas_used)?,
+            logs_bloom: data(&receipt.logs_bloom)?,
+            logs: receipt
+                .logs
+                .into_iter()
+                .map(|log| {
+                    Ok(Log {
+                        address: data(&log.address)?,
+                        topics: log
+                            .topics
+                            .iter()
+                            .map(|topic| data(topic))
+                            .collect::<Result<_, anyhow::Error>>()?,
+                        data: data(&log.data)?,
+                    })
+                })
+                .collect::<Result<_, anyhow::Error>>()?,
+        })
+    }
+}
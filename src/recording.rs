@@ -0,0 +1,96 @@
+use anyhow::Context;
+use era_file_sink::compression::{snap_decode, snap_encode, MAX_ENTRY_SIZE};
+use era_file_sink::pb::sf::substreams::rpc::v2::BlockScopedData;
+use prost::Message;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const RECORDING_EXTENSION: &str = "sz";
+const FLAT_FILE_EXTENSION: &str = "pb.sz";
+
+/// Copies of every received block written in the same pass as the era
+/// files, so other outputs don't need a second fetch or a re-read of the era
+/// files:
+///
+/// - `RECORD_DIR`: the whole response, cursor included, for replay.
+/// - `FLAT_FILE_DIR`: one file per block holding the module output, a
+///   `VerifiableBlock` protobuf.
+pub struct Tee {
+    record_dir: Option<PathBuf>,
+    flat_file_dir: Option<PathBuf>,
+}
+
+impl Tee {
+    pub fn from_env() -> Self {
+        Self {
+            record_dir: env::var_os("RECORD_DIR").map(PathBuf::from),
+            flat_file_dir: env::var_os("FLAT_FILE_DIR").map(PathBuf::from),
+        }
+    }
+
+    pub fn write(&self, data: &BlockScopedData) -> Result<(), anyhow::Error> {
+        if let Some(dir) = &self.record_dir {
+            record(dir, data)?;
+        }
+        if let Some(dir) = &self.flat_file_dir {
+            write_flat_block(dir, data)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Saves `data`, which includes its cursor, as a snappy compressed protobuf
+/// named after its block number, so a session can be replayed exactly.
+fn record(dir: &Path, data: &BlockScopedData) -> Result<(), anyhow::Error> {
+    let block_number = data.clock.as_ref().map_or(0, |clock| clock.number);
+    let path = dir.join(format!("{:010}.{}", block_number, RECORDING_EXTENSION));
+    fs::write(&path, snap_encode(&data.encode_to_vec())?)
+        .context(format!("record block to '{}'", path.display()))
+}
+
+/// Saves the module output of `data` as a snappy compressed protobuf named
+/// after its block number.
+fn write_flat_block(dir: &Path, data: &BlockScopedData) -> Result<(), anyhow::Error> {
+    let block_number = data.clock.as_ref().map_or(0, |clock| clock.number);
+    let output = data
+        .output
+        .as_ref()
+        .and_then(|output| output.map_output.as_ref())
+        .ok_or(anyhow::anyhow!("No module output"))?;
+    let path = dir.join(format!("{:010}.{}", block_number, FLAT_FILE_EXTENSION));
+    fs::write(&path, snap_encode(&output.value)?)
+        .context(format!("write flat file '{}'", path.display()))
+}
+
+/// Recorded blocks in `dir` from `start_block` on, in block order.
+pub fn recorded_blocks(dir: &Path, start_block: u64) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut blocks = Vec::new();
+    for entry in fs::read_dir(dir).context(format!("list recording '{}'", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some(RECORDING_EXTENSION) {
+            continue;
+        }
+        let Some(block_number) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        if block_number >= start_block {
+            blocks.push((block_number, path));
+        }
+    }
+    blocks.sort();
+
+    Ok(blocks.into_iter().map(|(_, path)| path).collect())
+}
+
+pub fn read_recorded_block(path: &Path) -> Result<BlockScopedData, anyhow::Error> {
+    let data = fs::read(path).context(format!("read recorded block '{}'", path.display()))?;
+    Ok(BlockScopedData::decode(
+        snap_decode(&data, MAX_ENTRY_SIZE)?.as_slice(),
+    )?)
+}
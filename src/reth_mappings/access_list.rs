@@ -1,4 +1,4 @@
-use crate::pb::acme::verifiable_block::v1::AccessTuple;
+use crate::model::AccessTuple;
 use decoder::transactions::error::TransactionError;
 use reth_primitives::{AccessListItem, Address, H256};
 
@@ -1,11 +1,56 @@
-use crate::pb::acme::verifiable_block::v1::BlockHeader;
+use crate::model;
+use anyhow::bail;
 use decoder::headers::error;
 use reth_primitives::{Address, Bloom, Bytes, Header, H256, U256};
 
-impl TryFrom<&BlockHeader> for Header {
+/// Checks that every field the header encoding needs is present and has the
+/// right length, naming the first one that doesn't. Some sources fill uncle
+/// headers only partially, and those would otherwise panic while mapping.
+pub fn validate_header(block_header: &model::Header) -> Result<(), anyhow::Error> {
+    let fixed_fields = [
+        ("parent_hash", &block_header.parent_hash, 32),
+        ("uncle_hash", &block_header.uncle_hash, 32),
+        ("coinbase", &block_header.coinbase, 20),
+        ("state_root", &block_header.state_root, 32),
+        ("transactions_root", &block_header.transactions_root, 32),
+        ("receipt_root", &block_header.receipt_root, 32),
+        ("logs_bloom", &block_header.logs_bloom, 256),
+        ("mix_hash", &block_header.mix_hash, 32),
+    ];
+    for (field, value, length) in fixed_fields {
+        if value.len() != length {
+            bail!(
+                "header field {} is {} bytes long, expected {}",
+                field,
+                value.len(),
+                length
+            );
+        }
+    }
+    if let Some(withdrawals_root) = &block_header.withdrawals_root {
+        if withdrawals_root.len() != 32 {
+            bail!(
+                "header field withdrawals_root is {} bytes long, expected 32",
+                withdrawals_root.len()
+            );
+        }
+    }
+    if block_header.difficulty.is_none() {
+        bail!("header field difficulty is missing");
+    }
+    if block_header.timestamp.is_none() {
+        bail!("header field timestamp is missing");
+    }
+
+    Ok(())
+}
+
+impl TryFrom<&model::Header> for Header {
     type Error = anyhow::Error;
 
-    fn try_from(block_header: &BlockHeader) -> Result<Self, Self::Error> {
+    fn try_from(block_header: &model::Header) -> Result<Self, Self::Error> {
+        validate_header(block_header)?;
+
         let parent_hash = H256::from_slice(block_header.parent_hash.as_slice());
         let ommers_hash = H256::from_slice(block_header.uncle_hash.as_slice());
         let beneficiary = Address::from_slice(block_header.coinbase.as_slice());
@@ -18,7 +63,6 @@ impl TryFrom<&BlockHeader> for Header {
                 .difficulty
                 .as_ref()
                 .ok_or(error::BlockHeaderError::InvalidInput)?
-                .bytes
                 .as_slice(),
         );
         let number = block_header.number;
@@ -26,26 +70,16 @@ impl TryFrom<&BlockHeader> for Header {
         let gas_used = block_header.gas_used;
         let timestamp = block_header
             .timestamp
-            .clone()
-            .ok_or(error::BlockHeaderError::InvalidInput)?
-            .seconds as u64;
+            .ok_or(error::BlockHeaderError::InvalidInput)?;
         let extra_data = Bytes::from(block_header.extra_data.as_slice());
         let mix_hash = H256::from_slice(block_header.mix_hash.as_slice());
         let nonce = block_header.nonce;
-        let withdrawals_root = match block_header.withdrawals_root.is_empty() {
-            true => None,
-            false => Some(H256::from_slice(block_header.withdrawals_root.as_slice())),
-        };
+        let withdrawals_root = block_header
+            .withdrawals_root
+            .as_ref()
+            .map(|withdrawals_root| H256::from_slice(withdrawals_root.as_slice()));
         let base_fee_per_gas = match block_header.base_fee_per_gas.as_ref() {
-            Some(base_fee_per_gas) => {
-                let bytes = base_fee_per_gas.bytes.as_slice();
-                // if bytes is empty return None, else return u64 converted from bytes
-                match bytes.is_empty() {
-                    true => None,
-                    false => Some(U256::from_be_slice(bytes).try_into()?),
-                }
-            }
-
+            Some(base_fee_per_gas) => Some(U256::from_be_slice(base_fee_per_gas).try_into()?),
             None => None,
         };
         Ok(Header {
@@ -1,10 +1,11 @@
+use crate::model;
 use decoder::receipts::error::ReceiptError;
 use reth_primitives::{Address, Bytes, Log, H256};
 
-impl TryFrom<&crate::pb::acme::verifiable_block::v1::Log> for Log {
+impl TryFrom<&model::Log> for Log {
     type Error = ReceiptError;
 
-    fn try_from(log: &crate::pb::acme::verifiable_block::v1::Log) -> Result<Self, Self::Error> {
+    fn try_from(log: &model::Log) -> Result<Self, Self::Error> {
         let slice: [u8; 20] = log
             .address
             .as_slice()
@@ -4,3 +4,6 @@ mod log;
 mod receipt;
 mod signature;
 mod tx;
+
+pub use header::validate_header;
+pub use tx::map_signed_transaction;
@@ -1,14 +1,13 @@
-use crate::pb::acme::verifiable_block::v1::Transaction;
+use crate::model::{self, Transaction};
 use decoder::receipts::error::ReceiptError;
-use decoder::transactions::tx_type::map_tx_type;
-use reth_primitives::{Bloom, Log, Receipt, ReceiptWithBloom};
+use reth_primitives::{Bloom, Log, Receipt, ReceiptWithBloom, TxType};
 
-impl TryFrom<Transaction> for ReceiptWithBloom {
+impl TryFrom<&Transaction> for ReceiptWithBloom {
     type Error = ReceiptError;
 
-    fn try_from(trace: Transaction) -> Result<Self, Self::Error> {
-        let success = map_success(&trace.status);
-        let tx_type = map_tx_type(&trace.r#type)?;
+    fn try_from(trace: &Transaction) -> Result<Self, Self::Error> {
+        let success = trace.succeeded;
+        let tx_type = TxType::from(trace.tx_type);
         let trace_receipt = match &trace.receipt {
             Some(receipt) => receipt,
             None => return Err(ReceiptError::MissingReceipt),
@@ -29,10 +28,6 @@ impl TryFrom<Transaction> for ReceiptWithBloom {
     }
 }
 
-fn map_success(status: &i32) -> bool {
-    *status == 1
-}
-
 fn map_bloom(slice: &[u8]) -> Result<Bloom, ReceiptError> {
     if slice.len() == 256 {
         let array: [u8; 256] = slice
@@ -44,6 +39,6 @@ fn map_bloom(slice: &[u8]) -> Result<Bloom, ReceiptError> {
     }
 }
 
-fn map_logs(logs: &[crate::pb::acme::verifiable_block::v1::Log]) -> Result<Vec<Log>, ReceiptError> {
+fn map_logs(logs: &[model::Log]) -> Result<Vec<Log>, ReceiptError> {
     logs.iter().map(Log::try_from).collect()
 }
@@ -1,7 +1,16 @@
-use crate::pb::acme::verifiable_block::v1::Transaction;
+use crate::model::Transaction;
 use decoder::transactions::error::TransactionError;
 use reth_primitives::{Signature, U256};
 
+/// The `v` value of a signature, which is big endian and longer than one byte
+/// for large EIP-155 chain ids.
+pub(crate) fn signature_v(trace: &Transaction) -> u64 {
+    trace
+        .v
+        .iter()
+        .fold(0, |v, byte| (v << 8) | u64::from(*byte))
+}
+
 impl TryFrom<&Transaction> for Signature {
     type Error = TransactionError;
 
@@ -27,14 +36,15 @@ impl TryFrom<&Transaction> for Signature {
 }
 
 fn get_y_parity(trace: &Transaction) -> Result<bool, TransactionError> {
-    let v: u8 = if trace.v.is_empty() { 0 } else { trace.v[0] };
+    let v = signature_v(trace);
 
     if v == 0 || v == 1 {
         Ok(v == 1)
     } else if v == 27 || v == 28 {
         Ok(v - 27 == 1)
-    } else if v == 37 || v == 38 {
-        Ok(v - 37 == 1)
+    } else if v >= 35 {
+        // EIP-155: v = chain_id * 2 + 35 + parity
+        Ok((v - 35) % 2 == 1)
     } else {
         Err(TransactionError::MissingValue)
     }
@@ -1,126 +1,127 @@
-use crate::pb::acme::verifiable_block::v1::{AccessTuple, BigInt, Transaction};
+use crate::e2store::utils::bigint_to_u128;
+use crate::model::{self, AccessTuple, Transaction};
+use crate::reth_mappings::signature::signature_v;
 use decoder::transactions::error::TransactionError;
-use decoder::transactions::tx_type::map_tx_type;
 use reth_primitives::{
     AccessList, AccessListItem, Address, Bytes, ChainId, Signature, Transaction as RethTransaction,
     TransactionKind, TransactionSigned, TxEip1559, TxEip2930, TxLegacy, TxType, H256,
 };
 use std::str::FromStr;
 
-impl TryFrom<&Transaction> for TransactionSigned {
-    type Error = TransactionError;
-
-    fn try_from(trace: &Transaction) -> Result<Self, Self::Error> {
-        let transaction = RethTransaction::try_from(trace)?;
-        let signature = Signature::try_from(trace)?;
-
-        let hash = H256::from_str(&hex::encode(trace.hash.as_slice()))
-            .map_err(|_| TransactionError::MissingCall)?;
+/// Maps a transaction of the chain identified by `chain_id`, which the trace
+/// itself doesn't carry.
+pub fn map_signed_transaction(
+    trace: &Transaction,
+    chain_id: ChainId,
+) -> Result<TransactionSigned, TransactionError> {
+    let transaction = map_transaction(trace, chain_id)?;
+    let signature = Signature::try_from(trace)?;
+
+    let hash = H256::from_str(&hex::encode(trace.hash.as_slice()))
+        .map_err(|_| TransactionError::MissingCall)?;
+
+    let tx_signed = TransactionSigned {
+        transaction,
+        signature,
+        hash,
+    };
+
+    Ok(tx_signed)
+}
 
-        let tx_signed = TransactionSigned {
-            transaction,
-            signature,
-            hash,
-        };
+fn map_transaction(
+    trace: &Transaction,
+    chain_id: ChainId,
+) -> Result<RethTransaction, TransactionError> {
+    let tx_type = TxType::from(trace.tx_type);
+
+    let nonce = trace.nonce;
+    let gas_price = optional_u128(&trace.gas_price)?;
+    let gas_limit = trace.gas_limit;
+
+    let to = get_tx_kind(trace)?;
+
+    let value = optional_u128(&trace.value)?;
+    let input = Bytes::from(trace.input.as_slice());
+
+    let transaction: RethTransaction = match tx_type {
+        TxType::Legacy => {
+            let v = signature_v(trace);
+
+            let chain_id: Option<ChainId> = if v == 27 || v == 28 {
+                None
+            } else {
+                Some(chain_id)
+            };
+
+            RethTransaction::Legacy(TxLegacy {
+                chain_id,
+                nonce,
+                gas_price,
+                gas_limit,
+                to,
+                value,
+                input,
+            })
+        }
+        TxType::EIP2930 => {
+            let access_list = compute_access_list(&trace.access_list)?;
+
+            RethTransaction::Eip2930(TxEip2930 {
+                chain_id,
+                nonce,
+                gas_price,
+                gas_limit,
+                to,
+                value,
+                access_list,
+                input,
+            })
+        }
+        TxType::EIP1559 => {
+            let access_list = compute_access_list(&trace.access_list)?;
+            let max_fee_per_gas = optional_u128(&trace.max_fee_per_gas)?;
+            let max_priority_fee_per_gas = optional_u128(&trace.max_priority_fee_per_gas)?;
+
+            RethTransaction::Eip1559(TxEip1559 {
+                chain_id,
+                nonce,
+                gas_limit,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                to,
+                value,
+                access_list,
+                input,
+            })
+        }
+    };
+
+    Ok(transaction)
+}
 
-        Ok(tx_signed)
+fn get_tx_kind(trace: &Transaction) -> Result<TransactionKind, TransactionError> {
+    match &trace.to {
+        None => Ok(TransactionKind::Create),
+        Some(to) => {
+            let address = Address::from_slice(to.as_slice());
+            Ok(TransactionKind::Call(address))
+        }
     }
 }
 
-impl TryFrom<&Transaction> for RethTransaction {
-    type Error = TransactionError;
-
-    fn try_from(trace: &Transaction) -> Result<Self, Self::Error> {
-        let tx_type = map_tx_type(&trace.r#type)?;
-
-        let nonce = trace.nonce;
-        let trace_gas_price = trace
-            .gas_price
-            .clone()
-            .unwrap_or_else(|| BigInt { bytes: vec![0] });
-        let gas_price = trace_gas_price.try_into()?;
-        let gas_limit = trace.gas_limit;
-
-        let to = get_tx_kind(trace)?;
-
-        let chain_id = 1;
-
-        let trace_value = trace
-            .value
-            .clone()
-            .unwrap_or_else(|| BigInt { bytes: vec![0] });
-        let value = trace_value.try_into()?;
-        let input = Bytes::from(trace.input.as_slice());
-
-        let transaction: RethTransaction = match tx_type {
-            TxType::Legacy => {
-                let v: u8 = if trace.v.is_empty() { 0 } else { trace.v[0] };
-
-                let chain_id: Option<ChainId> = if v == 27 || v == 28 { None } else { Some(1) };
-
-                RethTransaction::Legacy(TxLegacy {
-                    chain_id,
-                    nonce,
-                    gas_price,
-                    gas_limit,
-                    to,
-                    value,
-                    input,
-                })
-            }
-            TxType::EIP2930 => {
-                let access_list = compute_access_list(&trace.access_list)?;
-
-                RethTransaction::Eip2930(TxEip2930 {
-                    chain_id,
-                    nonce,
-                    gas_price,
-                    gas_limit,
-                    to,
-                    value,
-                    access_list,
-                    input,
-                })
-            }
-            TxType::EIP1559 => {
-                let access_list = compute_access_list(&trace.access_list)?;
-                let trace_max_fee_per_gas = trace
-                    .max_fee_per_gas
-                    .clone()
-                    .unwrap_or_else(|| BigInt { bytes: vec![0] });
-                let max_fee_per_gas = trace_max_fee_per_gas.try_into()?;
-
-                let trace_max_priority_fee_per_gas = trace
-                    .max_priority_fee_per_gas
-                    .clone()
-                    .unwrap_or_else(|| BigInt { bytes: vec![0] });
-                let max_priority_fee_per_gas = trace_max_priority_fee_per_gas.try_into()?;
-
-                RethTransaction::Eip1559(TxEip1559 {
-                    chain_id,
-                    nonce,
-                    gas_limit,
-                    max_fee_per_gas,
-                    max_priority_fee_per_gas,
-                    to,
-                    value,
-                    access_list,
-                    input,
-                })
-            }
-        };
-
-        Ok(transaction)
-    }
+/// An optional amount, which sources leave out when it is zero.
+fn optional_u128(value: &Option<Vec<u8>>) -> Result<u128, TransactionError> {
+    value.as_deref().map_or(Ok(0), bigint_to_u128)
 }
 
-fn get_tx_kind(trace: &Transaction) -> Result<TransactionKind, TransactionError> {
-    let to = &trace.to;
-    if to.is_empty() {
-        Ok(TransactionKind::Create)
-    } else {
-        let address = Address::from_slice(trace.to.as_slice());
-        Ok(TransactionKind::Call(address))
+impl From<model::TxType> for TxType {
+    fn from(tx_type: model::TxType) -> Self {
+        match tx_type {
+            model::TxType::Legacy => TxType::Legacy,
+            model::TxType::AccessList => TxType::EIP2930,
+            model::TxType::DynamicFee => TxType::EIP1559,
+        }
     }
 }
 
@@ -1,58 +1,281 @@
-use crate::pb::acme::verifiable_block::v1::{BlockHeader, Log, TransactionReceipt};
-use rlp::{Encodable, RlpStream};
-
-impl Encodable for BlockHeader {
-    fn rlp_append(&self, s: &mut RlpStream) {
-        s.begin_unbounded_list()
-            .append(&self.parent_hash)
-            .append(&self.uncle_hash)
-            .append(&self.coinbase)
-            .append(&self.state_root)
-            .append(&self.transactions_root)
-            .append(&self.receipt_root)
-            .append(&self.logs_bloom)
-            .append(&self.difficulty.clone().expect("Missing difficulty").bytes)
-            .append(&encode_number(self.number))
-            .append(&encode_number(self.gas_limit))
-            .append(&encode_number(self.gas_used))
-            .append(&encode_number(
-                self.timestamp.clone().expect("Missing timestamp").seconds as u64,
-            ))
-            .append(&self.extra_data)
-            .append(&self.mix_hash)
-            .append(&self.nonce.to_be_bytes().as_slice())
-            .finalize_unbounded_list();
+//! RLP encodings of the model types that reth has no encoder for, and helpers
+//! for working on encoded lists, written with `alloy-rlp`. Byte fields are
+//! encoded as strings and numbers without leading zeros, except the nonce,
+//! which is always 8 bytes.
+
+use crate::model::{Header, Log, Receipt};
+use alloy_rlp::{encode_list, list_length, BufMut, Encodable, PayloadView};
+
+/// The items of the encoded list `rlp`, each still encoded.
+pub fn list_items(mut rlp: &[u8]) -> Result<Vec<&[u8]>, alloy_rlp::Error> {
+    match alloy_rlp::Header::decode_raw(&mut rlp)? {
+        PayloadView::List(items) => Ok(items),
+        PayloadView::String(_) => Err(alloy_rlp::Error::UnexpectedString),
+    }
+}
+
+/// The list of the already encoded `items`.
+pub fn raw_list<T: AsRef<[u8]>>(items: &[T]) -> Vec<u8> {
+    let mut list = Vec::new();
+    alloy_rlp::Header {
+        list: true,
+        payload_length: items.iter().map(|item| item.as_ref().len()).sum(),
+    }
+    .encode(&mut list);
+    for item in items {
+        list.extend_from_slice(item.as_ref());
+    }
+    list
+}
+
+/// Implements `Encodable` for a type encoded as the list of the fields its
+/// `with_fields` passes on.
+macro_rules! impl_encodable_list {
+    ($($type_:ty),+) => {$(
+        impl Encodable for $type_ {
+            fn encode(&self, out: &mut dyn BufMut) {
+                self.with_fields(|fields| encode_list::<_, dyn Encodable>(fields, out))
+            }
+
+            fn length(&self) -> usize {
+                self.with_fields(|fields| list_length::<_, dyn Encodable>(fields))
+            }
+        }
+    )+};
+}
+
+impl_encodable_list!(Header, Receipt, Log);
+
+impl Header {
+    fn with_fields<R>(&self, f: impl FnOnce(&[&dyn Encodable]) -> R) -> R {
+        let nonce = self.nonce.to_be_bytes();
+        f(&[
+            &self.parent_hash.as_slice(),
+            &self.uncle_hash.as_slice(),
+            &self.coinbase.as_slice(),
+            &self.state_root.as_slice(),
+            &self.transactions_root.as_slice(),
+            &self.receipt_root.as_slice(),
+            &self.logs_bloom.as_slice(),
+            &self.difficulty.as_deref().expect("Missing difficulty"),
+            &self.number,
+            &self.gas_limit,
+            &self.gas_used,
+            &self.timestamp.expect("Missing timestamp"),
+            &self.extra_data.as_slice(),
+            &self.mix_hash.as_slice(),
+            &nonce,
+        ])
     }
 }
 
-fn encode_number(n: u64) -> Vec<u8> {
-    let leading_zeros = n.leading_zeros();
-    let bytes = n.to_be_bytes();
-    bytes[(leading_zeros / 8) as usize..].to_vec()
+impl Receipt {
+    fn with_fields<R>(&self, f: impl FnOnce(&[&dyn Encodable]) -> R) -> R {
+        f(&[
+            &self.state_root.as_slice(),
+            &self.cumulative_gas_used,
+            &self.logs_bloom.as_slice(),
+            &self.logs,
+        ])
+    }
 }
 
-impl Encodable for TransactionReceipt {
-    fn rlp_append(&self, s: &mut RlpStream) {
-        s.begin_unbounded_list()
-            .append(&self.state_root)
-            .append(&encode_number(self.cumulative_gas_used))
-            .append(&self.logs_bloom)
-            .append_list(&self.logs)
-            .finalize_unbounded_list();
+impl Log {
+    fn with_fields<R>(&self, f: impl FnOnce(&[&dyn Encodable]) -> R) -> R {
+        let topics: Vec<&[u8]> = self.topics.iter().map(Vec::as_slice).collect();
+        f(&[&self.address.as_slice(), &topics, &self.data.as_slice()])
     }
 }
 
-impl Encodable for Log {
-    fn rlp_append(&self, s: &mut RlpStream) {
-        let topics = self
-            .topics
-            .iter()
-            .map(|topic| topic.as_slice())
-            .collect::<Vec<&[u8]>>();
-        s.begin_unbounded_list()
-            .append(&self.address)
-            .append_list::<&[u8], &[u8]>(topics.as_slice())
-            .append(&self.data)
-            .finalize_unbounded_list();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::e2store::rlp_encode_legacy_receipts;
+    use reth_primitives::keccak256;
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        hex::decode(hex).unwrap()
+    }
+
+    /// Decodes the concatenation of the hex strings in `parts`.
+    fn concat(parts: &[&str]) -> Vec<u8> {
+        bytes(&parts.concat())
+    }
+
+    fn bloom(set: &[(usize, u8)]) -> Vec<u8> {
+        let mut bloom = vec![0; 256];
+        for (index, byte) in set {
+            bloom[*index] = *byte;
+        }
+        bloom
+    }
+
+    /// Mainnet block 1.
+    fn block_1_header() -> Header {
+        Header {
+            parent_hash: bytes("d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"),
+            uncle_hash: bytes("1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347"),
+            coinbase: bytes("05a56e2d52c817161883f50c441c3228cfe54d9f"),
+            state_root: bytes("d67e4d450343046425ae4271474353857ab860dbc0a1dde64b41b5cd3a532bf3"),
+            transactions_root: bytes(
+                "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+            ),
+            receipt_root: bytes("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"),
+            logs_bloom: bloom(&[]),
+            difficulty: Some(bytes("03ff800000")),
+            number: 1,
+            gas_limit: 5000,
+            gas_used: 0,
+            timestamp: Some(1438269988),
+            extra_data: b"Geth/v1.0.0/linux/go1.4.2".to_vec(),
+            mix_hash: bytes("969b900de27b6ac6a67742365dd65f55a0526c41fd18e1b16f1a1215c2e66f59"),
+            nonce: 0x539bd4979fef1ec4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn mainnet_header() {
+        let encoded = alloy_rlp::encode(block_1_header());
+        let zero_bloom = "00".repeat(256);
+        assert_eq!(
+            encoded,
+            concat(&[
+                "f90211",
+                "a0d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3",
+                "a01dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347",
+                "9405a56e2d52c817161883f50c441c3228cfe54d9f",
+                "a0d67e4d450343046425ae4271474353857ab860dbc0a1dde64b41b5cd3a532bf3",
+                "a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+                "a056e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+                "b90100",
+                &zero_bloom,
+                // Difficulty, number, gas limit, gas used and timestamp,
+                // without leading zeros.
+                "8503ff800000",
+                "01",
+                "821388",
+                "80",
+                "8455ba4224",
+                "99476574682f76312e302e302f6c696e75782f676f312e342e32",
+                "a0969b900de27b6ac6a67742365dd65f55a0526c41fd18e1b16f1a1215c2e66f59",
+                // The nonce keeps all 8 bytes.
+                "88539bd4979fef1ec4",
+            ])
+        );
+        assert_eq!(encoded.len(), block_1_header().length());
+        assert_eq!(
+            hex::encode(keccak256(&encoded)),
+            "88e96d4537bea4d9c05d12549907b32561d3bf31f45aae734cdc119f13406cb6"
+        );
+    }
+
+    #[test]
+    fn pre_byzantium_receipts_with_logs() {
+        let receipts = [
+            Receipt {
+                state_root: vec![0x11; 32],
+                cumulative_gas_used: 21000,
+                logs_bloom: bloom(&[(3, 0x10), (100, 0x02), (255, 0x80)]),
+                logs: vec![Log {
+                    address: vec![0x22; 20],
+                    topics: vec![vec![0x33; 32], vec![0x44; 32]],
+                    data: bytes("0102"),
+                }],
+            },
+            Receipt {
+                state_root: vec![0x55; 32],
+                cumulative_gas_used: 65536,
+                logs_bloom: bloom(&[]),
+                logs: vec![Log {
+                    address: vec![0x66; 20],
+                    topics: Vec::new(),
+                    data: Vec::new(),
+                }],
+            },
+        ];
+        let encoded = rlp_encode_legacy_receipts(&receipts.iter().collect::<Vec<_>>());
+
+        assert_eq!(
+            encoded,
+            concat(&[
+                "f902ce",
+                "f90187",
+                "a01111111111111111111111111111111111111111111111111111111111111111",
+                "825208",
+                "b90100",
+                &hex::encode(bloom(&[(3, 0x10), (100, 0x02), (255, 0x80)])),
+                "f85e",
+                "f85c",
+                "942222222222222222222222222222222222222222",
+                // Topics are a list of strings.
+                "f842",
+                "a03333333333333333333333333333333333333333333333333333333333333333",
+                "a04444444444444444444444444444444444444444444444444444444444444444",
+                "820102",
+                "f90141",
+                "a05555555555555555555555555555555555555555555555555555555555555555",
+                "83010000",
+                "b90100",
+                &"00".repeat(256),
+                "d8",
+                "d7",
+                "946666666666666666666666666666666666666666",
+                "c0",
+                "80",
+            ])
+        );
+        assert_eq!(
+            hex::encode(keccak256(&encoded)),
+            "7358e04a2db18592ef1e25cd0cadab3a890d3c5a71ec2a978fd30c045fffbb6a"
+        );
+    }
+
+    #[test]
+    fn post_byzantium_status_receipt() {
+        // The status takes the place of the state root, as a one byte string.
+        let receipts = [Receipt {
+            state_root: vec![1],
+            cumulative_gas_used: 1_234_567,
+            logs_bloom: bloom(&[]),
+            logs: vec![Log {
+                address: vec![0x77; 20],
+                topics: vec![vec![0x88; 32]],
+                data: vec![0; 3],
+            }],
+        }];
+        let encoded = rlp_encode_legacy_receipts(&receipts.iter().collect::<Vec<_>>());
+
+        assert_eq!(
+            encoded,
+            concat(&[
+                "f9014a",
+                "f90147",
+                "01",
+                "8312d687",
+                "b90100",
+                &"00".repeat(256),
+                "f83d",
+                "f83b",
+                "947777777777777777777777777777777777777777",
+                "e1",
+                "a08888888888888888888888888888888888888888888888888888888888888888",
+                "83000000",
+            ])
+        );
+        assert_eq!(
+            hex::encode(keccak256(&encoded)),
+            "6da2e9218cc9c056b504d1c5e5ddd064aef60c6bb7db9fb75baf7c3fca2f86c5"
+        );
+    }
+
+    #[test]
+    fn list_helpers() {
+        let encoded = alloy_rlp::encode(block_1_header());
+        let items = list_items(&encoded).unwrap();
+        assert_eq!(items.len(), 15);
+        assert_eq!(items[8], [0x01]);
+        assert_eq!(raw_list(&items), encoded);
+        assert!(list_items(&[0x82, 0x01, 0x02]).is_err());
     }
 }
@@ -0,0 +1,296 @@
+//! Compares the blocks of an era file with an archive node over JSON-RPC.
+//!
+//! Nothing here goes through the encoders the sink writes era files with:
+//! block hashes are hashed from the stored headers, transactions are counted
+//! in the stored bodies and receipts roots are rebuilt from the stored
+//! receipts, then all of them are matched against what the node reports.
+
+use crate::http::HttpClient;
+use crate::uncles::data;
+use anyhow::{anyhow, bail, Context};
+use era_file_sink::compression::{snap_decode, MAX_ENTRY_SIZE};
+use era_file_sink::e2store::builder::MAX_HEADER_SIZE;
+use era_file_sink::e2store::{read_entry_header, E2StoreType, UnknownEntryPolicy, HEADER_SIZE};
+use era_file_sink::encryption::{EncryptionKey, EraReader};
+use era_file_sink::epoch::{first_block, EPOCH_SIZE};
+use era_file_sink::rlp::{list_items, raw_list};
+use reth_primitives::{keccak256, Header};
+use reth_rlp::Decodable;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// What was read from an era file for one block.
+struct EraBlock {
+    number: u64,
+    hash: [u8; 32],
+    transactions: usize,
+    receipts_root: [u8; 32],
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<RpcBlock>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcBlock {
+    hash: String,
+    transactions: Vec<serde_json::Value>,
+    receipts_root: String,
+}
+
+/// Outcome of [`check_epoch`].
+pub struct RpcReport {
+    pub file: PathBuf,
+    pub checked: usize,
+    /// Every difference with the node, empty if all checked blocks match.
+    pub problems: Vec<String>,
+}
+
+/// Finds the file of `epoch` among `files` and compares `samples` blocks
+/// spread over it, or all of them, with the node at `url`.
+pub async fn check_epoch(
+    url: &str,
+    epoch: u64,
+    files: &[PathBuf],
+    samples: Option<u64>,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+) -> Result<RpcReport, anyhow::Error> {
+    let mut era = None;
+    for file in files {
+        let blocks = read_blocks(file, unknown_entries, key)
+            .context(format!("read '{}'", file.display()))?;
+        if blocks.first().map(|block| block.number) == Some(first_block(epoch)) {
+            era = Some((file, blocks));
+            break;
+        }
+    }
+    let (file, blocks) = era.ok_or(anyhow!("none of the files holds epoch {}", epoch))?;
+
+    let indexes: Vec<usize> = match samples {
+        Some(samples) if samples < blocks.len() as u64 => (0..samples)
+            .map(|sample| (sample * blocks.len() as u64 / samples) as usize)
+            .collect(),
+        _ => (0..blocks.len()).collect(),
+    };
+
+    let http = HttpClient::from_env()?;
+    let mut report = RpcReport {
+        file: file.clone(),
+        checked: 0,
+        problems: Vec::new(),
+    };
+    for index in indexes {
+        let block = &blocks[index];
+        let remote = fetch_block(&http, url, block.number)
+            .await
+            .context(format!("fetch block {}", block.number))?;
+        report.checked += 1;
+
+        if data(&remote.hash)? != block.hash {
+            report.problems.push(format!(
+                "block {} has hash 0x{}, the node has {}",
+                block.number,
+                hex::encode(block.hash),
+                remote.hash
+            ));
+        }
+        if remote.transactions.len() != block.transactions {
+            report.problems.push(format!(
+                "block {} has {} transactions, the node has {}",
+                block.number,
+                block.transactions,
+                remote.transactions.len()
+            ));
+        }
+        if data(&remote.receipts_root)? != block.receipts_root {
+            report.problems.push(format!(
+                "receipts of block {} have root 0x{}, the node has {}",
+                block.number,
+                hex::encode(block.receipts_root),
+                remote.receipts_root
+            ));
+        }
+    }
+
+    Ok(report)
+}
+
+async fn fetch_block(http: &HttpClient, url: &str, number: u64) -> Result<RpcBlock, anyhow::Error> {
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "eth_getBlockByNumber",
+        "params": [format!("{:#x}", number), false],
+    });
+    let response: RpcResponse = http
+        .send(|client| client.post(url).json(&request))
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    if let Some(error) = response.error {
+        bail!("node returned an error: {}", error);
+    }
+
+    response
+        .result
+        .ok_or(anyhow!("node doesn't know this block"))
+}
+
+/// Reads the hash, transaction count and receipts root of every block in
+/// the era file at `path`.
+fn read_blocks(
+    path: &Path,
+    unknown_entries: UnknownEntryPolicy,
+    key: Option<&EncryptionKey>,
+) -> Result<Vec<EraBlock>, anyhow::Error> {
+    let mut reader = EraReader::open(path, key)?;
+    let mut blocks = Vec::new();
+    let mut header = None;
+    let mut transactions = 0;
+    let mut offset = 0;
+    loop {
+        let (type_, length) = match read_entry_header(&mut reader) {
+            Ok(header) => header,
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        unknown_entries.check_era1(type_, offset)?;
+        offset += HEADER_SIZE + length as u64;
+        let mut data = vec![0; length as usize];
+        reader.read_exact(&mut data)?;
+
+        if type_ == E2StoreType::CompressedHeader as u16 {
+            let rlp = snap_decode(&data, MAX_HEADER_SIZE)?;
+            let number = Header::decode(&mut rlp.as_slice())
+                .map_err(|err| anyhow!("invalid header RLP: {:?}", err))?
+                .number;
+            header = Some((number, keccak256(&rlp).0));
+        } else if type_ == E2StoreType::CompressedBody as u16 {
+            let rlp = snap_decode(&data, MAX_ENTRY_SIZE)?;
+            let body = list_items(&rlp)?;
+            transactions = list_items(body.first().ok_or(anyhow!("empty block body"))?)?.len();
+        } else if type_ == E2StoreType::CompressedReceipts as u16 {
+            let (number, hash) = header.ok_or(anyhow!("receipts entry before any header"))?;
+            let rlp = snap_decode(&data, MAX_ENTRY_SIZE)?;
+            blocks.push(EraBlock {
+                number,
+                hash,
+                transactions,
+                receipts_root: receipts_root(&rlp)
+                    .context(format!("receipts of block {}", number))?,
+            });
+        } else if type_ == E2StoreType::Accumulator as u16 {
+            break;
+        }
+    }
+    if blocks.len() as u64 > EPOCH_SIZE {
+        bail!("holds {} blocks, more than an epoch", blocks.len());
+    }
+
+    Ok(blocks)
+}
+
+/// Root of the trie mapping the RLP encoded index of each receipt in the
+/// encoded list `receipts` to its consensus encoding. Typed receipts are
+/// stored as strings holding the type and the receipt.
+fn receipts_root(receipts: &[u8]) -> Result<[u8; 32], anyhow::Error> {
+    let mut items = Vec::new();
+    for (index, receipt) in list_items(receipts)?.into_iter().enumerate() {
+        let mut payload = receipt;
+        let header = alloy_rlp::Header::decode(&mut payload)?;
+        let value = if header.list {
+            receipt.to_vec()
+        } else {
+            payload[..header.payload_length].to_vec()
+        };
+        items.push((nibbles(&alloy_rlp::encode(index)), value));
+    }
+    items.sort();
+
+    Ok(keccak256(trie_node(&items, 0)).0)
+}
+
+fn nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// Encodes the node holding `items`, sorted by key, whose first `depth`
+/// nibbles are already consumed by the path leading to it.
+fn trie_node(items: &[(Vec<u8>, Vec<u8>)], depth: usize) -> Vec<u8> {
+    let fields = match items {
+        [] => return string(&[]),
+        [(key, value)] => vec![string(&compact_path(&key[depth..], true)), string(value)],
+        _ => {
+            let first = &items[0].0;
+            let last = &items[items.len() - 1].0;
+            let shared = first[depth..]
+                .iter()
+                .zip(&last[depth..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if shared > 0 {
+                vec![
+                    string(&compact_path(&first[depth..depth + shared], false)),
+                    child(&trie_node(items, depth + shared)),
+                ]
+            } else {
+                let mut fields: Vec<Vec<u8>> = (0..16)
+                    .map(|nibble| {
+                        let children: Vec<_> = items
+                            .iter()
+                            .filter(|(key, _)| key.get(depth) == Some(&nibble))
+                            .cloned()
+                            .collect();
+                        if children.is_empty() {
+                            string(&[])
+                        } else {
+                            child(&trie_node(&children, depth + 1))
+                        }
+                    })
+                    .collect();
+                fields.push(match items.iter().find(|(key, _)| key.len() == depth) {
+                    Some((_, value)) => string(value),
+                    None => string(&[]),
+                });
+                fields
+            }
+        }
+    };
+
+    raw_list(&fields)
+}
+
+fn string(bytes: &[u8]) -> Vec<u8> {
+    alloy_rlp::encode(bytes)
+}
+
+/// Nodes shorter than a hash are embedded in their parent, others are
+/// referenced by hash.
+fn child(node: &[u8]) -> Vec<u8> {
+    if node.len() < 32 {
+        node.to_vec()
+    } else {
+        string(&keccak256(node).0)
+    }
+}
+
+/// Hex prefix encoding of a path of nibbles.
+fn compact_path(path: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut compact = if path.len() % 2 == 1 {
+        vec![((flag + 1) << 4) | path[0]]
+    } else {
+        vec![flag << 4]
+    };
+    let even = &path[path.len() % 2..];
+    compact.extend(even.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    compact
+}
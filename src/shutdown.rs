@@ -0,0 +1,64 @@
+//! Stops a run cleanly on SIGINT or SIGTERM. The sink stops reading the
+//! stream, syncs the era in progress and persists the cursor of its last
+//! block, so the next run resumes inside that era instead of rebuilding it.
+
+use std::fmt::{self, Display};
+
+/// Listens for termination signals. Once created, the signals no longer kill
+/// the process, so it is only created where a run can stop cleanly.
+pub struct Shutdown {
+    #[cfg(unix)]
+    interrupt: tokio::signal::unix::Signal,
+    #[cfg(unix)]
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl Shutdown {
+    pub fn listen() -> Result<Self, anyhow::Error> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            Ok(Self {
+                interrupt: signal(SignalKind::interrupt())?,
+                terminate: signal(SignalKind::terminate())?,
+            })
+        }
+        #[cfg(not(unix))]
+        Ok(Self {})
+    }
+
+    /// Resolves with the name of the next termination signal.
+    pub async fn requested(&mut self) -> &'static str {
+        #[cfg(unix)]
+        {
+            tokio::select! {
+                _ = self.interrupt.recv() => "SIGINT",
+                _ = self.terminate.recv() => "SIGTERM",
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            if tokio::signal::ctrl_c().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+            "Ctrl-C"
+        }
+    }
+}
+
+/// The error of a run stopped by `signal` once its state is saved.
+#[derive(Debug)]
+pub struct Interrupted(pub &'static str);
+
+impl Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stopped by {}, the next run resumes where this one stopped",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for Interrupted {}
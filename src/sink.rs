@@ -0,0 +1,375 @@
+//! Where finished era files end up, picked by the scheme of `--output`: a
+//! local directory, `s3://`, `gs://` or `az://`. Era files are always built in
+//! the local output directory, which needs seeking and syncing, then each
+//! finished file goes through [`Sink::publish`] whatever the destination is.
+//! The cursor follows once an era is published, so the copy next to the files
+//! never points past the files stored there.
+//!
+//! The sink hands finished eras to an [`UploadQueue`] and goes on streaming
+//! while they are uploaded, as uploads and the stream are limited by different
+//! things. The stream only waits once too many eras are queued.
+//!
+//! Cloud stores keep one pooled client for the whole run and retry with the
+//! [`HttpSettings`] of the other HTTP clients. S3 uploads carry an
+//! `x-amz-checksum-sha256` header, so every request, and every part of a
+//! multipart upload, is checked by the server before it is stored.
+
+use crate::http::HttpSettings;
+use anyhow::{anyhow, bail, Context};
+use futures03::TryStreamExt;
+use object_store::aws::{AmazonS3Builder, Checksum};
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, ObjectStoreScheme, PutPayload, WriteMultipart};
+use reqwest::Url;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// Files larger than this are uploaded in parts of this size, so a failed
+/// request only resends one part.
+const UPLOAD_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Parts of one file uploaded at the same time.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+const DEFAULT_UPLOAD_CONCURRENCY: usize = 4;
+const DEFAULT_UPLOAD_QUEUE_DEPTH: usize = 8;
+
+/// A destination for finished files. Cloud credentials are read from the
+/// environment variables of each provider, such as `AWS_ACCESS_KEY_ID`,
+/// `GOOGLE_APPLICATION_CREDENTIALS` or `AZURE_STORAGE_ACCOUNT_KEY`.
+pub struct Sink {
+    url: String,
+    store: Box<dyn ObjectStore>,
+    prefix: ObjectPath,
+    stores_cursor: bool,
+}
+
+impl Sink {
+    /// Opens the destination at `url`. Era files are built in `staging_dir`,
+    /// which can't be the destination too, as published files are removed
+    /// from it.
+    pub fn from_url(
+        url: &str,
+        staging_dir: &Path,
+        http: &HttpSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let parsed = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            // Plain paths are local directories.
+            Err(_) => {
+                fs::create_dir_all(url).context(format!("create output '{}'", url))?;
+                Url::from_directory_path(fs::canonicalize(url)?)
+                    .map_err(|_| anyhow!("output '{}' is not a valid directory", url))?
+            }
+        };
+        let (scheme, path) = ObjectStoreScheme::parse(&parsed)
+            .map_err(|_| anyhow!("output '{}' is not a path or a storage URL", url))?;
+        let (options, retry) = http.object_store_options();
+        let store: Box<dyn ObjectStore> = match scheme {
+            ObjectStoreScheme::Local => {
+                let directory = parsed
+                    .to_file_path()
+                    .map_err(|_| anyhow!("output '{}' is not a valid directory", url))?;
+                if fs::canonicalize(staging_dir).ok() == fs::canonicalize(&directory).ok() {
+                    bail!(
+                        "output '{}' is the output directory, leave --output out to keep files there",
+                        url
+                    );
+                }
+                Box::new(LocalFileSystem::new())
+            }
+            ObjectStoreScheme::AmazonS3 => Box::new(
+                AmazonS3Builder::from_env()
+                    .with_url(url)
+                    .with_client_options(options)
+                    .with_retry(retry)
+                    .with_checksum_algorithm(Checksum::SHA256)
+                    .build()?,
+            ),
+            ObjectStoreScheme::GoogleCloudStorage => Box::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_url(url)
+                    .with_client_options(options)
+                    .with_retry(retry)
+                    .build()?,
+            ),
+            ObjectStoreScheme::MicrosoftAzure => Box::new(
+                MicrosoftAzureBuilder::from_env()
+                    .with_url(url)
+                    .with_client_options(options)
+                    .with_retry(retry)
+                    .build()?,
+            ),
+            _ => bail!(
+                "unsupported output '{}', expected a local path or an s3://, gs:// or az:// URL",
+                url
+            ),
+        };
+
+        Ok(Self {
+            url: url.to_string(),
+            store,
+            prefix: ObjectPath::parse(path)?,
+            stores_cursor: true,
+        })
+    }
+
+    /// Leaves the cursor out, for runs that only write part of the eras at
+    /// the destination and must not replace its cursor.
+    pub fn without_cursor(mut self) -> Self {
+        self.stores_cursor = false;
+        self
+    }
+
+    pub fn stores_cursor(&self) -> bool {
+        self.stores_cursor
+    }
+
+    pub fn describe(&self) -> &str {
+        &self.url
+    }
+
+    fn location(&self, file_name: &str) -> ObjectPath {
+        self.prefix.child(file_name)
+    }
+
+    /// Downloads `file_name`, `None` if it doesn't exist.
+    pub async fn download(&self, file_name: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        match self.store.get(&self.location(file_name)).await {
+            Ok(result) => Ok(Some(result.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(err).context(format!("download '{}'", file_name)),
+        }
+    }
+
+    /// Stores the file at `path` under its file name.
+    pub async fn upload(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let file_name = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .ok_or(anyhow!("'{}' has no file name", path.display()))?;
+        let location = self.location(file_name);
+        let mut file = File::open(path).context(format!("open '{}'", path.display()))?;
+
+        if file.metadata()?.len() <= UPLOAD_PART_SIZE as u64 {
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            self.store
+                .put(&location, PutPayload::from(data))
+                .await
+                .context(format!("upload '{}'", path.display()))?;
+            return Ok(());
+        }
+
+        let mut upload = WriteMultipart::new_with_chunk_size(
+            self.store.put_multipart(&location).await?,
+            UPLOAD_PART_SIZE,
+        );
+        let mut part = vec![0; UPLOAD_PART_SIZE];
+        loop {
+            let length = file.read(&mut part)?;
+            if length == 0 {
+                break;
+            }
+            if let Err(err) = upload.wait_for_capacity(MAX_CONCURRENT_PARTS).await {
+                upload.abort().await.ok();
+                return Err(err).context(format!("upload '{}'", path.display()));
+            }
+            upload.write(&part[..length]);
+        }
+        upload
+            .finish()
+            .await
+            .context(format!("upload '{}'", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Stores `data` under `file_name`.
+    pub async fn upload_bytes(&self, file_name: &str, data: Vec<u8>) -> Result<(), anyhow::Error> {
+        self.store
+            .put(&self.location(file_name), PutPayload::from(data))
+            .await
+            .context(format!("upload '{}'", file_name))?;
+
+        Ok(())
+    }
+
+    /// Names of the files stored at the destination.
+    pub async fn file_names(&self) -> Result<Vec<String>, anyhow::Error> {
+        let objects: Vec<_> = self
+            .store
+            .list(Some(&self.prefix))
+            .try_collect()
+            .await
+            .context(format!("list '{}'", self.url))?;
+
+        Ok(objects
+            .into_iter()
+            .filter_map(|object| object.location.filename().map(str::to_string))
+            .collect())
+    }
+
+    /// Stores each of `paths` and removes the local copy once it is stored.
+    pub async fn publish(&self, paths: &[PathBuf]) -> Result<(), anyhow::Error> {
+        for path in paths {
+            self.upload(path).await?;
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// How finished eras are uploaded, from `UPLOAD_CONCURRENCY`, the eras
+/// uploaded at the same time, 4 by default, and `UPLOAD_QUEUE_DEPTH`, the
+/// finished eras waiting for their upload before the stream pauses, 8 by
+/// default.
+#[derive(Clone, Copy, Debug)]
+pub struct UploadSettings {
+    pub concurrency: usize,
+    pub queue_depth: usize,
+}
+
+impl UploadSettings {
+    pub fn from_env() -> Result<Self, anyhow::Error> {
+        let concurrency = match env::var("UPLOAD_CONCURRENCY") {
+            Ok(concurrency) => concurrency
+                .parse::<usize>()
+                .context("UPLOAD_CONCURRENCY is not a valid integer")?,
+            Err(_) => DEFAULT_UPLOAD_CONCURRENCY,
+        };
+        let queue_depth = match env::var("UPLOAD_QUEUE_DEPTH") {
+            Ok(depth) => depth
+                .parse::<usize>()
+                .context("UPLOAD_QUEUE_DEPTH is not a valid integer")?,
+            Err(_) => DEFAULT_UPLOAD_QUEUE_DEPTH,
+        };
+        if concurrency == 0 || queue_depth == 0 {
+            bail!("UPLOAD_CONCURRENCY and UPLOAD_QUEUE_DEPTH must be at least 1");
+        }
+
+        Ok(Self {
+            concurrency,
+            queue_depth,
+        })
+    }
+}
+
+/// A finished era being uploaded.
+struct QueuedEra {
+    upload: JoinHandle<Result<(), anyhow::Error>>,
+    /// The cursor persisted after the era, stored once the era and every era
+    /// before it are.
+    cursor: Vec<u8>,
+}
+
+/// Uploads finished eras in the background, in order of completion for the
+/// cursor.
+pub struct UploadQueue {
+    sink: Arc<Sink>,
+    permits: Arc<Semaphore>,
+    concurrency: usize,
+    queue_depth: usize,
+    queued: VecDeque<QueuedEra>,
+    cursor_file: String,
+}
+
+impl UploadQueue {
+    /// `cursor_file` is the name the cursor is stored under.
+    pub fn new(sink: Sink, settings: UploadSettings, cursor_file: &str) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            permits: Arc::new(Semaphore::new(settings.concurrency)),
+            concurrency: settings.concurrency,
+            queue_depth: settings.queue_depth,
+            queued: VecDeque::new(),
+            cursor_file: cursor_file.to_string(),
+        }
+    }
+
+    /// Starts publishing the files of a finished era, followed by `cursor`.
+    /// Waits first while the queue is full.
+    pub async fn push(
+        &mut self,
+        paths: Vec<PathBuf>,
+        cursor: Vec<u8>,
+    ) -> Result<(), anyhow::Error> {
+        self.store_completed(false).await?;
+        while self.queued.len() >= self.queue_depth {
+            self.store_front().await?;
+        }
+
+        let sink = self.sink.clone();
+        let permits = self.permits.clone();
+        let upload = tokio::spawn(async move {
+            let _permit = permits.acquire_owned().await?;
+            sink.publish(&paths).await
+        });
+        self.queued.push_back(QueuedEra { upload, cursor });
+
+        Ok(())
+    }
+
+    pub fn sink(&self) -> &Sink {
+        &self.sink
+    }
+
+    /// Waits for every queued era and stores the last cursor.
+    pub async fn finish(&mut self) -> Result<(), anyhow::Error> {
+        self.store_completed(true).await
+    }
+
+    /// Finished eras not stored yet, and how many of them are being uploaded
+    /// right now.
+    pub fn depth(&self) -> (usize, usize) {
+        let queued = self
+            .queued
+            .iter()
+            .filter(|era| !era.upload.is_finished())
+            .count();
+        (queued, self.concurrency - self.permits.available_permits())
+    }
+
+    /// Takes the eras at the front of the queue whose upload is over, or all
+    /// of them if `wait`, then stores the cursor of the last one.
+    async fn store_completed(&mut self, wait: bool) -> Result<(), anyhow::Error> {
+        let mut cursor = None;
+        while let Some(era) = self.queued.pop_front() {
+            if !wait && !era.upload.is_finished() {
+                self.queued.push_front(era);
+                break;
+            }
+            era.upload.await??;
+            cursor = Some(era.cursor);
+        }
+        if let (Some(cursor), true) = (cursor, self.sink.stores_cursor()) {
+            self.sink.upload_bytes(&self.cursor_file, cursor).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the era at the front of the queue and stores its cursor.
+    async fn store_front(&mut self) -> Result<(), anyhow::Error> {
+        if let Some(era) = self.queued.pop_front() {
+            era.upload.await??;
+            if self.sink.stores_cursor() {
+                self.sink
+                    .upload_bytes(&self.cursor_file, era.cursor)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+}
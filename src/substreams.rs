@@ -1,17 +1,22 @@
-use std::{fmt::Display, sync::Arc, time::Duration};
+use std::{
+    fmt::Display,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use http::{uri::Scheme, Uri};
-use revm_primitives::HashMap;
 use serde::Deserialize;
+use tokio::sync::Mutex;
 
 use tonic::{
     codegen::http,
     metadata::MetadataValue,
-    transport::{Channel, ClientTlsConfig},
+    transport::{Channel, ClientTlsConfig, Endpoint},
 };
 
-use crate::pb::sf::substreams::rpc::v2::{stream_client::StreamClient, Request, Response};
+use crate::http::HttpClient;
+use era_file_sink::pb::client::rpc::v2::{stream_client::StreamClient, Request, Response};
 
 #[derive(Deserialize)]
 struct SFRes {
@@ -19,10 +24,37 @@ struct SFRes {
     // expires_at: u64, // Using u64 for timestamps is typical in Rust
 }
 
-#[derive(Clone, Debug)]
+/// Lifetime asked for session tokens.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// How long before it expires a session token is replaced.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+struct SessionToken {
+    value: String,
+    expires_at: Instant,
+}
+
+/// How requests to the endpoint are authenticated.
+#[derive(Debug)]
+pub enum Credentials {
+    /// An API key, exchanged for session tokens.
+    ApiKey(String),
+    /// A token sent as is, for long-lived tokens and for self-hosted
+    /// endpoints that don't go through the StreamingFast auth server. It is
+    /// never replaced, so it must outlast the run.
+    Token(String),
+}
+
+#[derive(Debug)]
 pub struct SubstreamsEndpoint {
     pub uri: String,
-    pub token: Option<String>,
+    credentials: Option<Credentials>,
+    http: HttpClient,
+    /// Issued from the API key and issued again shortly before it expires,
+    /// so runs can last longer than a token.
+    token: Mutex<Option<SessionToken>>,
     channel: Channel,
 }
 
@@ -35,74 +67,74 @@ impl Display for SubstreamsEndpoint {
 impl SubstreamsEndpoint {
     pub async fn new<S: AsRef<str>>(
         url: S,
-        api_key: Option<String>,
+        credentials: Option<Credentials>,
+        http: &HttpClient,
     ) -> Result<Self, anyhow::Error> {
-        let uri = url
-            .as_ref()
-            .parse::<Uri>()
-            .expect("the url should have been validated by now, so it is a valid Uri");
-
-        let client = reqwest::Client::new();
+        let endpoint = channel_endpoint(parse_url(url.as_ref())?)?;
 
-        let mut map = HashMap::new();
+        let uri = endpoint.uri().to_string();
+        let channel = endpoint.connect_lazy();
 
-        // Insert the api_key as a reference to a string slice (`&str`).
+        let endpoint = SubstreamsEndpoint {
+            uri,
+            credentials,
+            http: http.clone(),
+            token: Mutex::new(None),
+            channel,
+        };
+        // Issued right away, so a bad API key stops the run before it starts.
+        endpoint.token().await?;
 
-        let data = format!(
-            r#"{{"api_key": "{}", "lifetime": {}}}"#,
-            api_key.unwrap(),
-            "3600"
-        );
+        Ok(endpoint)
+    }
 
-        map.insert("lifetime", "3600");
-        let response = client
-            .post("https://auth.streamingfast.io/v1/auth/issue")
-            .header("Content-Type", "application/json") // Explicitly set the content type.
-            .body(data.to_string())
-            .send() // Send the request.
-            .await?; // Wait for the response.
-
-        let token;
-        if response.status().is_success() {
-            let sf_res: SFRes = response.json().await?;
-            token = Some(sf_res.token);
-        } else {
-            eprintln!("Failed to get a successful response: {}", response.status());
-            if let Ok(err_body) = response.text().await {
-                eprintln!("Error details: {}", err_body);
-            }
-            return Err(anyhow!("failed to fetch token"));
+    /// The session token to send, issued again if it is about to expire.
+    async fn token(&self) -> Result<Option<String>, anyhow::Error> {
+        let api_key = match &self.credentials {
+            Some(Credentials::ApiKey(api_key)) => api_key,
+            Some(Credentials::Token(token)) => return Ok(Some(token.clone())),
+            None => return Ok(None),
+        };
+        let mut token = self.token.lock().await;
+        let fresh = token
+            .as_ref()
+            .is_some_and(|token| token.expires_at > Instant::now() + TOKEN_REFRESH_MARGIN);
+        if !fresh {
+            let issued_at = Instant::now();
+            *token = Some(SessionToken {
+                value: issue_token(api_key, &self.http).await?,
+                expires_at: issued_at + TOKEN_LIFETIME,
+            });
         }
 
-        let endpoint = match uri.scheme().unwrap_or(&Scheme::HTTP).as_str() {
-            "http" => Channel::builder(uri),
-            "https" => Channel::builder(uri)
-                .tls_config(ClientTlsConfig::new())
-                .expect("TLS config on this host is invalid"),
-            _ => panic!("invalid uri scheme for firehose endpoint"),
-        }
-        .connect_timeout(Duration::from_secs(10))
-        .tcp_keepalive(Some(Duration::from_secs(30)));
+        Ok(token.as_ref().map(|token| token.value.clone()))
+    }
 
-        let uri = endpoint.uri().to_string();
-        let channel = endpoint.connect_lazy();
+    /// Drops the session token after the endpoint rejected it, so the next
+    /// request issues a new one. Returns false if the token can't be
+    /// replaced, because it was given as is.
+    pub async fn reject_token(&self) -> bool {
+        *self.token.lock().await = None;
+        matches!(self.credentials, Some(Credentials::ApiKey(_)))
+    }
 
-        Ok(SubstreamsEndpoint {
-            uri,
-            channel,
-            token,
-        })
+    /// Opens a connection to `url` right away instead of on first use, to
+    /// check that the endpoint can be reached.
+    pub async fn check_reachable(url: &str) -> Result<(), anyhow::Error> {
+        channel_endpoint(parse_url(url)?)?.connect().await?;
+
+        Ok(())
     }
 
     pub async fn substreams(
         self: Arc<Self>,
         request: Request,
     ) -> Result<tonic::Streaming<Response>, anyhow::Error> {
-        let token_metadata: Option<MetadataValue<tonic::metadata::Ascii>> = match self.token.clone()
-        {
-            Some(token) => Some(token.as_str().try_into()?),
-            None => None,
-        };
+        let token_metadata: Option<MetadataValue<tonic::metadata::Ascii>> =
+            match self.token().await? {
+                Some(token) => Some(token.as_str().try_into()?),
+                None => None,
+            };
 
         let mut client = StreamClient::with_interceptor(
             self.channel.clone(),
@@ -121,3 +153,59 @@ impl SubstreamsEndpoint {
         Ok(block_stream)
     }
 }
+
+/// Exchanges an API key for a session token.
+pub async fn issue_token(api_key: &str, http: &HttpClient) -> Result<String, anyhow::Error> {
+    let body = serde_json::json!({
+        "api_key": api_key,
+        "lifetime": TOKEN_LIFETIME.as_secs(),
+    });
+    let response = http
+        .send(|client| {
+            client
+                .post("https://auth.streamingfast.io/v1/auth/issue")
+                .json(&body)
+        })
+        .await?;
+
+    let status = response.status();
+    if status.is_success() {
+        let sf_res: SFRes = response.json().await?;
+        println!("Auth: issued a session token");
+        Ok(sf_res.token)
+    } else {
+        eprintln!("Failed to get a successful response: {}", status);
+        if let Ok(err_body) = response.text().await {
+            eprintln!("Error details: {}", err_body);
+        }
+        Err(anyhow!(
+            "failed to fetch token, auth server answered {}",
+            status
+        ))
+    }
+}
+
+/// Parses the URL of a Substreams endpoint.
+pub fn parse_url(url: &str) -> Result<Uri, anyhow::Error> {
+    url.parse::<Uri>()
+        .context(format!("'{}' is not a valid url", url))
+}
+
+fn channel_endpoint(uri: Uri) -> Result<Endpoint, anyhow::Error> {
+    let endpoint = match uri.scheme().unwrap_or(&Scheme::HTTP).as_str() {
+        "http" => Channel::builder(uri),
+        "https" => Channel::builder(uri)
+            .tls_config(ClientTlsConfig::new())
+            .context("TLS config on this host is invalid")?,
+        scheme => {
+            return Err(anyhow!(
+                "invalid uri scheme '{}' for firehose endpoint, expected http or https",
+                scheme
+            ))
+        }
+    };
+
+    Ok(endpoint
+        .connect_timeout(Duration::from_secs(10))
+        .tcp_keepalive(Some(Duration::from_secs(30))))
+}
@@ -2,26 +2,42 @@ use anyhow::{anyhow, Error};
 use async_stream::try_stream;
 use futures03::{Stream, StreamExt};
 use std::{
+    collections::BTreeMap,
+    path::Path,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
     time::Duration,
 };
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
 use tokio_retry::strategy::ExponentialBackoff;
+use tonic::Code;
 
-use crate::pb::sf::substreams::rpc::v2::{
-    response::Message, BlockScopedData, BlockUndoSignal, Request, Response,
+use era_file_sink::pb::sf::substreams::rpc::v2::{
+    module_progress::Type, response::Message, BlockScopedData, BlockUndoSignal, ModulesProgress,
+    Request, Response, SessionInit,
 };
-use crate::pb::sf::substreams::v1::Modules;
+use era_file_sink::pb::sf::substreams::v1::Modules;
 
+use crate::recording::{read_recorded_block, recorded_blocks};
 use crate::substreams::SubstreamsEndpoint;
+use crate::throttle::Throttle;
 
 pub enum BlockResponse {
     New(BlockScopedData),
     Undo(BlockUndoSignal),
+    /// Sent once per connection, before any data.
+    Session(SessionInit),
+    /// Sent while the server prepares data, so a stream that is still working
+    /// can be told apart from one that went silent.
+    Progress(ModulesProgress),
 }
 
+/// The one path blocks take from the Substreams endpoint to the era files.
+///
+/// Reconnection, cursor tracking and stall detection happen inside the
+/// stream, so the consumer only polls typed [`BlockResponse`] events, in
+/// order, and feeds new blocks into the `EraBuilder`.
 pub struct SubstreamsStream {
     stream: Pin<Box<dyn Stream<Item = Result<BlockResponse, Error>> + Send>>,
 }
@@ -34,6 +50,7 @@ impl SubstreamsStream {
         output_module_name: String,
         start_block: i64,
         end_block: u64,
+        receive_timeout: Duration,
     ) -> Self {
         SubstreamsStream {
             stream: Box::pin(stream_blocks(
@@ -43,12 +60,28 @@ impl SubstreamsStream {
                 output_module_name,
                 start_block,
                 end_block,
+                receive_timeout,
             )),
         }
     }
+
+    /// Plays back the blocks recorded in `dir` from `start_block` on, in
+    /// place of a live session.
+    pub fn replay(dir: &Path, start_block: u64) -> Result<Self, Error> {
+        let blocks = recorded_blocks(dir, start_block)?;
+
+        Ok(SubstreamsStream {
+            stream: Box::pin(
+                futures03::stream::iter(blocks)
+                    .map(|path| read_recorded_block(&path).map(BlockResponse::New)),
+            ),
+        })
+    }
 }
 
 // Create the Stream implementation that streams blocks with auto-reconnection.
+// A connection that stays silent for longer than `receive_timeout` is treated
+// as failed and reconnected from the latest cursor.
 fn stream_blocks(
     endpoint: Arc<SubstreamsEndpoint>,
     cursor: Option<String>,
@@ -56,12 +89,17 @@ fn stream_blocks(
     output_module_name: String,
     start_block_num: i64,
     stop_block_num: u64,
+    receive_timeout: Duration,
 ) -> impl Stream<Item = Result<BlockResponse, Error>> {
     let mut latest_cursor = cursor.unwrap_or_default();
     let mut backoff = ExponentialBackoff::from_millis(500).max_delay(Duration::from_secs(45));
+    let mut terminations = Terminations::default();
+    // Whether the endpoint rejected the session token since the last block.
+    let mut token_rejected = false;
 
     try_stream! {
         loop {
+            let permit = Throttle::global().acquire().await;
             let result = endpoint.clone().substreams(Request {
                 start_block_num,
                 start_cursor: latest_cursor.clone(),
@@ -78,13 +116,31 @@ fn stream_blocks(
             }).await;
 
             match result {
-                Ok(stream) => {
+                Ok(mut stream) => {
                     let mut encountered_error = false;
-                    for await response in stream{
+                    loop {
+                        let response = match timeout(receive_timeout, stream.next()).await {
+                            Ok(Some(response)) => response,
+                            Ok(None) => break,
+                            Err(_) => {
+                                terminations.record("ReceiveTimeout", format!("no message received for {:?}", receive_timeout));
+                                encountered_error = true;
+                                break;
+                            }
+                        };
+
+                        #[cfg(feature = "chaos")]
+                        era_file_sink::chaos::inject_delay().await;
+
                         match process_substreams_response(response).await {
                             BlockProcessedResult::BlockScopedData(block_scoped_data) => {
+                                #[cfg(feature = "chaos")]
+                                let block_scoped_data = era_file_sink::chaos::inject_corruption(block_scoped_data);
+
                                 // Reset backoff because we got a good value from the stream
                                 backoff = ExponentialBackoff::from_millis(500).max_delay(Duration::from_secs(45));
+                                token_rejected = false;
+                                Throttle::global().received();
 
                                 let cursor = block_scoped_data.cursor.clone();
                                 yield BlockResponse::New(block_scoped_data);
@@ -100,19 +156,39 @@ fn stream_blocks(
 
                                 latest_cursor = cursor;
                             },
+                            BlockProcessedResult::Session(session) => {
+                                yield BlockResponse::Session(session);
+                            },
+                            BlockProcessedResult::Progress(progress) => {
+                                yield BlockResponse::Progress(progress);
+                            },
                             BlockProcessedResult::Skip() => {},
                             BlockProcessedResult::TonicError(status) => {
-                                // Unauthenticated errors are not retried, we forward the error back to the
-                                // stream consumer which handles it
+                                // A rejected session token is replaced once, it may have
+                                // expired or been revoked. If the new one is rejected too,
+                                // or the token was given as is, the error is forwarded back
+                                // to the stream consumer which handles it
                                 if status.code() == tonic::Code::Unauthenticated {
-                                    return Err(anyhow::Error::new(status.clone()))?;
+                                    if token_rejected || !endpoint.reject_token().await {
+                                        return Err(anyhow::Error::new(status.clone()))?;
+                                    }
+                                    token_rejected = true;
+                                }
+                                if status.code() == tonic::Code::ResourceExhausted {
+                                    Throttle::global().rate_limited();
                                 }
 
-                                println!("Received tonic error {:#}", status);
+                                terminations.record_status(&status);
                                 encountered_error = true;
                                 break;
                             },
                         }
+
+                        #[cfg(feature = "chaos")]
+                        if era_file_sink::chaos::inject_disconnect() {
+                            encountered_error = true;
+                            break;
+                        }
                     }
 
                     if !encountered_error {
@@ -125,10 +201,26 @@ fn stream_blocks(
                     // case where we actually _want_ to back off in case we keep
                     // having connection errors.
 
-                    println!("Unable to connect to endpoint: {:#}", e);
+                    match e.downcast_ref::<tonic::Status>() {
+                        Some(status) if status.code() == tonic::Code::Unauthenticated => {
+                            // A token given as is would only be rejected again.
+                            if !endpoint.reject_token().await {
+                                return Err(anyhow::Error::new(status.clone()))?;
+                            }
+                            terminations.record_status(status);
+                        }
+                        Some(status) => {
+                            if status.code() == tonic::Code::ResourceExhausted {
+                                Throttle::global().rate_limited();
+                            }
+                            terminations.record_status(status);
+                        }
+                        None => terminations.record("Connect", format!("{:#}", e)),
+                    }
                 }
             }
 
+            drop(permit);
             // If we reach this point, we must wait a bit before retrying
             if let Some(duration) = backoff.next() {
                 sleep(duration).await
@@ -139,10 +231,54 @@ fn stream_blocks(
     }
 }
 
+/// Why connections ended, by gRPC status code or local reason, so the logs
+/// tell quota exhaustion apart from network flakiness when a backfill slows
+/// down.
+#[derive(Default)]
+struct Terminations {
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl Terminations {
+    fn record_status(&mut self, status: &tonic::Status) {
+        let reason = match status.code() {
+            Code::ResourceExhausted => "ResourceExhausted",
+            Code::Unavailable => "Unavailable",
+            Code::Unauthenticated => "Unauthenticated",
+            Code::DeadlineExceeded => "DeadlineExceeded",
+            Code::Internal => "Internal",
+            Code::Cancelled => "Cancelled",
+            _ => "OtherStatus",
+        };
+        let hint = match status.code() {
+            Code::ResourceExhausted => ", the API key is likely out of quota",
+            Code::Unavailable => ", the endpoint or the network is having trouble",
+            _ => "",
+        };
+        self.record(reason, format!("{:#}{}", status, hint));
+    }
+
+    fn record(&mut self, reason: &'static str, details: String) {
+        *self.counts.entry(reason).or_default() += 1;
+        println!(
+            "Stream terminated ({}): {}, reconnecting. Terminations so far: {}",
+            reason,
+            details,
+            self.counts
+                .iter()
+                .map(|(reason, count)| format!("{}={}", reason, count))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
+
 enum BlockProcessedResult {
     Skip(),
     BlockScopedData(BlockScopedData),
     BlockUndoSignal(BlockUndoSignal),
+    Session(SessionInit),
+    Progress(ModulesProgress),
     TonicError(tonic::Status),
 }
 
@@ -161,42 +297,14 @@ async fn process_substreams_response(
         Some(Message::BlockUndoSignal(block_undo_signal)) => {
             BlockProcessedResult::BlockUndoSignal(block_undo_signal)
         }
-        Some(Message::Progress(_progress)) => {
+        Some(Message::Session(session)) => BlockProcessedResult::Session(session),
+        Some(Message::Progress(progress)) => {
             // The `ModulesProgress` messages goal is to report active parallel processing happening
             // either to fill up backward (relative to your request's start block) some missing state
-            // or pre-process forward blocks (again relative).
-            //
-            // You could log that in trace or accumulate to push as metrics. Here a snippet of code
-            // that prints progress to standard out. If your `BlockScopedData` messages seems to never
-            // arrive in production mode, it's because progresses is happening but not yet for the output
-            // module you requested.
-            //
-            // let progresses: Vec<_> = progress
-            //     .modules
-            //     .iter()
-            //     .filter_map(|module| {
-            //         use crate::pb::sf::substreams::rpc::v2::module_progress::Type;
-
-            //         if let Type::ProcessedRanges(range) = module.r#type.as_ref().unwrap() {
-            //             Some(format!(
-            //                 "{} @ [{}]",
-            //                 module.name,
-            //                 range
-            //                     .processed_ranges
-            //                     .iter()
-            //                     .map(|x| x.to_string())
-            //                     .collect::<Vec<_>>()
-            //                     .join(", ")
-            //             ))
-            //         } else {
-            //             None
-            //         }
-            //     })
-            //     .collect();
-
-            // println!("Progess {}", progresses.join(", "));
-
-            BlockProcessedResult::Skip()
+            // or pre-process forward blocks (again relative). If your `BlockScopedData` messages seems
+            // to never arrive in production mode, it's because progresses is happening but not yet for
+            // the output module you requested.
+            BlockProcessedResult::Progress(progress)
         }
         None => {
             println!("Got None on substream message");
@@ -206,6 +314,30 @@ async fn process_substreams_response(
     }
 }
 
+/// Summarizes a progress message as `module @ [ranges]` entries, reporting
+/// failed modules with their reason.
+pub fn describe_progress(progress: &ModulesProgress) -> String {
+    progress
+        .modules
+        .iter()
+        .filter_map(|module| match module.r#type.as_ref()? {
+            Type::ProcessedRanges(range) => Some(format!(
+                "{} @ [{}]",
+                module.name,
+                range
+                    .processed_ranges
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            Type::Failed(failed) => Some(format!("{} failed: {}", module.name, failed.reason)),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl Stream for SubstreamsStream {
     type Item = Result<BlockResponse, Error>;
 
@@ -0,0 +1,164 @@
+//! Adapts to rate limiting of the API key. Every stream of the process holds
+//! a permit while it is connected. When the endpoint answers
+//! `RESOURCE_EXHAUSTED`, the number of permits is halved and no stream
+//! connects again until a cool-down has passed, doubling with every rate
+//! limit in a row, instead of each worker reconnecting on its own schedule
+//! until the key is blocked. Once the endpoint has been quiet for a while,
+//! permits come back one at a time.
+
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::time::sleep;
+
+/// Cool-down after the first rate limit in a row.
+const FIRST_COOL_DOWN: Duration = Duration::from_secs(30);
+const MAX_COOL_DOWN: Duration = Duration::from_secs(15 * 60);
+
+/// Time without rate limits after which a permit is given back.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+pub struct Throttle {
+    state: Mutex<State>,
+    released: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    /// Connected streams.
+    active: usize,
+    /// Most streams connected at once, which recovery goes back up to.
+    peak: usize,
+    /// Streams allowed at once, unlimited until the first rate limit.
+    limit: Option<usize>,
+    /// Rate limits since the endpoint was last quiet for a recovery interval.
+    strikes: u32,
+    cool_down_until: Option<Instant>,
+    last_limited: Option<Instant>,
+}
+
+/// Held by a connected stream, giving its place back when dropped.
+pub struct Permit {
+    throttle: &'static Throttle,
+}
+
+impl Throttle {
+    /// The throttle shared by every stream of the process, as they share the
+    /// API key.
+    pub fn global() -> &'static Throttle {
+        static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+        THROTTLE.get_or_init(|| Throttle {
+            state: Mutex::new(State::default()),
+            released: Notify::new(),
+        })
+    }
+
+    /// Waits for the cool-down to pass and for a free place, then takes it.
+    pub async fn acquire(&'static self) -> Permit {
+        loop {
+            let released = self.released.notified();
+            let cool_down = {
+                let mut state = self.state();
+                let has_room = match state.limit {
+                    Some(limit) => state.active < limit,
+                    None => true,
+                };
+                match state.cool_down_remaining() {
+                    Some(remaining) => Some(remaining),
+                    None if has_room => {
+                        state.active += 1;
+                        state.peak = state.peak.max(state.active);
+                        return Permit { throttle: self };
+                    }
+                    None => None,
+                }
+            };
+            match cool_down {
+                Some(remaining) => sleep(remaining).await,
+                None => released.await,
+            }
+        }
+    }
+
+    /// Records that the endpoint rate limited a stream.
+    pub fn rate_limited(&self) {
+        let mut state = self.state();
+        let now = Instant::now();
+        // Streams connected before the cool-down started may report the same
+        // rate limit, which only counts once.
+        if state.cool_down_remaining().is_some() {
+            return;
+        }
+        let limit = (state.limit.unwrap_or(state.peak) / 2).max(1);
+        state.limit = Some(limit);
+        state.strikes += 1;
+        let cool_down = FIRST_COOL_DOWN
+            .saturating_mul(2u32.saturating_pow(state.strikes - 1))
+            .min(MAX_COOL_DOWN);
+        state.cool_down_until = Some(now + cool_down);
+        state.last_limited = Some(now);
+        println!(
+            "Throttle: rate limited, allowing {} concurrent streams and cooling down for {:?}",
+            limit, cool_down
+        );
+    }
+
+    /// Records that a stream received data, giving a place back if the
+    /// endpoint has been quiet long enough.
+    pub fn received(&self) {
+        let mut state = self.state();
+        let (Some(limit), Some(last_limited)) = (state.limit, state.last_limited) else {
+            return;
+        };
+        if last_limited.elapsed() < RECOVERY_INTERVAL {
+            return;
+        }
+        state.strikes = 0;
+        state.last_limited = Some(Instant::now());
+        if limit + 1 >= state.peak {
+            state.limit = None;
+            state.last_limited = None;
+            println!("Throttle: no rate limits lately, streams are no longer limited");
+        } else {
+            state.limit = Some(limit + 1);
+            println!(
+                "Throttle: no rate limits lately, allowing {} concurrent streams",
+                limit + 1
+            );
+        }
+        drop(state);
+        self.released.notify_waiters();
+    }
+
+    /// The current limits, while there are any.
+    pub fn describe(&self) -> Option<String> {
+        let state = self.state();
+        let limit = state.limit?;
+        let mut description = format!("{} of {} concurrent streams connected", state.active, limit);
+        if let Some(remaining) = state.cool_down_remaining() {
+            description += &format!(", cooling down for {}s more", remaining.as_secs());
+        }
+        Some(description)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl State {
+    fn cool_down_remaining(&self) -> Option<Duration> {
+        self.cool_down_until
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.throttle.state().active -= 1;
+        self.throttle.released.notify_waiters();
+    }
+}
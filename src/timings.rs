@@ -0,0 +1,32 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// Time spent in each pipeline stage while building one era, reported so
+/// operators can tell whether bandwidth, cores or disks limit throughput.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EraTimings {
+    pub receive: Duration,
+    pub decode: Duration,
+    pub mapping: Duration,
+    pub rlp: Duration,
+    pub compression: Duration,
+    pub write: Duration,
+}
+
+impl Display for EraTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "receive {:.2?}, decode {:.2?}, mapping {:.2?}, rlp {:.2?}, compression {:.2?}, write {:.2?}",
+            self.receive, self.decode, self.mapping, self.rlp, self.compression, self.write
+        )
+    }
+}
+
+/// Runs `f` and adds its wall-clock time to `elapsed`.
+pub fn timed<T>(elapsed: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    *elapsed += started.elapsed();
+    result
+}
@@ -0,0 +1,121 @@
+use crate::e2store::utils::bigint_to_u128;
+use crate::model::Block;
+use anyhow::{anyhow, bail, Context};
+use std::collections::HashMap;
+
+/// Where the total difficulty stored after each block comes from.
+///
+/// Some sources don't fill in the header's total difficulty, so it can also be
+/// summed up from the block difficulties or looked up in a table.
+pub trait TotalDifficultyProvider {
+    /// Total difficulty including `block`, big endian. Blocks are passed in
+    /// order.
+    fn total_difficulty(&mut self, block: &Block) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+/// Takes the total difficulty from the block header.
+pub struct FromHeader;
+
+impl TotalDifficultyProvider for FromHeader {
+    fn total_difficulty(&mut self, block: &Block) -> Result<Vec<u8>, anyhow::Error> {
+        block
+            .header
+            .total_difficulty
+            .clone()
+            .ok_or(anyhow!("No total difficulty"))
+    }
+}
+
+/// Adds up header difficulties. Needs every block from genesis on, as there
+/// is nothing to start the sum from otherwise.
+#[derive(Default)]
+pub struct RunningSum {
+    last: Option<(u64, u128)>,
+}
+
+impl TotalDifficultyProvider for RunningSum {
+    fn total_difficulty(&mut self, block: &Block) -> Result<Vec<u8>, anyhow::Error> {
+        let difficulty = bigint_to_u128(
+            block
+                .header
+                .difficulty
+                .as_ref()
+                .ok_or(anyhow!("No difficulty"))?,
+        )?;
+
+        let total_difficulty = match self.last {
+            None if block.number == 0 => difficulty,
+            Some((number, total_difficulty)) if number + 1 == block.number => {
+                total_difficulty + difficulty
+            }
+            _ => bail!(
+                "running total difficulty needs consecutive blocks from genesis, got block {}",
+                block.number
+            ),
+        };
+        self.last = Some((block.number, total_difficulty));
+
+        Ok(to_bigint(total_difficulty))
+    }
+}
+
+/// Looks total difficulties up in a table with one `<block number> <total
+/// difficulty>` pair per line, both in decimal.
+pub struct Table {
+    values: HashMap<u64, u128>,
+}
+
+impl Table {
+    pub fn from_file(path: &str) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .context(format!("read total difficulty table '{}'", path))?;
+        let values = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (number, total_difficulty) = line
+                    .split_once(char::is_whitespace)
+                    .ok_or(anyhow!("malformed total difficulty line '{}'", line))?;
+                Ok((
+                    number.trim().parse::<u64>()?,
+                    total_difficulty.trim().parse::<u128>()?,
+                ))
+            })
+            .collect::<Result<HashMap<u64, u128>, anyhow::Error>>()
+            .context(format!("parse total difficulty table '{}'", path))?;
+
+        Ok(Self { values })
+    }
+}
+
+impl TotalDifficultyProvider for Table {
+    fn total_difficulty(&mut self, block: &Block) -> Result<Vec<u8>, anyhow::Error> {
+        self.values
+            .get(&block.number)
+            .map(|total_difficulty| to_bigint(*total_difficulty))
+            .ok_or(anyhow!(
+                "no total difficulty for block {} in the table",
+                block.number
+            ))
+    }
+}
+
+/// Picks a provider from `source`: `header`, `running-sum` or the path of a
+/// table file.
+pub fn load(source: &str) -> Result<Box<dyn TotalDifficultyProvider>, anyhow::Error> {
+    match source {
+        "header" => Ok(Box::new(FromHeader)),
+        "running-sum" => Ok(Box::<RunningSum>::default()),
+        path => Ok(Box::new(Table::from_file(path)?)),
+    }
+}
+
+fn to_bigint(value: u128) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let first = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len());
+
+    bytes[first..].to_vec()
+}
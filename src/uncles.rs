@@ -0,0 +1,162 @@
+use crate::http::HttpClient;
+use anyhow::{anyhow, bail, Context};
+use era_file_sink::model::{Block, Header};
+use era_file_sink::reth_mappings::validate_header;
+use serde::Deserialize;
+
+/// Fetches complete uncle headers from an Ethereum JSON-RPC endpoint, for
+/// streams whose uncle headers lack fields the body encoding needs.
+pub struct UncleSource {
+    url: String,
+    http: HttpClient,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<RpcHeader>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcHeader {
+    parent_hash: String,
+    sha3_uncles: String,
+    miner: String,
+    state_root: String,
+    transactions_root: String,
+    receipts_root: String,
+    logs_bloom: String,
+    difficulty: String,
+    number: String,
+    gas_limit: String,
+    gas_used: String,
+    timestamp: String,
+    extra_data: String,
+    mix_hash: String,
+    nonce: String,
+    hash: String,
+    base_fee_per_gas: Option<String>,
+}
+
+impl UncleSource {
+    pub fn new(url: String, http: HttpClient) -> Self {
+        Self { url, http }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Uncle `index` of the block with hash `block_hash`.
+    async fn fetch(&self, block_hash: &[u8], index: usize) -> Result<Header, anyhow::Error> {
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_getUncleByBlockHashAndIndex",
+            "params": [format!("0x{}", hex::encode(block_hash)), format!("{:#x}", index)],
+        });
+        let response: RpcResponse = self
+            .http
+            .send(|client| client.post(&self.url).json(&request))
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.error {
+            bail!("uncle header source returned an error: {}", error);
+        }
+        let header = response
+            .result
+            .ok_or(anyhow!("uncle header source doesn't know this uncle"))?;
+
+        header.try_into()
+    }
+}
+
+impl TryFrom<RpcHeader> for Header {
+    type Error = anyhow::Error;
+
+    fn try_from(header: RpcHeader) -> Result<Self, Self::Error> {
+        Ok(Header {
+            parent_hash: data(&header.parent_hash)?,
+            uncle_hash: data(&header.sha3_uncles)?,
+            coinbase: data(&header.miner)?,
+            state_root: data(&header.state_root)?,
+            transactions_root: data(&header.transactions_root)?,
+            receipt_root: data(&header.receipts_root)?,
+            logs_bloom: data(&header.logs_bloom)?,
+            difficulty: Some(data(&header.difficulty)?),
+            number: quantity(&header.number)?,
+            gas_limit: quantity(&header.gas_limit)?,
+            gas_used: quantity(&header.gas_used)?,
+            timestamp: Some(quantity(&header.timestamp)?),
+            extra_data: data(&header.extra_data)?,
+            mix_hash: data(&header.mix_hash)?,
+            nonce: quantity(&header.nonce)?,
+            hash: data(&header.hash)?,
+            base_fee_per_gas: header
+                .base_fee_per_gas
+                .map(|base_fee| data(&base_fee))
+                .transpose()?,
+            ..Default::default()
+        })
+    }
+}
+
+/// Decodes a `0x` prefixed hex value, padding quantities of odd length.
+pub fn data(value: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let value = value.strip_prefix("0x").unwrap_or(value);
+    let value = if value.len() % 2 == 1 {
+        format!("0{}", value)
+    } else {
+        value.to_string()
+    };
+
+    hex::decode(&value).context(format!("'{}' is not hex", value))
+}
+
+pub fn quantity(value: &str) -> Result<u64, anyhow::Error> {
+    u64::from_str_radix(value.strip_prefix("0x").unwrap_or(value), 16)
+        .context(format!("'{}' is not a hex quantity", value))
+}
+
+/// Checks every uncle of `block` and replaces the incomplete ones with the
+/// headers from `source`, returning how many were fetched. Without a source,
+/// the first incomplete uncle is an error.
+pub async fn complete_uncles(
+    block: &mut Block,
+    source: Option<&UncleSource>,
+) -> Result<usize, anyhow::Error> {
+    let mut fetched = 0;
+    for index in 0..block.uncles.len() {
+        let Err(err) = validate_header(&block.uncles[index]) else {
+            continue;
+        };
+        let Some(source) = source else {
+            return Err(err.context(format!(
+                "uncle {} is incomplete, set UNCLE_HEADER_SOURCE to fetch it from a JSON-RPC node",
+                index
+            )));
+        };
+
+        let uncle = source
+            .fetch(&block.hash, index)
+            .await
+            .context(format!("fetch uncle {}", index))?;
+        validate_header(&uncle).context(format!("fetched uncle {}", index))?;
+        let partial = &block.uncles[index];
+        if !partial.hash.is_empty() && uncle.hash != partial.hash {
+            bail!(
+                "fetched uncle {} has hash 0x{}, expected 0x{}",
+                index,
+                hex::encode(&uncle.hash),
+                hex::encode(&partial.hash)
+            );
+        }
+        block.uncles[index] = uncle;
+        fetched += 1;
+    }
+
+    Ok(fetched)
+}
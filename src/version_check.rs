@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/semiotic-ai/era-file-sink/releases/latest";
+
+/// How long the release lookup may take before it is abandoned.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Looks up the latest release in the background and warns if this build is
+/// older. Encoding fixes ship in releases, and archives written by an
+/// outdated encoder are expensive to find later. Failures are ignored, so
+/// the check never holds up or breaks a run.
+pub fn spawn() {
+    tokio::spawn(async {
+        if let Ok(Some(latest)) = latest_release().await {
+            let current = env!("CARGO_PKG_VERSION");
+            if parse_version(&latest) > parse_version(current) {
+                println!(
+                    "Warning: era-file-sink {} is available, this is {}. Newer releases may fix encoding bugs",
+                    latest, current
+                );
+            }
+        }
+    });
+}
+
+async fn latest_release() -> Result<Option<String>, reqwest::Error> {
+    let release: serde_json::Value = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .user_agent(concat!("era-file-sink/", env!("CARGO_PKG_VERSION")))
+        .build()?
+        .get(LATEST_RELEASE_URL)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(release["tag_name"]
+        .as_str()
+        .map(|tag| tag.trim_start_matches('v').to_string()))
+}
+
+/// Numeric components of a `major.minor.patch` version, ignoring any
+/// pre-release suffix.
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version
+        .split(['-', '+'])
+        .next()?
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
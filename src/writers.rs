@@ -0,0 +1,84 @@
+//! Writer adapters that observe the bytes passing through them.
+
+use sha2::digest::{FixedOutputReset, Output};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+
+/// Counts the bytes written to the inner writer.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::starting_at(inner, 0)
+    }
+
+    /// Continues counting from `count`, for writers positioned after data
+    /// that was written earlier.
+    pub fn starting_at(inner: W, count: u64) -> Self {
+        Self { inner, count }
+    }
+
+    /// Bytes written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hashes the bytes written to the inner writer with `D`, SHA-256 unless
+/// another [`Digest`] such as `sha3::Keccak256` is picked.
+pub struct HashingWriter<W, D = Sha256> {
+    inner: W,
+    hasher: D,
+}
+
+impl<W: Write, D: Digest> HashingWriter<W, D> {
+    pub fn new(inner: W) -> Self {
+        Self::from_parts(inner, D::new())
+    }
+
+    /// Continues hashing with `hasher`, for writers positioned after data
+    /// that was hashed earlier.
+    pub fn from_parts(inner: W, hasher: D) -> Self {
+        Self { inner, hasher }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Digest of everything written since the last reset, starting over for
+    /// what comes next.
+    pub fn finalize_reset(&mut self) -> Output<D>
+    where
+        D: FixedOutputReset,
+    {
+        Digest::finalize_reset(&mut self.hasher)
+    }
+}
+
+impl<W: Write, D: Digest> Write for HashingWriter<W, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}